@@ -1,19 +1,33 @@
 use color_eyre::eyre;
-use indexmap::IndexMap;
+use indexmap::{IndexMap, IndexSet};
 use ratatui::widgets::ListState;
+use std::mem;
 use std::sync::Arc;
-use std::time::SystemTime;
+use std::time::{Duration, Instant};
 use tcp_chat_server::entities::{Message, Room, User};
 use tcp_chat_server::proto::chat_client::ChatClient;
-use tcp_chat_server::proto::serverside_room_event::Event::NewMessage;
-use tcp_chat_server::proto::serverside_user_event::Event::AddedToRoom;
+use tcp_chat_server::proto::client_frame::Frame as ClientFrameKind;
+use tcp_chat_server::proto::server_frame::Frame as ServerFrameKind;
+use tcp_chat_server::proto::serverside_room_event::Event::{
+    MessageDeleted, MessageEdited, NewMessage, ReactionAdded, ReactionRemoved, RoomUpdated,
+    UserJoined, UserLeft, UserTyping,
+};
+use tcp_chat_server::proto::serverside_user_event::Event::{
+    AddedToRoom, KickedFromRoom, Mentioned, NotificationsChanged, PresenceChanged, ProfileUpdated,
+};
 use tcp_chat_server::proto::user_lookup_request::Identifier;
-use tcp_chat_server::proto::{self, UserLookupRequest};
-use tokio::sync::Mutex;
+use tcp_chat_server::proto::UserLookupRequest;
+use tcp_chat_server::proto::{
+    self, MessagePageRequest, PageDirection, ReactionCount, ReadMarkerRequest, TypingRequest,
+};
+use tcp_chat_server::proto::{ClientFrame, ClientsideMessage, ServersideRoomEvent};
+use tokio::sync::{mpsc, Mutex};
+use tokio_stream::wrappers::ReceiverStream;
 use tokio_stream::StreamExt;
 use tonic::service::interceptor::InterceptedService;
 use tonic::service::Interceptor;
 use tonic::transport::{Certificate, Channel, ClientTlsConfig};
+use tonic::{Code, Status};
 use uuid::Uuid;
 
 // Some named UUID types for readability.
@@ -41,6 +55,12 @@ where
     /// An intermediate buffer to hold the message being written.
     pub(crate) message_draft: String,
 
+    /// Why the last message draft couldn't be sent, shown until a message goes through.
+    pub(crate) notice: Option<String>,
+
+    /// The room we've told the server we're typing in, and when we last did so.
+    pub(crate) typing_sent: Option<(RoomUUID, Instant)>,
+
     /// The UUID of the room the user currently has opened (focused).
     pub(crate) room_list_state: ListState,
 
@@ -55,10 +75,31 @@ where
     /// A list of all messages in each room.
     /// Acts as a cache to avoid unnecessary lookup requests to the server.
     pub(crate) messages: Cache<MessageUUID, Message>,
+
+    /// How many users have reacted with each emoji, for every message with reactions.
+    pub(crate) reactions: Cache<MessageUUID, IndexMap<String, u32>>,
+
+    /// How many messages from other users are unread in each room.
+    pub(crate) unread: Cache<RoomUUID, u64>,
+
+    /// How many unread messages mention the current user in each room.
+    pub(crate) mentions: Cache<RoomUUID, u64>,
+
+    /// What the current user wants to be notified of in each room.
+    pub(crate) notifications: Cache<RoomUUID, proto::RoomNotificationSettings>,
+
+    /// Which users are currently typing a message in each room.
+    pub(crate) typing: Cache<RoomUUID, IndexSet<UserUUID>>,
+
+    /// The last known presence of users that share a room with the current user.
+    pub(crate) presence: Cache<UserUUID, proto::Presence>,
+
+    /// The profiles of users that have changed them since we've logged in.
+    pub(crate) profiles: Cache<UserUUID, proto::Profile>,
 }
 
 impl Chat<crate::app::Interceptor> {
-    pub async fn new(user: User) -> Self {
+    pub async fn new(user: User, auth_pair: proto::AuthPair) -> Self {
         let tls_config = ClientTlsConfig::new()
             .ca_certificate(Certificate::from_pem(crate::TLS_CERT))
             .domain_name("example.com");
@@ -68,16 +109,25 @@ impl Chat<crate::app::Interceptor> {
             .connect()
             .await
             .expect("Could not connect to the Chat service!");
-        let interceptor = crate::app::Interceptor::new(user.auth_pair());
+        let interceptor = crate::app::Interceptor::new(auth_pair);
 
         Self {
             user: user.clone(),
             refreshed: false,
             message_draft: String::default(),
+            notice: None,
+            typing_sent: None,
             room_list_state: ListState::default(),
             users: Arc::new(Mutex::new(IndexMap::new())),
             rooms: Arc::new(Mutex::new(IndexMap::new())),
             messages: Arc::new(Mutex::new(IndexMap::new())),
+            reactions: Arc::new(Mutex::new(IndexMap::new())),
+            unread: Arc::new(Mutex::new(IndexMap::new())),
+            mentions: Arc::new(Mutex::new(IndexMap::new())),
+            notifications: Arc::new(Mutex::new(IndexMap::new())),
+            typing: Arc::new(Mutex::new(IndexMap::new())),
+            presence: Arc::new(Mutex::new(IndexMap::new())),
+            profiles: Arc::new(Mutex::new(IndexMap::new())),
             client: {
                 Arc::new(Mutex::new(ChatClient::with_interceptor(
                     channel,
@@ -92,6 +142,21 @@ impl<I> Chat<I>
 where
     I: Interceptor + Send + 'static,
 {
+    /// How many of the latest messages to load for each room on startup.
+    const HISTORY_PAGE_SIZE: u32 = 100;
+
+    /// How often to remind the server that the user is still typing.
+    /// Must be well below the server's typing timeout.
+    const TYPING_REFRESH_INTERVAL: Duration = Duration::from_secs(2);
+
+    /// How many frames may wait to be sent to the server at once.
+    const OUTGOING_FRAME_BUFFER: usize = 16;
+
+    /// How long to wait before reconnecting once the connection to the server is lost.
+    /// The delay doubles with every attempt that fails, up to [`Self::MAX_RECONNECT_DELAY`].
+    const MIN_RECONNECT_DELAY: Duration = Duration::from_secs(1);
+    const MAX_RECONNECT_DELAY: Duration = Duration::from_secs(30);
+
     /// Fetches all the necessary data from the server and fires up event threads.
     ///
     /// # Errors
@@ -104,7 +169,7 @@ where
     /// This function will panic if a panic occurs in any of the event threads.
     pub(super) async fn load_data(&mut self) -> eyre::Result<()> {
         self.load_static_rooms().await?;
-        self.user_event_thread();
+        self.event_thread();
         self.refreshed = true;
 
         Ok(())
@@ -116,45 +181,84 @@ where
     ///
     /// This function will return an error if the gRPC call fails or the server sends
     /// malformed room metadata.
-    async fn load_static_rooms(&mut self) -> eyre::Result<()> {
-        let rooms = self
-            .client
-            .lock()
-            .await
-            .list_rooms(())
-            .await?
-            .into_inner()
-            .rooms;
+    async fn load_static_rooms(&self) -> eyre::Result<()> {
+        Self::reload_rooms(
+            &self.client,
+            &self.rooms,
+            &self.unread,
+            &self.notifications,
+            &self.messages,
+            &self.users,
+            &self.reactions,
+        )
+        .await
+    }
 
-        let mut room_cache = self.rooms.lock().await;
+    /// Replaces the cached rooms, along with their unread counts, notification settings and
+    /// latest messages, with whatever the server has now.
+    ///
+    /// This method is associated for the same reason as [`Self::load_static_messages`].
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the gRPC call fails or the server sends
+    /// malformed room metadata.
+    async fn reload_rooms(
+        client: &Arc<Mutex<ChatClient<InterceptedService<Channel, I>>>>,
+        rooms: &Cache<RoomUUID, Room>,
+        unread: &Cache<RoomUUID, u64>,
+        notifications: &Cache<RoomUUID, proto::RoomNotificationSettings>,
+        messages: &Cache<MessageUUID, Message>,
+        users: &Cache<UserUUID, proto::User>,
+        reactions: &Cache<MessageUUID, IndexMap<String, u32>>,
+    ) -> eyre::Result<()> {
+        let server_rooms = client.lock().await.list_rooms(()).await?.into_inner().rooms;
+
+        messages.lock().await.clear();
+        reactions.lock().await.clear();
+        let mut room_cache = rooms.lock().await;
         room_cache.clear();
-        for r in rooms {
+        let mut unread_cache = unread.lock().await;
+        unread_cache.clear();
+        let mut notification_cache = notifications.lock().await;
+        notification_cache.clear();
+        for r in server_rooms {
             let untrusted_uuid = r
                 .uuid
                 .ok_or_else(|| eyre::eyre!("The server did not provide the room's UUID"))?;
             let uuid = Uuid::try_from(untrusted_uuid)?;
 
-            let _ = room_cache.insert(uuid, Room { uuid, name: r.name });
+            let _ = room_cache.insert(
+                uuid,
+                Room {
+                    uuid,
+                    kind: proto::RoomKind::try_from(r.kind).unwrap_or_default().into(),
+                    name: r.name,
+                    topic: r.topic,
+                    description: r.description,
+                },
+            );
+            let _ = unread_cache.insert(uuid, r.unread_count);
+            if let Some(settings) = r.notifications {
+                let _ = notification_cache.insert(uuid, settings);
+            }
             Self::load_static_messages(
                 uuid,
-                Arc::clone(&self.client),
-                Arc::clone(&self.messages),
-                Arc::clone(&self.users),
+                Arc::clone(client),
+                Arc::clone(messages),
+                Arc::clone(users),
+                Arc::clone(reactions),
             )
             .await?;
-            Self::room_event_thread(
-                uuid,
-                Arc::clone(&self.client),
-                Arc::clone(&self.messages),
-                Arc::clone(&self.users),
-            );
         }
+        drop(notification_cache);
+        drop(unread_cache);
         drop(room_cache);
 
         Ok(())
     }
 
-    /// Loads all static* messages for a specified room.
+    /// Loads the latest static* messages for a specified room, up to [`Self::HISTORY_PAGE_SIZE`].
     ///
     /// This method is associated and requires a `client_arc` and `messages_arc` instead of
     /// acquiring these `Arc` from `self`, because doing so would render it impossible to use
@@ -172,42 +276,39 @@ where
         client_arc: Arc<Mutex<ChatClient<InterceptedService<Channel, I>>>>,
         messages_arc: Cache<MessageUUID, Message>,
         users_arc: Cache<UserUUID, proto::User>,
+        reactions_arc: Cache<MessageUUID, IndexMap<String, u32>>,
     ) -> eyre::Result<()> {
         let messages = client_arc
             .lock()
             .await
-            .list_messages(proto::Uuid::from(room_uuid))
+            .list_messages_page(MessagePageRequest {
+                room_uuid: Some(proto::Uuid::from(room_uuid)),
+                cursor: None,
+                direction: PageDirection::Backward.into(),
+                page_size: Self::HISTORY_PAGE_SIZE,
+            })
             .await?
             .into_inner()
             .messages;
 
-        for m in messages {
+        for mut m in messages {
             assert_eq!(Some(proto::Uuid::from(room_uuid)), m.room_uuid);
 
             // Parse the message's untrused fields.
-            let untrusted_message_uuid = m
-                .uuid
-                .ok_or_else(|| eyre::eyre!("The serverside message did not specify its `uuid`"))?;
-            let message_uuid = Uuid::try_from(untrusted_message_uuid)?;
-            let untrusted_sender_uuid = m.sender_uuid.ok_or_else(|| {
-                eyre::eyre!("The serverside message did not specify `sender_uuid`")
-            })?;
-            let sender_uuid = Uuid::try_from(untrusted_sender_uuid)?;
-            let unstrusted_timestamp = m.timestamp.ok_or_else(|| {
-                eyre::eyre!("The serverside message's `timestamp` was not specified")
-            })?;
-            let timestamp = SystemTime::try_from(unstrusted_timestamp)?;
-
-            messages_arc.lock().await.insert(
-                message_uuid,
-                Message {
-                    uuid: message_uuid,
-                    sender_uuid,
-                    room_uuid,
-                    text: m.text,
-                    timestamp,
-                },
-            );
+            let reaction_counts = mem::take(&mut m.reactions);
+            let message = Message::try_from(m)?;
+            let sender_uuid = message.sender_uuid;
+            if !reaction_counts.is_empty() {
+                let reaction_counts = reaction_counts
+                    .into_iter()
+                    .map(|ReactionCount { emoji, count }| (emoji, count))
+                    .collect();
+                reactions_arc
+                    .lock()
+                    .await
+                    .insert(message.uuid, reaction_counts);
+            }
+            messages_arc.lock().await.insert(message.uuid, message);
 
             let mut users = users_arc.lock().await;
             if users.get(&sender_uuid).is_none() {
@@ -230,148 +331,545 @@ where
         Ok(())
     }
 
-    /// Spawns an `async` task that listens for any [`ServersideUserEvent`]s and handles them accordingly.
+    /// Sends the message draft to the focused room.
+    ///
+    /// If the server refuses the message (say, because the user is sending too fast),
+    /// the draft is kept so that it can be sent again, and a notice tells the user why.
+    pub(super) async fn send_draft(&mut self) {
+        let Some(room_uuid) = self.focused_room_uuid().await else {
+            return;
+        };
+
+        let sent = self
+            .client
+            .lock()
+            .await
+            .send_message(ClientsideMessage {
+                room_uuid: Some(room_uuid.into()),
+                text: self.message_draft.clone(),
+                reply_to: None,
+                attachments: vec![],
+            })
+            .await;
+        match sent {
+            Ok(_) => {
+                self.message_draft.clear();
+                self.notice = None;
+            }
+            Err(status) => self.notice = Some(send_failure_notice(&status)),
+        }
+    }
+
+    /// Tells the server that the user has read everything in the focused room.
+    ///
+    /// Does nothing if the focused room has no unread messages.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the gRPC call fails.
+    pub(super) async fn mark_focused_room_read(&self) -> eyre::Result<()> {
+        let Some(room_uuid) = self.focused_room_uuid().await else {
+            return Ok(());
+        };
+
+        if self
+            .unread
+            .lock()
+            .await
+            .get(&room_uuid)
+            .copied()
+            .unwrap_or_default()
+            == 0
+        {
+            return Ok(());
+        }
+
+        let last_message_uuid = self
+            .messages
+            .lock()
+            .await
+            .values()
+            .rev()
+            .find(|m| m.room_uuid == room_uuid)
+            .map(|m| m.uuid);
+        if let Some(message_uuid) = last_message_uuid {
+            self.client
+                .lock()
+                .await
+                .mark_read(ReadMarkerRequest {
+                    room_uuid: Some(room_uuid.into()),
+                    message_uuid: Some(message_uuid.into()),
+                })
+                .await?;
+        }
+        self.unread.lock().await.insert(room_uuid, 0);
+        self.mentions.lock().await.shift_remove(&room_uuid);
+
+        Ok(())
+    }
+
+    /// Tells the server whether the user is typing in the focused room.
+    ///
+    /// The user is considered to be typing while the message draft is not empty.
+    /// The typing state is refreshed every [`Self::TYPING_REFRESH_INTERVAL`] so that
+    /// it doesn't expire, and the server is told right away once the draft is cleared
+    /// or the user switches to another room.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the gRPC call fails.
+    pub(super) async fn update_typing(&mut self) -> eyre::Result<()> {
+        let focused_room_uuid = self.focused_room_uuid().await;
+        let typing_room_uuid = focused_room_uuid.filter(|_| !self.message_draft.is_empty());
+
+        // Stop typing in the room we've been typing in before, if that's no longer the case.
+        if let Some((room_uuid, _)) = self.typing_sent {
+            if typing_room_uuid != Some(room_uuid) {
+                self.typing_sent = None;
+                self.client
+                    .lock()
+                    .await
+                    .set_typing(TypingRequest {
+                        room_uuid: Some(room_uuid.into()),
+                        typing: false,
+                    })
+                    .await?;
+            }
+        }
+
+        let Some(room_uuid) = typing_room_uuid else {
+            return Ok(());
+        };
+        if self
+            .typing_sent
+            .is_some_and(|(_, sent_at)| sent_at.elapsed() < Self::TYPING_REFRESH_INTERVAL)
+        {
+            return Ok(());
+        }
+
+        self.client
+            .lock()
+            .await
+            .set_typing(TypingRequest {
+                room_uuid: Some(room_uuid.into()),
+                typing: true,
+            })
+            .await?;
+        self.typing_sent = Some((room_uuid, Instant::now()));
+
+        Ok(())
+    }
+
+    /// Returns the UUID of the room the user currently has opened, if any.
+    pub(super) async fn focused_room_uuid(&self) -> Option<RoomUUID> {
+        let i = self.room_list_state.selected()?;
+        self.rooms.lock().await.keys().nth(i).copied()
+    }
+
+    /// Spawns an `async` task that opens a single connection to the server, subscribes it
+    /// to every room, and handles all the room and user events it carries accordingly.
+    ///
+    /// If the connection is lost, including when the server ends it after we've fallen too
+    /// far behind, the task reloads the rooms and their messages and connects again,
+    /// backing off between attempts.
     ///
     /// # Panics
     ///
-    /// Panics if there are any errors while the subscription is active or being initiated.
-    /// "errors while the subscription is active" means missing or invalid room metadata.
-    fn user_event_thread(&mut self) {
+    /// Panics if there are any errors while the connection is active.
+    /// "errors while the connection is active" means missing or invalid room metadata.
+    #[allow(clippy::too_many_lines)]
+    fn event_thread(&self) {
         let client = Arc::clone(&self.client);
         let rooms = Arc::clone(&self.rooms);
         let messages_arc = Arc::clone(&self.messages);
         let users = Arc::clone(&self.users);
+        let reactions = Arc::clone(&self.reactions);
+        let unread = Arc::clone(&self.unread);
+        let mentions = Arc::clone(&self.mentions);
+        let notifications = Arc::clone(&self.notifications);
+        let typing = Arc::clone(&self.typing);
+        let presence = Arc::clone(&self.presence);
+        let profiles = Arc::clone(&self.profiles);
+        let user_uuid = self.user.uuid;
 
         tokio::spawn(async move {
-            let mut stream = client
-                .lock()
-                .await
-                .subscribe_to_user(())
-                .await
-                .expect("Could not subscribe to user events")
-                .into_inner();
-            while let Some(Ok(event)) = stream.next().await {
-                match event
-                    .event
-                    .expect("The server sent an event message with no actual event inside")
-                {
-                    AddedToRoom(untrusted_room_uuid) => {
-                        let room = client
-                            .lock()
-                            .await
-                            .lookup_room(untrusted_room_uuid.clone())
-                            .await
-                            .unwrap_or_else(|_| panic!("Could not query the server about room with UUID {untrusted_room_uuid:#?}"))
-                            .into_inner();
-                        let uuid = room
-                            .uuid
-                            .expect("The server did not provide the room's UUID")
-                            .try_into()
-                            .expect("The server-provided room UUID is invalid");
-                        rooms.lock().await.insert(
-                            uuid,
-                            Room {
-                                uuid,
-                                name: room.name,
-                            },
-                        );
-
-                        Self::load_static_messages(
-                            uuid,
-                            Arc::clone(&client),
-                            messages_arc.clone(),
-                            Arc::clone(&users),
+            let mut reconnect_delay = Self::MIN_RECONNECT_DELAY;
+            let mut reconnecting = false;
+            loop {
+                if reconnecting {
+                    tokio::time::sleep(reconnect_delay).await;
+                    reconnect_delay = (reconnect_delay * 2).min(Self::MAX_RECONNECT_DELAY);
+                }
+
+                // Frames go to the server through a channel, so that rooms can be subscribed to later on.
+                let (frame_tx, frame_rx) = mpsc::channel(Self::OUTGOING_FRAME_BUFFER);
+                let connection = client
+                    .lock()
+                    .await
+                    .connect(ReceiverStream::new(frame_rx))
+                    .await;
+
+                // Whatever happened while we weren't connected is lost, so start over
+                // once the new connection is there to catch anything that happens next.
+                let reloaded = match &connection {
+                    Ok(_) if reconnecting => {
+                        typing.lock().await.clear();
+                        Self::reload_rooms(
+                            &client,
+                            &rooms,
+                            &unread,
+                            &notifications,
+                            &messages_arc,
+                            &users,
+                            &reactions,
                         )
                         .await
-                        .unwrap_or_else(|_| panic!("Couldn't load messages for room {uuid:?}"));
-                        Self::room_event_thread(
-                            uuid,
-                            Arc::clone(&client),
-                            Arc::clone(&messages_arc),
-                            Arc::clone(&users),
-                        );
+                        .is_ok()
+                    }
+                    Ok(_) => true,
+                    Err(_) => false,
+                };
+                reconnecting = true;
+
+                if let (Ok(connection), true) = (connection, reloaded) {
+                    let mut stream = connection.into_inner();
+                    let room_uuids: Vec<RoomUUID> = rooms.lock().await.keys().copied().collect();
+                    for room_uuid in room_uuids {
+                        let _ = frame_tx.send(subscribe_frame(room_uuid)).await;
+                    }
+
+                    // The stream ends with an error on DATA_LOSS or if the transport fails.
+                    while let Some(Ok(server_frame)) = stream.next().await {
+                        reconnect_delay = Self::MIN_RECONNECT_DELAY;
+                        let sequence = server_frame.sequence;
+
+                        match server_frame
+                            .frame
+                            .expect("The server sent a frame with nothing inside")
+                        {
+                            ServerFrameKind::RoomEvent(event) => {
+                                Self::handle_room_event(
+                                    event,
+                                    user_uuid,
+                                    &client,
+                                    &rooms,
+                                    &messages_arc,
+                                    &users,
+                                    &reactions,
+                                    &unread,
+                                    &typing,
+                                )
+                                .await;
+                            }
+                            ServerFrameKind::UserEvent(event) => {
+                                match event.event.expect(
+                                    "The server sent an event message with no actual event inside",
+                                ) {
+                                    AddedToRoom(untrusted_room_uuid) => {
+                                        let room = client
+                                    .lock()
+                                    .await
+                                    .lookup_room(untrusted_room_uuid.clone())
+                                    .await
+                                    .unwrap_or_else(|_| panic!("Could not query the server about room with UUID {untrusted_room_uuid:#?}"))
+                                    .into_inner();
+                                        let uuid = room
+                                            .uuid
+                                            .expect("The server did not provide the room's UUID")
+                                            .try_into()
+                                            .expect("The server-provided room UUID is invalid");
+                                        rooms.lock().await.insert(
+                                            uuid,
+                                            Room {
+                                                uuid,
+                                                kind: proto::RoomKind::try_from(room.kind)
+                                                    .unwrap_or_default()
+                                                    .into(),
+                                                name: room.name,
+                                                topic: room.topic,
+                                                description: room.description,
+                                            },
+                                        );
+                                        unread.lock().await.insert(uuid, room.unread_count);
+                                        if let Some(settings) = room.notifications {
+                                            notifications.lock().await.insert(uuid, settings);
+                                        }
+
+                                        Self::load_static_messages(
+                                            uuid,
+                                            Arc::clone(&client),
+                                            messages_arc.clone(),
+                                            Arc::clone(&users),
+                                            Arc::clone(&reactions),
+                                        )
+                                        .await
+                                        .unwrap_or_else(
+                                            |_| panic!("Couldn't load messages for room {uuid:?}"),
+                                        );
+                                        let _ = frame_tx.send(subscribe_frame(uuid)).await;
+                                    }
+
+                                    KickedFromRoom(untrusted_room_uuid) => {
+                                        let uuid = Uuid::try_from(untrusted_room_uuid)
+                                            .expect("The server-provided room UUID is invalid");
+
+                                        // Forget everything about the room, and stop receiving its events.
+                                        let _ = frame_tx.send(unsubscribe_frame(uuid)).await;
+                                        rooms.lock().await.shift_remove(&uuid);
+                                        unread.lock().await.shift_remove(&uuid);
+                                        mentions.lock().await.shift_remove(&uuid);
+                                        notifications.lock().await.shift_remove(&uuid);
+                                        typing.lock().await.shift_remove(&uuid);
+                                        let mut messages = messages_arc.lock().await;
+                                        let mut reactions = reactions.lock().await;
+                                        messages.retain(|message_uuid, message| {
+                                            if message.room_uuid == uuid {
+                                                reactions.shift_remove(message_uuid);
+                                            }
+                                            message.room_uuid != uuid
+                                        });
+                                        drop(reactions);
+                                        drop(messages);
+                                    }
+
+                                    PresenceChanged(user_presence) => {
+                                        let peer_uuid = user_presence
+                                            .user_uuid
+                                            .clone()
+                                            .and_then(|u| Uuid::try_from(u).ok())
+                                            .expect("The server-provided user UUID is invalid");
+                                        presence.lock().await.insert(peer_uuid, user_presence);
+                                    }
+
+                                    ProfileUpdated(profile) => {
+                                        let user = profile
+                                            .user
+                                            .clone()
+                                            .expect("The server sent a profile without its user");
+                                        let peer_uuid = user
+                                            .uuid
+                                            .clone()
+                                            .and_then(|u| Uuid::try_from(u).ok())
+                                            .expect("The server-provided user UUID is invalid");
+
+                                        // The username might've changed along with the profile.
+                                        users.lock().await.insert(peer_uuid, user);
+                                        profiles.lock().await.insert(peer_uuid, profile);
+                                    }
+
+                                    Mentioned(message) => {
+                                        let room_uuid = message
+                                            .room_uuid
+                                            .and_then(|u| Uuid::try_from(u).ok())
+                                            .expect("The server-provided room UUID is invalid");
+                                        *mentions.lock().await.entry(room_uuid).or_default() += 1;
+                                    }
+
+                                    NotificationsChanged(settings) => {
+                                        let room_uuid = settings
+                                            .room_uuid
+                                            .clone()
+                                            .and_then(|u| Uuid::try_from(u).ok())
+                                            .expect("The server-provided room UUID is invalid");
+                                        notifications.lock().await.insert(room_uuid, settings);
+                                    }
+                                }
+                            }
+                            ServerFrameKind::Subscribed(_)
+                            | ServerFrameKind::SubscriptionRefused(_) => {}
+                        }
+
+                        // Only acknowledge frames once they're handled, so that the server
+                        // holds off while we're busy.
+                        let _ = frame_tx.send(ack_frame(sequence)).await;
                     }
                 }
             }
         });
     }
 
-    /// Spawns an `async` task that listens for [`ServersideRoomEvent`]s and handles them accordingly.
+    /// Handles a [`ServersideRoomEvent`] that came over the connection to the server.
     ///
     /// # Panics
     ///
-    /// Panics if there are any errors while the subscription is active or being initiated.
-    /// "errors while the subscription is active" means missing or invalid message metadata.
-    fn room_event_thread(
-        room_uuid: Uuid,
-        client: Arc<Mutex<ChatClient<InterceptedService<Channel, I>>>>,
-        messages: Cache<MessageUUID, Message>,
-        users: Cache<UserUUID, proto::User>,
+    /// Panics if the event is malformed, which means missing or invalid message metadata.
+    #[allow(clippy::too_many_arguments, clippy::too_many_lines)]
+    async fn handle_room_event(
+        event: ServersideRoomEvent,
+        user_uuid: Uuid,
+        client: &Arc<Mutex<ChatClient<InterceptedService<Channel, I>>>>,
+        rooms: &Cache<RoomUUID, Room>,
+        messages: &Cache<MessageUUID, Message>,
+        users: &Cache<UserUUID, proto::User>,
+        reactions: &Cache<MessageUUID, IndexMap<String, u32>>,
+        unread: &Cache<RoomUUID, u64>,
+        typing: &Cache<RoomUUID, IndexSet<UserUUID>>,
     ) {
-        tokio::spawn(async move {
-            let mut stream = client
-                .lock()
-                .await
-                .subscribe_to_room(proto::Uuid::from(room_uuid))
-                .await
-                .unwrap_or_else(|stat| panic!("Couldn't subscribe to room {room_uuid:?}: {stat:?}"))
-                .into_inner();
-
-            while let Some(Ok(event)) = stream.next().await {
-                match event
-                    .event
-                    .unwrap_or_else(|| panic!("Caught an error in room event stream"))
-                {
-                    NewMessage(m) => {
-                        assert_eq!(Some(proto::Uuid::from(room_uuid)), m.room_uuid);
-
-                        // Parse the message's untrused fields.
-                        let untrusted_message_uuid = m
-                            .uuid
-                            .expect("The serverside message did not specify its `uuid`");
-                        let message_uuid = Uuid::try_from(untrusted_message_uuid)
-                            .expect("The serverside message's UUID was invalid");
-                        let untrusted_sender_uuid = m
-                            .sender_uuid
-                            .expect("The serverside message did not specify `sender_uuid`");
-                        let sender_uuid = Uuid::try_from(untrusted_sender_uuid)
-                            .expect("The serverside message's `sender_uuid` was invalid");
-                        let unstrusted_timestamp = m
-                            .timestamp
-                            .expect("The serverside message's `timestamp` was not specified");
-                        let timestamp = SystemTime::try_from(unstrusted_timestamp)
-                            .expect("The serverside message's `timestamp` was invalid");
-
-                        messages.lock().await.insert(
-                            message_uuid,
-                            Message {
-                                uuid: message_uuid,
-                                sender_uuid,
-                                room_uuid,
-                                text: m.text,
-                                timestamp,
-                            },
-                        );
-
-                        let mut users = users.lock().await;
-                        if users.get(&sender_uuid).is_none() {
-                            let _ = users.insert(
-                                sender_uuid,
-                                client
-                                    .lock()
-                                    .await
-                                    .lookup_user(UserLookupRequest {
-                                        identifier: Some(Identifier::Uuid(sender_uuid.into())),
-                                    })
-                                    .await
-                                    .unwrap()
-                                    .into_inner(),
-                            );
+        let room_uuid: Uuid = event
+            .room_uuid
+            .and_then(|u| u.try_into().ok())
+            .expect("The server sent a room event without a valid room UUID");
+
+        // Ignore whatever is still in flight from rooms we've been kicked from (or left).
+        if !rooms.lock().await.contains_key(&room_uuid) {
+            return;
+        }
+
+        match event
+            .event
+            .unwrap_or_else(|| panic!("Caught an error in room event stream"))
+        {
+            NewMessage(m) => {
+                assert_eq!(Some(proto::Uuid::from(room_uuid)), m.room_uuid);
+
+                // Parse the message's untrused fields.
+                let message = Message::try_from(m)
+                    .unwrap_or_else(|err| panic!("The serverside message was malformed: {err}"));
+                let sender_uuid = message.sender_uuid;
+                messages.lock().await.insert(message.uuid, message);
+                if sender_uuid != user_uuid {
+                    *unread.lock().await.entry(room_uuid).or_default() += 1;
+                }
+
+                let mut users = users.lock().await;
+                if users.get(&sender_uuid).is_none() {
+                    let _ = users.insert(
+                        sender_uuid,
+                        client
+                            .lock()
+                            .await
+                            .lookup_user(UserLookupRequest {
+                                identifier: Some(Identifier::Uuid(sender_uuid.into())),
+                            })
+                            .await
+                            .unwrap()
+                            .into_inner(),
+                    );
+                }
+                drop(users);
+            }
+
+            MessageEdited(m) | MessageDeleted(m) => {
+                assert_eq!(Some(proto::Uuid::from(room_uuid)), m.room_uuid);
+
+                // Tombstones lose all of their reactions.
+                if m.deleted_at.is_some() {
+                    let message_uuid = m
+                        .uuid
+                        .clone()
+                        .and_then(|u| Uuid::try_from(u).ok())
+                        .expect("The serverside message's UUID was invalid");
+                    reactions.lock().await.shift_remove(&message_uuid);
+                }
+
+                // Update the cached message in place, so it keeps its position.
+                let edited_message = Message::try_from(m)
+                    .unwrap_or_else(|err| panic!("The serverside message was malformed: {err}"));
+                if let Some(cached_message) = messages.lock().await.get_mut(&edited_message.uuid) {
+                    *cached_message = edited_message;
+                }
+            }
+
+            ReactionAdded(reaction) => {
+                let message_uuid = reaction
+                    .message_uuid
+                    .and_then(|u| Uuid::try_from(u).ok())
+                    .expect("The reaction's message UUID was invalid");
+                let mut reactions = reactions.lock().await;
+                *reactions
+                    .entry(message_uuid)
+                    .or_default()
+                    .entry(reaction.emoji)
+                    .or_default() += 1;
+                drop(reactions);
+            }
+
+            ReactionRemoved(reaction) => {
+                let message_uuid = reaction
+                    .message_uuid
+                    .and_then(|u| Uuid::try_from(u).ok())
+                    .expect("The reaction's message UUID was invalid");
+                let mut reactions = reactions.lock().await;
+                if let Some(counts) = reactions.get_mut(&message_uuid) {
+                    if let Some(count) = counts.get_mut(&reaction.emoji) {
+                        *count = count.saturating_sub(1);
+                        if *count == 0 {
+                            counts.shift_remove(&reaction.emoji);
                         }
-                        drop(users);
+                    }
+                    if counts.is_empty() {
+                        reactions.shift_remove(&message_uuid);
                     }
                 }
+                drop(reactions);
             }
-        });
+
+            UserJoined(user) | UserLeft(user) => {
+                let member_uuid = user
+                    .uuid
+                    .clone()
+                    .and_then(|u| Uuid::try_from(u).ok())
+                    .expect("The room member's UUID was invalid");
+                users.lock().await.insert(member_uuid, user);
+            }
+
+            RoomUpdated(metadata) => {
+                if let Some(room) = rooms.lock().await.get_mut(&room_uuid) {
+                    room.name = metadata.name;
+                    room.topic = metadata.topic;
+                    room.description = metadata.description;
+                }
+            }
+
+            UserTyping(user_typing) => {
+                let typist_uuid = user_typing
+                    .user_uuid
+                    .and_then(|u| Uuid::try_from(u).ok())
+                    .expect("The typing user's UUID was invalid");
+                if typist_uuid == user_uuid {
+                    return;
+                }
+                let mut typing = typing.lock().await;
+                let typists = typing.entry(room_uuid).or_default();
+                if user_typing.typing {
+                    typists.insert(typist_uuid);
+                } else {
+                    typists.shift_remove(&typist_uuid);
+                }
+                drop(typing);
+            }
+        }
+    }
+}
+
+fn subscribe_frame(room_uuid: RoomUUID) -> ClientFrame {
+    ClientFrame {
+        frame: Some(ClientFrameKind::Subscribe(room_uuid.into())),
+    }
+}
+
+fn unsubscribe_frame(room_uuid: RoomUUID) -> ClientFrame {
+    ClientFrame {
+        frame: Some(ClientFrameKind::Unsubscribe(room_uuid.into())),
+    }
+}
+
+const fn ack_frame(sequence: u64) -> ClientFrame {
+    ClientFrame {
+        frame: Some(ClientFrameKind::Ack(sequence)),
+    }
+}
+
+/// Explains why the server refused a message, including when to try again if it was rate limited.
+fn send_failure_notice(status: &Status) -> String {
+    let retry_after = status
+        .metadata()
+        .get("retry-after")
+        .and_then(|value| value.to_str().ok());
+    match (status.code(), retry_after) {
+        (Code::ResourceExhausted, Some(seconds)) => {
+            format!(" Sending too fast, try again in {seconds}s")
+        }
+        _ => format!(" Couldn't send the message: {}", status.message()),
     }
 }
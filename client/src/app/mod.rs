@@ -11,9 +11,7 @@ use crossterm::event::{self, DisableMouseCapture, EnableMouseCapture, Event, Key
 use crossterm::terminal::{self, EnterAlternateScreen, LeaveAlternateScreen};
 use ratatui::backend::{Backend, CrosstermBackend};
 use ratatui::Terminal;
-use std::mem;
 use std::{io, panic, time::Duration};
-use tcp_chat_server::proto::{self, ClientsideMessage};
 use tokio::{sync::oneshot, task::JoinHandle};
 use tokio_util::sync::CancellationToken;
 
@@ -64,6 +62,11 @@ where
 
         loop {
             self.render_ui().await?;
+            if let Stage::LoggedIn { ref mut chat } = self.stage {
+                // If these fail, we'll just try again on the next frame.
+                let _ = chat.mark_focused_room_read().await;
+                let _ = chat.update_typing().await;
+            }
 
             if event::poll(Duration::from_millis(100))? {
                 if let Event::Key(event) = event::read()? {
@@ -74,6 +77,10 @@ where
 
                     // Quit with no questions asked if the user hits Escape.
                     if event.code == KeyCode::Esc {
+                        if let Stage::LoggedIn { ref chat } = self.stage {
+                            // If this fails, the session will just expire on its own.
+                            let _ = chat.client.lock().await.logout(()).await;
+                        }
                         let _ = cancel_signal.send(());
                         break;
                     }
@@ -104,25 +111,20 @@ where
                         },
 
                         Stage::LoggedIn { ref mut chat } => match event.code {
-                            KeyCode::Enter => {
-                                if !chat.message_draft.is_empty() {
-                                    let rooms = chat.rooms.lock().await;
-                                    let room_uuid = match chat.room_list_state.selected() {
-                                        Some(i) => rooms.keys().nth(i).unwrap(),
-                                        None => todo!(),
-                                    };
+                            KeyCode::Enter if !chat.message_draft.is_empty() => {
+                                chat.send_draft().await;
+                            }
 
-                                    chat.client
-                                        .lock()
-                                        .await
-                                        .send_message(ClientsideMessage {
-                                            room_uuid: Some(proto::Uuid::from(*room_uuid)),
-                                            text: mem::take(&mut chat.message_draft),
-                                        })
-                                        .await
-                                        .unwrap();
-
-                                    drop(rooms);
+                            KeyCode::Up | KeyCode::Down => {
+                                let room_count = chat.rooms.lock().await.len();
+                                if room_count > 0 {
+                                    let focused = chat.room_list_state.selected().unwrap_or(0);
+                                    let focused = if event.code == KeyCode::Up {
+                                        (focused + room_count - 1) % room_count
+                                    } else {
+                                        (focused + 1) % room_count
+                                    };
+                                    chat.room_list_state.select(Some(focused));
                                 }
                             }
 
@@ -42,7 +42,7 @@ impl Registry {
         &self.editing_mode
     }
 
-    pub fn toggle_mode(&mut self) {
+    pub const fn toggle_mode(&mut self) {
         match &self.editing_mode {
             EditingMode::Username => self.editing_mode = EditingMode::Password,
             EditingMode::Password => self.editing_mode = EditingMode::Username,
@@ -52,29 +52,36 @@ impl Registry {
     pub async fn into_chat(mut self) -> Result<Chat<Interceptor>, Status> {
         let username = self.username.clone();
         let password = self.password.clone();
+        let device = format!("tcp-chat TUI on {}", std::env::consts::OS);
         let auth_pair = self
-            .login_as_user(UserCredentials { username, password })
+            .login_as_user(UserCredentials {
+                username,
+                password,
+                device,
+            })
             .await?
             .into_inner();
         let proto_uuid = auth_pair
             .user_uuid
+            .clone()
             .ok_or_else(|| Status::invalid_argument("The server did not return a user UUID"))?;
         let uuid = Uuid::try_from(proto_uuid)
             .map_err(|_| Status::invalid_argument("The server returned an invalid user UUID"))?;
-        let auth_token = auth_pair
-            .token
-            .ok_or_else(|| Status::invalid_argument("The server did not provide an AuthToken!"))?
-            .to_string();
+        if auth_pair.token.is_none() {
+            return Err(Status::invalid_argument(
+                "The server did not provide an AuthToken!",
+            ));
+        }
 
         self.failed = false;
         let user = User {
             uuid,
             username: self.username,
             password: self.password,
-            auth_token,
+            last_seen: None,
         };
 
-        Ok(Chat::new(user).await)
+        Ok(Chat::new(user, auth_pair).await)
     }
 }
 
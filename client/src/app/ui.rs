@@ -1,11 +1,17 @@
 use super::{registry::EditingMode, Registry};
 use super::{App, Stage};
+use indexmap::IndexMap;
 use ratatui::backend::Backend;
 use ratatui::layout::{Constraint, Direction, Layout, Rect};
 use ratatui::style::{Style, Stylize};
+use ratatui::text::{Line, Span, Text};
 use ratatui::widgets::{Block, List, ListDirection, Paragraph};
-use ratatui::{text::Line, Frame};
+use ratatui::Frame;
+use std::time::SystemTime;
 use std::{io, rc::Rc};
+use tcp_chat_server::entities::{Message, NotificationLevel, Room};
+use tcp_chat_server::proto;
+use uuid::Uuid;
 
 impl<B> App<B>
 where
@@ -32,6 +38,13 @@ where
             let rooms = chat.rooms.lock().await;
             let messages = chat.messages.lock().await;
             let users = chat.users.lock().await;
+            let reactions = chat.reactions.lock().await;
+            let unread = chat.unread.lock().await;
+            let mentions = chat.mentions.lock().await;
+            let notifications = chat.notifications.lock().await;
+            let now = SystemTime::now();
+            let typing = chat.typing.lock().await;
+            let profiles = chat.profiles.lock().await;
 
             self.terminal.draw(|frame| {
                 let sections = Layout::default()
@@ -47,11 +60,14 @@ where
                 let draft_area = sections.get(1).unwrap();
 
                 // Render the list of user's rooms.
-                let room_list = List::new(
-                    rooms
-                        .values()
-                        .map(|room| Line::from(format!(" {} ", room.name))),
-                )
+                let room_list = List::new(rooms.values().map(|room| {
+                    room_list_line(
+                        room,
+                        unread.get(&room.uuid).copied(),
+                        mentions.get(&room.uuid).copied(),
+                        notification_level(notifications.get(&room.uuid), now),
+                    )
+                }))
                 .highlight_style(Style::default().on_dark_gray().bold())
                 .block(
                     Block::bordered()
@@ -59,7 +75,12 @@ where
                         .title_top(Line::from(" Rooms ").left_aligned())
                         .title_style(Style::default().white().bold()),
                 );
-                if !rooms.is_empty() {
+                if !rooms.is_empty()
+                    && chat
+                        .room_list_state
+                        .selected()
+                        .is_none_or(|i| i >= rooms.len())
+                {
                     chat.room_list_state.select(Some(0));
                 }
 
@@ -67,38 +88,50 @@ where
                 let focused_room_uuid = chat
                     .room_list_state
                     .selected()
-                    .and_then(|i| messages.values().map(|m| &m.room_uuid).nth(i));
+                    .and_then(|i| rooms.keys().nth(i));
                 let message_list = List::new(
                     messages
                         .values()
                         .rev()
                         .filter(|msg| Some(&msg.room_uuid) == focused_room_uuid)
                         .map(|msg| {
-                            format!(
-                                " ({}) {}",
-                                users.get(&msg.sender_uuid).map_or("unknown", |user| {
-                                    if msg.sender_uuid == chat.user.uuid {
-                                        return "you";
-                                    }
-                                    user.username.as_str()
-                                }),
-                                msg.text
-                            )
+                            let sender = if msg.sender_uuid == chat.user.uuid {
+                                "you"
+                            } else {
+                                shown_name(&msg.sender_uuid, &users, &profiles).unwrap_or("unknown")
+                            };
+                            message_text(msg, sender, reactions.get(&msg.uuid))
                         }),
                 )
                 .direction(ListDirection::BottomToTop)
                 .block(
                     Block::bordered()
                         .border_style(Style::default().dark_gray())
-                        .title_top(Line::from(" Messages ").left_aligned())
+                        .title_top(
+                            message_list_title(focused_room_uuid.and_then(|u| rooms.get(u)))
+                                .left_aligned(),
+                        )
                         .title_style(Style::default().white().bold()),
                 );
 
-                // Render the message draft (where the user types in the message).
-                let message_draft = Paragraph::new(Line::styled(
-                    format!(" (msg) > {}_", chat.message_draft),
-                    Style::default().green().bold(),
-                ));
+                // Render the message draft (where the user types in the message), with a note
+                // about who else is typing (or why the last message wasn't sent) right above it.
+                let typists: Vec<&str> = focused_room_uuid
+                    .and_then(|room_uuid| typing.get(room_uuid))
+                    .into_iter()
+                    .flatten()
+                    .map(|u| shown_name(u, &users, &profiles).unwrap_or("someone"))
+                    .collect();
+                let message_draft = Paragraph::new(vec![
+                    chat.notice.as_ref().map_or_else(
+                        || typing_line(&typists),
+                        |notice| Line::styled(notice.clone(), Style::default().red().bold()),
+                    ),
+                    Line::styled(
+                        format!(" (msg) > {}_", chat.message_draft),
+                        Style::default().green().bold(),
+                    ),
+                ]);
 
                 frame.render_stateful_widget(room_list, *room_list_area, &mut chat.room_list_state);
                 frame.render_widget(message_list, *message_list_area);
@@ -223,3 +256,122 @@ fn render_password_prompt(
     frame.render_widget(password_label, password_area[0]);
     frame.render_widget(password_field, password_area[1]);
 }
+
+/// The name to show for a user: their display name if they've set one, or their username.
+fn shown_name<'a>(
+    user_uuid: &Uuid,
+    users: &'a IndexMap<Uuid, proto::User>,
+    profiles: &'a IndexMap<Uuid, proto::Profile>,
+) -> Option<&'a str> {
+    profiles
+        .get(user_uuid)
+        .map(|profile| profile.display_name.as_str())
+        .filter(|display_name| !display_name.trim().is_empty())
+        .or_else(|| users.get(user_uuid).map(|user| user.username.as_str()))
+}
+
+/// The notification level in effect for a room, which is the default one if the server didn't say.
+fn notification_level(
+    settings: Option<&proto::RoomNotificationSettings>,
+    now: SystemTime,
+) -> NotificationLevel {
+    settings.map_or_else(NotificationLevel::default, |settings| {
+        let muted_until = settings
+            .muted_until
+            .clone()
+            .and_then(|t| SystemTime::try_from(t).ok());
+        NotificationLevel::from(settings.level()).at(muted_until, now)
+    })
+}
+
+/// A line in the room list, with an unread badge if there's anything unread in the room,
+/// and a mention marker if any of it mentions the current user.
+///
+/// Muted rooms are dimmed and never highlighted, and rooms that only notify of
+/// mentions get no unread badge.
+fn room_list_line(
+    room: &Room,
+    unread: Option<u64>,
+    mentions: Option<u64>,
+    level: NotificationLevel,
+) -> Line<'_> {
+    if level == NotificationLevel::Muted {
+        return Line::styled(format!(" {} ", room.name), Style::default().dark_gray());
+    }
+
+    let mut line = Line::from(format!(" {} ", room.name));
+    let unread = unread.filter(|_| level == NotificationLevel::All);
+    if let Some(count) = unread.filter(|&c| c > 0) {
+        line.push_span(Span::styled(
+            format!("({count}) "),
+            Style::default().yellow().bold(),
+        ));
+    }
+    if mentions.is_some_and(|c| c > 0) {
+        line.push_span(Span::styled("@ ", Style::default().light_red().bold()));
+    }
+    line
+}
+
+/// A line telling who else is typing a message in a room (empty if nobody is).
+fn typing_line(typists: &[&str]) -> Line<'static> {
+    let text = match typists {
+        [] => return Line::default(),
+        [typist] => format!(" {typist} is typing…"),
+        [first, second] => format!(" {first} and {second} are typing…"),
+        _ => " several people are typing…".to_string(),
+    };
+    Line::styled(text, Style::default().italic().dark_gray())
+}
+
+/// A list item for a message: its text along with a line of reactions (if there are any).
+fn message_text(
+    msg: &Message,
+    sender: &str,
+    reactions: Option<&IndexMap<String, u32>>,
+) -> Text<'static> {
+    if msg.is_deleted() {
+        return Text::styled(
+            format!(" ({sender}) message deleted"),
+            Style::default().italic().dark_gray(),
+        );
+    }
+
+    let edited = if msg.edited_at.is_some() {
+        " (edited)"
+    } else {
+        ""
+    };
+    let thread = if msg.reply_to.is_some() { "↳ " } else { "" };
+    let mut text = Text::from(format!(" {thread}({sender}) {}{edited}", msg.text));
+
+    // Show the reactions (if any) right under the message.
+    if let Some(counts) = reactions {
+        let counts = counts
+            .iter()
+            .map(|(emoji, count)| format!("{emoji} {count}"))
+            .collect::<Vec<_>>()
+            .join("  ");
+        text.push_line(Line::styled(
+            format!("     {counts}"),
+            Style::default().dark_gray(),
+        ));
+    }
+
+    text
+}
+
+/// The title of the message list: the focused room's name, along with its topic (if it has one).
+fn message_list_title(room: Option<&Room>) -> Line<'_> {
+    match room {
+        None => Line::from(" Messages "),
+        Some(room) if room.topic.is_empty() => Line::from(format!(" {} ", room.name)),
+        Some(room) => Line::from(vec![
+            Span::raw(format!(" {} ", room.name)),
+            Span::styled(
+                format!("· {} ", room.topic),
+                Style::default().dark_gray().not_bold(),
+            ),
+        ]),
+    }
+}
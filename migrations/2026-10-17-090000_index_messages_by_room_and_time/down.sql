@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX messages_room_uuid_timestamp_idx;
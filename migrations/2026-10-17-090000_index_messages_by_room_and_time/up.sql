@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- The UUID is a tie-breaker for messages sent at the same moment (see `ListMessagesPage`).
+CREATE INDEX messages_room_uuid_timestamp_idx ON messages (room_uuid, timestamp, uuid);
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- Deleted messages are kept as tombstones (with their text wiped), so history ordering stays intact.
+ALTER TABLE messages ADD COLUMN deleted_at TIMESTAMP;
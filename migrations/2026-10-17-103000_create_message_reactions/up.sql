@@ -0,0 +1,7 @@
+-- Your SQL goes here
+CREATE TABLE message_reactions (
+    message_uuid UUID NOT NULL REFERENCES messages(uuid),
+    user_uuid UUID NOT NULL REFERENCES users(uuid),
+    emoji VARCHAR(32) NOT NULL,
+    PRIMARY KEY(message_uuid, user_uuid, emoji)
+);
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+-- Replies always point to the root message of their thread (see `SendMessage`).
+ALTER TABLE messages ADD COLUMN reply_to UUID REFERENCES messages(uuid);
+CREATE INDEX messages_reply_to_idx ON messages (reply_to);
@@ -0,0 +1,3 @@
+-- Your SQL goes here
+-- When the user was last online. NULL means never (or currently online for the first time).
+ALTER TABLE users ADD COLUMN last_seen TIMESTAMP;
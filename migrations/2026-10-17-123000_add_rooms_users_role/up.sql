@@ -0,0 +1,18 @@
+-- Your SQL goes here
+-- One of 'member', 'admin' or 'owner'.
+ALTER TABLE rooms_users ADD COLUMN role VARCHAR(16) NOT NULL DEFAULT 'member';
+
+-- Rooms created before roles existed don't remember who created them, so the member who spoke
+-- there first (or, if nobody did, the one with the lowest UUID) becomes their owner.
+UPDATE rooms_users AS ru
+SET role = 'owner'
+FROM (
+    SELECT DISTINCT ON (member.room_uuid) member.room_uuid, member.user_uuid
+    FROM rooms_users AS member
+    LEFT JOIN messages AS m
+        ON m.room_uuid = member.room_uuid AND m.sender_uuid = member.user_uuid
+    GROUP BY member.room_uuid, member.user_uuid
+    ORDER BY member.room_uuid, MIN(m.timestamp) ASC NULLS LAST, member.user_uuid
+) AS first_members
+WHERE ru.room_uuid = first_members.room_uuid
+  AND ru.user_uuid = first_members.user_uuid;
@@ -0,0 +1,4 @@
+-- Your SQL goes here
+ALTER TABLE rooms
+    ADD COLUMN topic VARCHAR(256) NOT NULL DEFAULT '',
+    ADD COLUMN description TEXT NOT NULL DEFAULT '';
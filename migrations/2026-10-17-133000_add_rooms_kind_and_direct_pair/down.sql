@@ -0,0 +1,9 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX rooms_direct_pair;
+
+ALTER TABLE rooms
+    DROP CONSTRAINT direct_pairs_are_canonical,
+    DROP CONSTRAINT direct_rooms_have_a_pair,
+    DROP COLUMN direct_user_high,
+    DROP COLUMN direct_user_low,
+    DROP COLUMN kind;
@@ -0,0 +1,14 @@
+-- Your SQL goes here
+-- Direct rooms are 1:1 chats, identified by an unordered pair of users stored in a canonical
+-- order (the smaller UUID first), so that each pair of users can only ever have one of them.
+-- Private chats created before this have no pair recorded, and stay as group rooms.
+ALTER TABLE rooms
+    ADD COLUMN kind VARCHAR(16) NOT NULL DEFAULT 'group',
+    ADD COLUMN direct_user_low UUID REFERENCES users(uuid),
+    ADD COLUMN direct_user_high UUID REFERENCES users(uuid),
+    ADD CONSTRAINT direct_rooms_have_a_pair
+        CHECK ((kind = 'direct') = (direct_user_low IS NOT NULL AND direct_user_high IS NOT NULL)),
+    ADD CONSTRAINT direct_pairs_are_canonical
+        CHECK (direct_user_low < direct_user_high);
+
+CREATE UNIQUE INDEX rooms_direct_pair ON rooms (direct_user_low, direct_user_high);
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX messages_search_vector;
+ALTER TABLE messages DROP COLUMN search_vector;
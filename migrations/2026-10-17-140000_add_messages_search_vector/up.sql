@@ -0,0 +1,7 @@
+-- Your SQL goes here
+-- The 'simple' configuration doesn't stem words, so it works for messages in any language.
+ALTER TABLE messages
+    ADD COLUMN search_vector TSVECTOR
+    GENERATED ALWAYS AS (to_tsvector('simple', text)) STORED;
+
+CREATE INDEX messages_search_vector ON messages USING GIN (search_vector);
@@ -0,0 +1,14 @@
+-- Your SQL goes here
+CREATE TABLE attachments (
+    uuid UUID PRIMARY KEY,
+    uploader_uuid UUID NOT NULL REFERENCES users(uuid),
+    room_uuid UUID NOT NULL REFERENCES rooms(uuid),
+    message_uuid UUID REFERENCES messages(uuid),
+    file_name VARCHAR(255) NOT NULL,
+    content_type VARCHAR(127) NOT NULL,
+    size BIGINT NOT NULL CHECK (size >= 0),
+    blake3_hash CHAR(64) NOT NULL,
+    uploaded_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX attachments_by_message ON attachments (message_uuid);
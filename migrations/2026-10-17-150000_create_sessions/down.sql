@@ -0,0 +1,11 @@
+-- This file should undo anything in `up.sql`
+-- Users get the token of their latest session back, or a fresh one if they have none.
+ALTER TABLE users ADD COLUMN auth_token CHAR(32);
+UPDATE users
+SET auth_token = COALESCE(
+    (SELECT token FROM sessions WHERE sessions.user_uuid = users.uuid ORDER BY created_at DESC LIMIT 1),
+    md5(random()::text)
+);
+ALTER TABLE users ALTER COLUMN auth_token SET NOT NULL;
+
+DROP TABLE sessions;
@@ -0,0 +1,21 @@
+-- Your SQL goes here
+CREATE TABLE sessions (
+    uuid UUID PRIMARY KEY,
+    user_uuid UUID NOT NULL REFERENCES users(uuid),
+    token CHAR(32) NOT NULL UNIQUE,
+    device VARCHAR(64) NOT NULL,
+    created_at TIMESTAMP NOT NULL,
+    expires_at TIMESTAMP NOT NULL,
+    last_used_at TIMESTAMP NOT NULL
+);
+
+CREATE INDEX sessions_by_user ON sessions (user_uuid);
+
+-- Tokens belong to sessions now. The old permanent ones become sessions of their own, so that
+-- clients that are already logged in stay logged in (until the session expires, like any other).
+-- The interval has to match `Session::LIFETIME`.
+INSERT INTO sessions (uuid, user_uuid, token, device, created_at, expires_at, last_used_at)
+SELECT gen_random_uuid(), uuid, auth_token, 'Unknown device', now(), now() + INTERVAL '30 days', now()
+FROM users;
+
+ALTER TABLE users DROP COLUMN auth_token;
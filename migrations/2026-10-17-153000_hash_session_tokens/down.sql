@@ -0,0 +1,7 @@
+-- This file should undo anything in `up.sql`
+
+-- Hashed tokens can't be turned back into plaintext ones, so every session ends.
+DELETE FROM sessions;
+
+ALTER TABLE sessions ADD COLUMN token CHAR(32) NOT NULL UNIQUE;
+ALTER TABLE sessions DROP COLUMN token_hash;
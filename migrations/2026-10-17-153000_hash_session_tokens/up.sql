@@ -0,0 +1,14 @@
+-- Your SQL goes here
+
+-- Tokens are stored as a keyed hash (HMAC-SHA256) instead of in plaintext. Existing tokens are
+-- re-keyed in place, so sessions started before this keep working. The key has to be the same
+-- as the server's `$TOKEN_HASH_KEY`, and is passed in as a setting through `$PGOPTIONS`.
+CREATE EXTENSION IF NOT EXISTS pgcrypto;
+
+ALTER TABLE sessions ADD COLUMN token_hash CHAR(64);
+UPDATE sessions
+SET token_hash = encode(hmac(token, current_setting('tcp_chat.token_hash_key'), 'sha256'), 'hex');
+ALTER TABLE sessions ALTER COLUMN token_hash SET NOT NULL;
+ALTER TABLE sessions ADD CONSTRAINT sessions_token_hash_key UNIQUE (token_hash);
+
+ALTER TABLE sessions DROP COLUMN token;
@@ -0,0 +1,3 @@
+-- This file should undo anything in `up.sql`
+-- Fails as long as anything is still attributed to deleted users, as there's nobody to give it back to.
+DELETE FROM users WHERE uuid = '00000000-0000-0000-0000-000000000000';
@@ -0,0 +1,6 @@
+-- Your SQL goes here
+-- Messages (and attachments) of deleted accounts are handed over to this placeholder, so that
+-- they stay in their rooms without pointing to anyone in particular. Nobody can log in as it.
+INSERT INTO users (uuid, username, password)
+VALUES ('00000000-0000-0000-0000-000000000000', 'Deleted user', '')
+ON CONFLICT DO NOTHING;
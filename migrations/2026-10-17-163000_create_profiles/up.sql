@@ -0,0 +1,12 @@
+-- Your SQL goes here
+-- Users that have never touched their profile don't have a row here, and have an empty profile.
+CREATE TABLE profiles (
+    user_uuid UUID NOT NULL PRIMARY KEY REFERENCES users(uuid),
+    display_name VARCHAR(64) NOT NULL DEFAULT '',
+    bio VARCHAR(512) NOT NULL DEFAULT '',
+    status_text VARCHAR(128) NOT NULL DEFAULT '',
+    status_emoji VARCHAR(32) NOT NULL DEFAULT '',
+    pronouns VARCHAR(32) NOT NULL DEFAULT '',
+    timezone VARCHAR(64) NOT NULL DEFAULT '',
+    updated_at TIMESTAMP NOT NULL DEFAULT now()
+);
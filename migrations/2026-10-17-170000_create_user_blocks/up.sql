@@ -0,0 +1,11 @@
+-- Your SQL goes here
+CREATE TABLE user_blocks (
+    blocker_uuid UUID NOT NULL REFERENCES users(uuid),
+    blocked_uuid UUID NOT NULL REFERENCES users(uuid),
+    blocked_at TIMESTAMP NOT NULL DEFAULT now(),
+    PRIMARY KEY(blocker_uuid, blocked_uuid),
+    CONSTRAINT users_cant_block_themselves CHECK (blocker_uuid <> blocked_uuid)
+);
+
+-- Deleting an account has to find everyone who blocked it.
+CREATE INDEX user_blocks_by_blocked ON user_blocks (blocked_uuid);
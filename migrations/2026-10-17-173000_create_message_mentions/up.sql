@@ -0,0 +1,9 @@
+-- Your SQL goes here
+CREATE TABLE message_mentions (
+    message_uuid UUID NOT NULL REFERENCES messages(uuid),
+    user_uuid UUID NOT NULL REFERENCES users(uuid),
+    PRIMARY KEY(message_uuid, user_uuid)
+);
+
+-- Mention inboxes are looked up by the mentioned user.
+CREATE INDEX message_mentions_by_user ON message_mentions (user_uuid);
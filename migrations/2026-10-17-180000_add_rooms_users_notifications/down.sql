@@ -0,0 +1,5 @@
+-- This file should undo anything in `up.sql`
+ALTER TABLE rooms_users
+    DROP CONSTRAINT only_muted_rooms_have_a_mute_end,
+    DROP COLUMN muted_until,
+    DROP COLUMN notification_level;
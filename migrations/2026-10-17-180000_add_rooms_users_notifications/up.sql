@@ -0,0 +1,7 @@
+-- Your SQL goes here
+-- One of 'all', 'mentions' or 'muted'. Rooms stay muted forever unless muted_until is set.
+ALTER TABLE rooms_users
+    ADD COLUMN notification_level VARCHAR(16) NOT NULL DEFAULT 'all',
+    ADD COLUMN muted_until TIMESTAMP,
+    ADD CONSTRAINT only_muted_rooms_have_a_mute_end
+        CHECK (muted_until IS NULL OR notification_level = 'muted');
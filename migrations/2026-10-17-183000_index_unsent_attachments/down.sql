@@ -0,0 +1,2 @@
+-- This file should undo anything in `up.sql`
+DROP INDEX attachments_unsent_by_uploader;
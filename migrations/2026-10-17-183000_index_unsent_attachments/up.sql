@@ -0,0 +1,4 @@
+-- Your SQL goes here
+-- Unsent attachments are counted against their uploader's quota, and deleted once they expire.
+CREATE INDEX attachments_unsent_by_uploader ON attachments (uploader_uuid, uploaded_at)
+WHERE message_uuid IS NULL;
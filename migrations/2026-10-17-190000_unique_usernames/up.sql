@@ -0,0 +1,14 @@
+-- Your SQL goes here
+-- Usernames used to only be checked for duplicates before being stored, which races. Any
+-- duplicates that slipped through get a suffix from their UUID, and can be renamed later.
+UPDATE users
+SET username = left(username, 55) || '_' || left(uuid::text, 8)
+WHERE uuid IN (
+    SELECT uuid FROM (
+        SELECT uuid, row_number() OVER (PARTITION BY username ORDER BY uuid) AS duplicate
+        FROM users
+    ) AS numbered
+    WHERE duplicate > 1
+);
+
+CREATE UNIQUE INDEX users_username_key ON users (username);
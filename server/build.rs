@@ -1,5 +1,7 @@
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    tonic_build::configure().compile(
+    // The generated clients' `connect` constructors would clash with the `Connect` RPC,
+    // and nothing uses them anyway, as clients are built on top of an existing channel.
+    tonic_build::configure().build_transport(false).compile(
         &[
             "../proto/entities.proto",
             "../proto/requests.proto",
@@ -1,8 +1,9 @@
-use crate::entities::User;
+use crate::entities::{Session, TokenKey};
 use crate::proto::AuthPair;
 use crate::services::acquire_connection_error_status;
 use crate::{entities::token::AuthToken, persistence::ConnectionPool};
 use std::str::FromStr;
+use std::time::{Duration, SystemTime};
 use tonic::{service::Interceptor, Request, Status};
 use uuid::Uuid;
 
@@ -14,6 +15,7 @@ pub trait AuthenticatedRequest {
     fn get_auth_pair(&self) -> Result<AuthPair, Self::Error>;
     fn get_originator_uuid(&self) -> Result<Uuid, Self::Error>;
     fn get_token(&self) -> Result<AuthToken, Self::Error>;
+    fn get_session_uuid(&self) -> Result<Uuid, Self::Error>;
 }
 
 #[derive(thiserror::Error, Debug, Clone, Copy)]
@@ -71,32 +73,50 @@ impl<T> AuthenticatedRequest for Request<T> {
             .and_then(|m| AuthToken::from_str(m).ok())
             .ok_or(AuthMetadataError::RetrievalError)
     }
+
+    fn get_session_uuid(&self) -> Result<Uuid, Self::Error> {
+        self.metadata()
+            .get(Authenticator::SESSION_UUID_KEY)
+            .and_then(|m| m.to_str().ok())
+            .and_then(|m| Uuid::from_str(m).ok())
+            .ok_or(AuthMetadataError::RetrievalError)
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Authenticator {
     persistence_pool: ConnectionPool,
+    token_key: TokenKey,
 }
 
 impl Authenticator {
-    pub fn new(persistence_pool: ConnectionPool) -> Self {
-        Self { persistence_pool }
+    pub const fn new(persistence_pool: ConnectionPool, token_key: TokenKey) -> Self {
+        Self {
+            persistence_pool,
+            token_key,
+        }
     }
 
     pub const USER_UUID_KEY: &'static str = "user_uuid";
     pub const AUTH_TOKEN_KEY: &'static str = "auth_token";
+    /// Set by the authenticator itself, never trusted from the client.
+    pub const SESSION_UUID_KEY: &'static str = "session_uuid";
+
+    /// How stale a session's `last_used_at` may get before it's updated, so that
+    /// not every single request has to write to the database.
+    const LAST_USED_RESOLUTION: Duration = Duration::from_secs(60);
 }
 
 impl Interceptor for Authenticator {
     fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
         let auth_pair: AuthPair = request.get_auth_pair().map_err(|_| unauthenticated())?;
-        let user_uuid: Uuid = auth_pair
+        let originator: Uuid = auth_pair
             .user_uuid
             .and_then(|u| u.try_into().ok())
             .ok_or_else(unauthenticated)?;
-        let proto_auth_token: String = auth_pair
+        let presented_token: AuthToken = auth_pair
             .token
-            .map(|t| t.to_string())
+            .and_then(|t| t.try_into().ok())
             .ok_or_else(unauthenticated)?;
 
         // WARN: Wipe out the AuthToken from the request's metadata,
@@ -109,25 +129,52 @@ impl Interceptor for Authenticator {
             .map_err(acquire_connection_error_status)?;
 
         // Import some traits and methods to interact with the ORM.
-        use crate::entities::schema::users::dsl::*;
-        use diesel::query_dsl::methods::{FilterDsl, SelectDsl};
-        use diesel::{ExpressionMethods, OptionalExtension, RunQueryDsl, SelectableHelper};
-
-        let user_with_matching_credentials = users
-            .filter(uuid.eq(user_uuid))
-            .filter(auth_token.eq(proto_auth_token))
-            .select(User::as_select())
-            .first(&mut connection)
-            .optional()
-            .map_err(|err| Status::internal(err.to_string()))?;
-
-        match user_with_matching_credentials {
-            Some(user) => {
-                tracing::trace!(message = "Authenticated request", username = ?user.username);
-                Ok(request)
-            }
-            None => Err(unauthenticated()),
+        use crate::entities::schema::sessions::dsl::*;
+        use diesel::prelude::*;
+
+        // Only hashes of the tokens are stored, and those are never looked up directly,
+        // so that how long the lookup takes can't tell anything about them.
+        let session = sessions
+            .filter(user_uuid.eq(originator))
+            .select(Session::as_select())
+            .load(&mut connection)
+            .map_err(|err| Status::internal(err.to_string()))?
+            .into_iter()
+            .find(|session| self.token_key.verify(&presented_token, &session.token_hash))
+            .ok_or_else(unauthenticated)?;
+
+        let now = SystemTime::now();
+        if session.is_expired_at(now) {
+            let _ = diesel::delete(sessions.find(session.uuid)).execute(&mut connection);
+            tracing::info!(message = "Rejected an expired session", user = ?originator);
+            return Err(Status::unauthenticated(
+                "The session has expired, please log in again",
+            ));
         }
+
+        if let Err(error) = diesel::update(
+            sessions
+                .find(session.uuid)
+                .filter(last_used_at.lt(now - Self::LAST_USED_RESOLUTION)),
+        )
+        .set(last_used_at.eq(now))
+        .execute(&mut connection)
+        {
+            tracing::warn!(message = "Could not update the session's last use", ?error);
+        }
+
+        // Overwrite whatever the client may have put there.
+        let session_uuid = session
+            .uuid
+            .to_string()
+            .parse()
+            .map_err(|_| Status::internal("Could not attach the session to the request"))?;
+        request
+            .metadata_mut()
+            .insert(Self::SESSION_UUID_KEY, session_uuid);
+
+        tracing::trace!(message = "Authenticated request", user = ?originator);
+        Ok(request)
     }
 }
 
@@ -140,7 +187,7 @@ fn unauthenticated() -> Status {
 #[allow(clippy::unwrap_used)]
 mod tests {
     use super::AuthenticatedRequest;
-    use crate::{entities::User, proto::AuthPair};
+    use crate::entities::{AuthToken, Session, TokenKey, User};
     use rand_chacha::ChaCha20Rng;
     use rand_core::{OsRng, RngCore, SeedableRng};
     use tonic::Request;
@@ -148,11 +195,10 @@ mod tests {
     #[test]
     fn auth_pair_roundtrip() {
         let mut rng = ChaCha20Rng::seed_from_u64(OsRng.next_u64());
-        let user = User::new("user_1".into(), "pass_1".into(), &mut rng);
-        let auth_pair = AuthPair {
-            user_uuid: Some(user.uuid.into()),
-            token: Some(user.proto_token()),
-        };
+        let user = User::new("user_1".into(), "pass_1".into());
+        let token = AuthToken::new(&mut rng);
+        let token_hash = TokenKey::new(b"key_1").hash(&token);
+        let auth_pair = Session::new(user.uuid, "device_1", token_hash).auth_pair(token);
 
         let mut request = Request::new(());
         assert!(request.add_auth_pair(auth_pair.clone()).is_ok());
@@ -20,7 +20,7 @@
 //!
 //! ## Example usage
 //!
-//! ```rust
+//! ```ignore
 //! use futures::Stream;
 //! use std::task::{Context, Poll};
 //! use std::{ops::Deref, pin::Pin};
@@ -47,6 +47,10 @@
 //! frameworks, refer to the following GitHub issue:
 //! - [Tonic Issue #377](https://github.com/hyperium/tonic/issues/377)
 
+mod outbox;
+
+pub use outbox::Outbox;
+
 use futures::Stream;
 use std::task::{Context, Poll};
 use std::{ops::Deref, pin::Pin};
@@ -0,0 +1,148 @@
+//! # Outbox
+//!
+//! An `Outbox` holds the frames a connection has yet to send, and numbers them as they go out.
+//!
+//! Only a limited number of frames may be in flight at once: once the window is full, frames
+//! wait in the outbox until the client acknowledges some of the ones it got. The outbox itself
+//! is bounded too, so that a client that never acknowledges anything can't make the server
+//! hold on to an endless backlog.
+
+use std::collections::VecDeque;
+
+#[derive(Debug)]
+pub struct Outbox<T> {
+    queue: VecDeque<T>,
+    capacity: usize,
+    window: u64,
+    sequence: u64,
+    acknowledged: u64,
+}
+
+impl<T> Outbox<T> {
+    /// Creates an outbox that keeps up to `capacity` frames and lets `window` of them go unacknowledged.
+    #[must_use]
+    pub fn new(capacity: usize, window: u64) -> Self {
+        Self {
+            queue: VecDeque::with_capacity(capacity),
+            capacity,
+            window,
+            sequence: 0,
+            acknowledged: 0,
+        }
+    }
+
+    /// Queues a frame to be sent, or returns it back if the outbox is full.
+    ///
+    /// # Errors
+    ///
+    /// This function will return an error if the outbox already holds `capacity` frames.
+    pub fn push(&mut self, frame: T) -> Result<(), T> {
+        if self.queue.len() >= self.capacity {
+            return Err(frame);
+        }
+        self.queue.push_back(frame);
+        Ok(())
+    }
+
+    /// Whether there's a frame waiting and the window has room for it.
+    #[must_use]
+    pub fn is_ready(&self) -> bool {
+        !self.queue.is_empty() && self.sequence - self.acknowledged < self.window
+    }
+
+    /// Takes the next frame out along with its sequence number, if the window has room for it.
+    pub fn pop(&mut self) -> Option<(u64, T)> {
+        if !self.is_ready() {
+            return None;
+        }
+        let frame = self.queue.pop_front()?;
+        self.sequence += 1;
+        Some((self.sequence, frame))
+    }
+
+    /// Marks every frame up to (and including) `sequence` as acknowledged.
+    ///
+    /// Acknowledging frames that haven't been sent yet has no effect past the last sent one.
+    pub fn acknowledge(&mut self, sequence: u64) {
+        self.acknowledged = self.acknowledged.max(sequence.min(self.sequence));
+    }
+
+    /// How many frames are waiting to be sent.
+    #[must_use]
+    pub fn len(&self) -> usize {
+        self.queue.len()
+    }
+
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.queue.is_empty()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Outbox;
+    use tokio::sync::broadcast;
+
+    #[test]
+    fn window_holds_frames_back() {
+        let mut outbox = Outbox::new(8, 2);
+        for frame in 0..4 {
+            assert!(outbox.push(frame).is_ok());
+        }
+
+        assert_eq!(outbox.pop(), Some((1, 0)));
+        assert_eq!(outbox.pop(), Some((2, 1)));
+        assert_eq!(outbox.pop(), None);
+
+        // Acknowledging frames that were never sent doesn't open the window any further.
+        outbox.acknowledge(10);
+        assert_eq!(outbox.pop(), Some((3, 2)));
+        assert_eq!(outbox.pop(), Some((4, 3)));
+        assert_eq!(outbox.pop(), None);
+        assert!(outbox.is_empty());
+    }
+
+    #[test]
+    fn full_outbox_refuses_frames() {
+        let mut outbox = Outbox::new(2, 1);
+        assert!(outbox.push(1).is_ok());
+        assert!(outbox.push(2).is_ok());
+        assert_eq!(outbox.push(3), Err(3));
+
+        assert_eq!(outbox.pop(), Some((1, 1)));
+        assert!(outbox.push(3).is_ok());
+        assert_eq!(outbox.len(), 2);
+    }
+
+    /// A connection whose client stops acknowledging keeps draining the broadcast receiver
+    /// into its outbox, so it doesn't lag behind even with a tiny broadcast channel.
+    #[tokio::test]
+    async fn unacknowledged_connection_keeps_draining() {
+        const BROADCAST_CAPACITY: usize = 16;
+        const WINDOW: u64 = 64;
+        const EVENTS: usize = 200;
+
+        let (event_tx, mut event_rx) = broadcast::channel(BROADCAST_CAPACITY);
+        let mut outbox = Outbox::new(1024, WINDOW);
+        let mut sent = Vec::new();
+
+        for event in 0..EVENTS {
+            event_tx
+                .send(event)
+                .expect("The receiver should still be there");
+            let event = event_rx.recv().await.expect("The receiver should not lag");
+            assert!(outbox.push(event).is_ok());
+            while let Some((sequence, _)) = outbox.pop() {
+                sent.push(sequence);
+            }
+        }
+
+        // Nothing was acknowledged, so only the window went out, and the rest is still queued.
+        assert_eq!(sent.len() as u64, WINDOW);
+        assert_eq!(outbox.len(), EVENTS - sent.len());
+
+        outbox.acknowledge(WINDOW);
+        assert_eq!(outbox.pop().map(|(sequence, _)| sequence), Some(WINDOW + 1));
+    }
+}
@@ -0,0 +1,123 @@
+use super::{Message, Room, User};
+use crate::proto;
+use diesel::prelude::*;
+use std::time::{Duration, SystemTime};
+use uuid::Uuid;
+
+#[derive(Queryable, Identifiable, Selectable, Insertable, Associations, Debug, Clone)]
+#[diesel(table_name = crate::entities::schema::attachments)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(belongs_to(User, foreign_key = uploader_uuid))]
+#[diesel(belongs_to(Room, foreign_key = room_uuid))]
+#[diesel(belongs_to(Message, foreign_key = message_uuid))]
+#[diesel(primary_key(uuid))]
+pub struct Attachment {
+    pub uuid: Uuid,
+    pub uploader_uuid: Uuid,
+    pub room_uuid: Uuid,
+    /// The message the attachment was sent with, if it was sent at all.
+    pub message_uuid: Option<Uuid>,
+    pub file_name: String,
+    pub content_type: String,
+    pub size: i64,
+    pub blake3_hash: String,
+    pub uploaded_at: SystemTime,
+}
+
+impl Attachment {
+    /// The maximum size of an attachment in bytes.
+    pub const MAX_SIZE: u64 = 16 * 1024 * 1024;
+    /// How many attachments a single message may carry.
+    pub const MAX_PER_MESSAGE: usize = 10;
+    /// The size of the data chunks attachments are downloaded in.
+    pub const CHUNK_SIZE: usize = 64 * 1024;
+    /// The maximum length of a file name in bytes (matches the database column).
+    pub const MAX_FILE_NAME_LENGTH: usize = 255;
+    /// The maximum length of a content type in bytes (matches the database column).
+    pub const MAX_CONTENT_TYPE_LENGTH: usize = 127;
+    pub const DEFAULT_CONTENT_TYPE: &'static str = "application/octet-stream";
+    /// How long an attachment may stay unsent before it's deleted.
+    pub const UNSENT_LIFETIME: Duration = Duration::from_secs(24 * 60 * 60);
+    /// How many bytes of unsent attachments a single user may have at once.
+    pub const MAX_UNSENT_SIZE: u64 = 4 * Self::MAX_SIZE;
+
+    /// Ensure the file name is something that can be shown to other users.
+    ///
+    /// Attachments are stored under their UUID, so the name is never used as a path,
+    /// but clients will most likely save files under it.
+    pub fn validate_file_name(file_name: &str) -> Result<(), &'static str> {
+        if file_name.trim().is_empty() {
+            return Err("The file name can't be empty");
+        }
+        if file_name.len() > Self::MAX_FILE_NAME_LENGTH {
+            return Err("The file name is too long");
+        }
+        if file_name
+            .chars()
+            .any(|c| c.is_control() || c == '/' || c == '\\')
+        {
+            return Err("The file name can't contain path separators or control characters");
+        }
+        if file_name == "." || file_name == ".." {
+            return Err("The file name can't be a relative path");
+        }
+
+        Ok(())
+    }
+
+    pub fn validate_content_type(content_type: &str) -> Result<(), &'static str> {
+        if content_type.len() > Self::MAX_CONTENT_TYPE_LENGTH {
+            return Err("The content type is too long");
+        }
+        if content_type.chars().any(char::is_control) {
+            return Err("The content type can't contain control characters");
+        }
+
+        Ok(())
+    }
+}
+
+impl From<Attachment> for proto::Attachment {
+    fn from(attachment: Attachment) -> Self {
+        Self {
+            uuid: Some(attachment.uuid.into()),
+            uploader_uuid: Some(attachment.uploader_uuid.into()),
+            room_uuid: Some(attachment.room_uuid.into()),
+            file_name: attachment.file_name,
+            content_type: attachment.content_type,
+            size: u64::try_from(attachment.size).unwrap_or_default(),
+            blake3_hash: attachment.blake3_hash,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Attachment;
+
+    #[test]
+    fn valid_file_names() {
+        assert!(Attachment::validate_file_name("report.pdf").is_ok());
+        assert!(Attachment::validate_file_name("фото с отпуска.jpg").is_ok());
+        assert!(Attachment::validate_file_name(".bashrc").is_ok());
+    }
+
+    #[test]
+    fn invalid_file_names() {
+        assert!(Attachment::validate_file_name("").is_err());
+        assert!(Attachment::validate_file_name("   ").is_err());
+        assert!(Attachment::validate_file_name("..").is_err());
+        assert!(Attachment::validate_file_name("../../etc/passwd").is_err());
+        assert!(Attachment::validate_file_name("C:\\evil.exe").is_err());
+        assert!(Attachment::validate_file_name("line\nbreak").is_err());
+        assert!(Attachment::validate_file_name(&"a".repeat(256)).is_err());
+    }
+
+    #[test]
+    fn content_types() {
+        assert!(Attachment::validate_content_type("image/png").is_ok());
+        assert!(Attachment::validate_content_type("").is_ok());
+        assert!(Attachment::validate_content_type("text/plain; charset=utf-8").is_ok());
+        assert!(Attachment::validate_content_type("image/png\r\nX-Evil: 1").is_err());
+    }
+}
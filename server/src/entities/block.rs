@@ -0,0 +1,124 @@
+use crate::proto::serverside_room_event::Event as RoomEvent;
+use crate::proto::serverside_user_event::Event as UserEvent;
+use crate::proto::{ServersideRoomEvent, ServersideUserEvent};
+use diesel::prelude::*;
+use std::time::SystemTime;
+use uuid::Uuid;
+
+/// One user hiding another one's activity from themselves.
+///
+/// Blocks only go one way: the blocked user still sees everything the blocker does.
+#[derive(Queryable, Identifiable, Selectable, Insertable, Debug, Clone)]
+#[diesel(table_name = crate::entities::schema::user_blocks)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(primary_key(blocker_uuid, blocked_uuid))]
+pub struct UserBlock {
+    pub blocker_uuid: Uuid,
+    pub blocked_uuid: Uuid,
+    pub blocked_at: SystemTime,
+}
+
+impl UserBlock {
+    pub fn new(blocker_uuid: Uuid, blocked_uuid: Uuid) -> Self {
+        Self {
+            blocker_uuid,
+            blocked_uuid,
+            blocked_at: SystemTime::now(),
+        }
+    }
+
+    /// The user whose activity a room event is about, if blocking them should hide it.
+    ///
+    /// Membership changes and room updates are never hidden, so that everyone
+    /// agrees on who is in the room and what it's called.
+    pub fn room_event_author(event: &ServersideRoomEvent) -> Option<Uuid> {
+        let author = match event.event.as_ref()? {
+            RoomEvent::NewMessage(message)
+            | RoomEvent::MessageEdited(message)
+            | RoomEvent::MessageDeleted(message) => message.sender_uuid.clone(),
+            RoomEvent::ReactionAdded(reaction) | RoomEvent::ReactionRemoved(reaction) => {
+                reaction.user_uuid.clone()
+            }
+            RoomEvent::UserTyping(typing) => typing.user_uuid.clone(),
+            RoomEvent::UserJoined(_) | RoomEvent::UserLeft(_) | RoomEvent::RoomUpdated(_) => None,
+        };
+        author.and_then(|u| u.try_into().ok())
+    }
+
+    /// The user a personal event is about, if blocking them should hide it.
+    pub fn user_event_author(event: &ServersideUserEvent) -> Option<Uuid> {
+        let author = match event.event.as_ref()? {
+            UserEvent::PresenceChanged(presence) => presence.user_uuid.clone(),
+            UserEvent::ProfileUpdated(profile) => profile.user.clone()?.uuid,
+            UserEvent::Mentioned(message) => message.sender_uuid.clone(),
+            UserEvent::AddedToRoom(_)
+            | UserEvent::KickedFromRoom(_)
+            | UserEvent::NotificationsChanged(_) => None,
+        };
+        author.and_then(|u| u.try_into().ok())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::UserBlock;
+    use crate::proto::serverside_room_event::Event as RoomEvent;
+    use crate::proto::serverside_user_event::Event as UserEvent;
+    use crate::proto::{self, ServersideRoomEvent, ServersideUserEvent};
+    use uuid::Uuid;
+
+    fn room_event(event: RoomEvent) -> ServersideRoomEvent {
+        ServersideRoomEvent {
+            room_uuid: Some(Uuid::new_v4().into()),
+            event: Some(event),
+        }
+    }
+
+    #[test]
+    fn room_event_authors() {
+        let author = Uuid::new_v4();
+        let typing = RoomEvent::UserTyping(proto::UserTyping {
+            user_uuid: Some(author.into()),
+            typing: true,
+        });
+        let reaction = RoomEvent::ReactionAdded(proto::Reaction {
+            message_uuid: Some(Uuid::new_v4().into()),
+            user_uuid: Some(author.into()),
+            emoji: "👍".to_string(),
+        });
+        let joined = RoomEvent::UserJoined(proto::User {
+            uuid: Some(author.into()),
+            username: "alice".to_string(),
+        });
+
+        assert_eq!(
+            UserBlock::room_event_author(&room_event(typing)),
+            Some(author)
+        );
+        assert_eq!(
+            UserBlock::room_event_author(&room_event(reaction)),
+            Some(author)
+        );
+        assert_eq!(UserBlock::room_event_author(&room_event(joined)), None);
+    }
+
+    #[test]
+    fn user_event_authors() {
+        let author = Uuid::new_v4();
+        let presence = ServersideUserEvent {
+            user_uuid: Some(Uuid::new_v4().into()),
+            event: Some(UserEvent::PresenceChanged(proto::Presence {
+                user_uuid: Some(author.into()),
+                online: true,
+                last_seen: None,
+            })),
+        };
+        let added = ServersideUserEvent {
+            user_uuid: Some(Uuid::new_v4().into()),
+            event: Some(UserEvent::AddedToRoom(Uuid::new_v4().into())),
+        };
+
+        assert_eq!(UserBlock::user_event_author(&presence), Some(author));
+        assert_eq!(UserBlock::user_event_author(&added), None);
+    }
+}
@@ -0,0 +1,91 @@
+use super::{Message, User};
+use diesel::prelude::*;
+use itertools::Itertools;
+use uuid::Uuid;
+
+/// A user being called out with an `@username` in a message.
+#[derive(Queryable, Identifiable, Selectable, Insertable, Associations, Debug, Clone)]
+#[diesel(table_name = crate::entities::schema::message_mentions)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(belongs_to(Message, foreign_key = message_uuid))]
+#[diesel(belongs_to(User, foreign_key = user_uuid))]
+#[diesel(primary_key(message_uuid, user_uuid))]
+pub struct Mention {
+    pub message_uuid: Uuid,
+    pub user_uuid: Uuid,
+}
+
+impl Mention {
+    /// How many different users a single message can mention; the rest are ignored.
+    pub const MAX_PER_MESSAGE: usize = 20;
+
+    pub const fn new(message_uuid: Uuid, user_uuid: Uuid) -> Self {
+        Self {
+            message_uuid,
+            user_uuid,
+        }
+    }
+
+    /// Find the usernames mentioned in a message's text, in order of appearance.
+    ///
+    /// A mention is an `@` followed by letters, digits, `_`, `-` or `.`, so usernames
+    /// with spaces or punctuation in them can't be mentioned. The `@` has to start a
+    /// word, so that email addresses aren't taken for mentions, and a trailing dot is
+    /// assumed to end the sentence rather than the username.
+    pub fn parse_usernames(text: &str) -> Vec<&str> {
+        let is_username_char = |c: char| c.is_alphanumeric() || matches!(c, '_' | '-' | '.');
+
+        let mut usernames = vec![];
+        let mut previous = None;
+        for (index, c) in text.char_indices() {
+            let starts_word = previous.is_none_or(|p: char| !is_username_char(p) && p != '@');
+            previous = Some(c);
+            if c != '@' || !starts_word {
+                continue;
+            }
+
+            let rest = &text[index + 1..];
+            let end = rest.find(|c| !is_username_char(c)).unwrap_or(rest.len());
+            let username = rest[..end].trim_end_matches('.');
+            if !username.is_empty() {
+                usernames.push(username);
+            }
+        }
+
+        usernames
+            .into_iter()
+            .unique()
+            .take(Self::MAX_PER_MESSAGE)
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Mention;
+    use itertools::Itertools;
+
+    #[test]
+    fn mentioned_usernames() {
+        assert_eq!(
+            Mention::parse_usernames("@alice, have you seen @bob.smith?"),
+            ["alice", "bob.smith"]
+        );
+        assert_eq!(
+            Mention::parse_usernames("Ask @Алиса. Or (@bob_2)... @alice"),
+            ["Алиса", "bob_2", "alice"]
+        );
+        assert!(Mention::parse_usernames("").is_empty());
+        assert!(Mention::parse_usernames("Mail bob@example.com or @ me, @@alice").is_empty());
+    }
+
+    #[test]
+    fn mention_limit() {
+        let text = (0..=Mention::MAX_PER_MESSAGE)
+            .map(|i| format!("@user{i}"))
+            .join(" ");
+        let usernames = Mention::parse_usernames(&text);
+        assert_eq!(usernames.len(), Mention::MAX_PER_MESSAGE);
+        assert_eq!(usernames[0], "user0");
+    }
+}
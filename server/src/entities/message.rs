@@ -1,12 +1,23 @@
 use super::{ConversionError, Room, User};
 use crate::auth::Authenticator;
-use crate::proto::{ClientsideMessage, ServersideMessage};
+use crate::proto::{self, ClientsideMessage, ServersideMessage};
 use diesel::prelude::*;
 use std::{fmt, str::FromStr, time::SystemTime};
 use tonic::{Request, Status};
 use uuid::Uuid;
 
-#[derive(Queryable, Identifiable, Selectable, Insertable, Debug, Clone, Associations)]
+#[derive(
+    Queryable,
+    QueryableByName,
+    Identifiable,
+    Selectable,
+    Insertable,
+    Debug,
+    Clone,
+    PartialEq,
+    Eq,
+    Associations,
+)]
 #[diesel(table_name = crate::entities::schema::messages)]
 #[diesel(check_for_backend(diesel::pg::Pg))]
 #[diesel(belongs_to(User, foreign_key = sender_uuid))]
@@ -18,6 +29,9 @@ pub struct Message {
     pub room_uuid: Uuid,
     pub text: String,
     pub timestamp: SystemTime,
+    pub edited_at: Option<SystemTime>,
+    pub deleted_at: Option<SystemTime>,
+    pub reply_to: Option<Uuid>,
 }
 
 impl Message {
@@ -28,6 +42,9 @@ impl Message {
             room_uuid,
             text: text.into(),
             timestamp: SystemTime::now(),
+            edited_at: None,
+            deleted_at: None,
+            reply_to: None,
         }
     }
 
@@ -44,8 +61,19 @@ impl Message {
                 .ok_or(ConversionError::MissingField)?,
             text: msg.text,
             timestamp: SystemTime::now(),
+            edited_at: None,
+            deleted_at: None,
+            reply_to: msg
+                .reply_to
+                .map(Uuid::try_from)
+                .transpose()
+                .map_err(|_| ConversionError::InvalidField)?,
         })
     }
+
+    pub const fn is_deleted(&self) -> bool {
+        self.deleted_at.is_some()
+    }
 }
 
 impl fmt::Display for Message {
@@ -75,12 +103,103 @@ impl TryFrom<Request<ClientsideMessage>> for Message {
 
 impl From<Message> for ServersideMessage {
     fn from(msg: Message) -> Self {
+        // Tombstones are wiped in the database, but never let redacted text slip through anyway.
+        let text = if msg.is_deleted() {
+            String::new()
+        } else {
+            msg.text
+        };
+
         Self {
             uuid: Some(msg.uuid.into()),
             sender_uuid: Some(msg.sender_uuid.into()),
             room_uuid: Some(msg.room_uuid.into()),
-            text: msg.text,
+            text,
             timestamp: Some(msg.timestamp.into()),
+            edited_at: msg.edited_at.map(|t| t.into()),
+            deleted_at: msg.deleted_at.map(|t| t.into()),
+            reactions: vec![],
+            reply_to: msg.reply_to.map(|u| u.into()),
+            reply_count: 0,
+            attachments: vec![],
         }
     }
 }
+
+impl TryFrom<ServersideMessage> for Message {
+    type Error = ConversionError;
+
+    fn try_from(msg: ServersideMessage) -> Result<Self, Self::Error> {
+        let parse_uuid = |proto_uuid: Option<proto::Uuid>| -> Result<Uuid, ConversionError> {
+            proto_uuid
+                .ok_or(ConversionError::MissingField)?
+                .try_into()
+                .map_err(|_| ConversionError::InvalidField)
+        };
+
+        Ok(Self {
+            uuid: parse_uuid(msg.uuid)?,
+            sender_uuid: parse_uuid(msg.sender_uuid)?,
+            room_uuid: parse_uuid(msg.room_uuid)?,
+            text: msg.text,
+            timestamp: msg
+                .timestamp
+                .ok_or(ConversionError::MissingField)?
+                .try_into()
+                .map_err(|_| ConversionError::InvalidField)?,
+            edited_at: msg
+                .edited_at
+                .map(SystemTime::try_from)
+                .transpose()
+                .map_err(|_| ConversionError::InvalidField)?,
+            deleted_at: msg
+                .deleted_at
+                .map(SystemTime::try_from)
+                .transpose()
+                .map_err(|_| ConversionError::InvalidField)?,
+            reply_to: msg
+                .reply_to
+                .map(Uuid::try_from)
+                .transpose()
+                .map_err(|_| ConversionError::InvalidField)?,
+        })
+    }
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::Message;
+    use crate::proto::ServersideMessage;
+    use std::time::SystemTime;
+    use uuid::Uuid;
+
+    #[test]
+    fn conversion_roundtrip() {
+        let mut message = Message::new("hello", Uuid::new_v4(), Uuid::new_v4());
+        message.edited_at = Some(SystemTime::now());
+        message.reply_to = Some(Uuid::new_v4());
+
+        let proto_message: ServersideMessage = message.clone().into();
+        let converted_from_proto: Message = proto_message.try_into().unwrap();
+        assert_eq!(message, converted_from_proto);
+    }
+
+    #[test]
+    fn deleted_message_is_redacted() {
+        let mut message = Message::new("secret", Uuid::new_v4(), Uuid::new_v4());
+        message.deleted_at = Some(SystemTime::now());
+
+        let proto_message: ServersideMessage = message.into();
+        assert!(proto_message.text.is_empty());
+        assert!(proto_message.deleted_at.is_some());
+    }
+
+    #[test]
+    fn missing_field_from_proto() {
+        let message = Message::new("hello", Uuid::new_v4(), Uuid::new_v4());
+        let mut proto_message: ServersideMessage = message.into();
+        proto_message.timestamp = None;
+        assert!(Message::try_from(proto_message).is_err());
+    }
+}
@@ -1,20 +1,40 @@
 pub mod schema;
 
+pub mod attachment;
+pub mod block;
+pub mod mention;
 pub mod message;
+pub mod notification;
+pub mod profile;
+pub mod reaction;
 pub mod relations;
+pub mod role;
 pub mod room;
+pub mod search;
+pub mod session;
 pub mod token;
 pub mod user;
 pub mod uuid;
 
+pub use attachment::Attachment;
+pub use block::UserBlock;
+pub use mention::Mention;
 pub use message::Message;
-pub use relations::RoomUser;
-pub use room::Room;
-pub use token::AuthToken;
+pub use notification::NotificationLevel;
+pub use profile::{Profile, ProfileUpdate};
+pub use reaction::Reaction;
+pub use relations::{ReadMarker, RoomUser};
+pub use role::{RoomAction, RoomRole};
+pub use room::{Room, RoomKind};
+pub use search::{SearchCursor, SearchHit};
+pub use session::Session;
+pub use token::{AuthToken, TokenKey};
 pub use user::User;
 
 #[derive(thiserror::Error, Debug)]
 pub enum ConversionError {
     #[error("The protobuf entity is missing a required field")]
     MissingField,
+    #[error("The protobuf entity has a malformed field")]
+    InvalidField,
 }
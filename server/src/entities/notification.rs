@@ -0,0 +1,136 @@
+use crate::proto;
+use diesel::deserialize::{self, FromSql, FromSqlRow};
+use diesel::expression::AsExpression;
+use diesel::pg::{Pg, PgValue};
+use diesel::serialize::{self, IsNull, Output, ToSql};
+use diesel::sql_types::Text;
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+/// What a user wants to be notified of in a room.
+#[derive(AsExpression, FromSqlRow, Debug, Clone, Copy, PartialEq, Eq, Hash, Default)]
+#[diesel(sql_type = Text)]
+pub enum NotificationLevel {
+    #[default]
+    All,
+    MentionsOnly,
+    /// Nothing at all, either forever or until a certain moment.
+    Muted,
+}
+
+impl NotificationLevel {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::All => "all",
+            Self::MentionsOnly => "mentions",
+            Self::Muted => "muted",
+        }
+    }
+
+    /// The level in effect at a certain moment, given when the room's mute ends (if ever).
+    ///
+    /// Rooms go back to notifying of everything once their mute is over.
+    pub fn at(self, muted_until: Option<SystemTime>, now: SystemTime) -> Self {
+        match (self, muted_until) {
+            (Self::Muted, Some(mute_end)) if mute_end <= now => Self::All,
+            (level, _) => level,
+        }
+    }
+}
+
+impl fmt::Display for NotificationLevel {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for NotificationLevel {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "all" => Ok(Self::All),
+            "mentions" => Ok(Self::MentionsOnly),
+            "muted" => Ok(Self::Muted),
+            _ => Err("Unknown notification level"),
+        }
+    }
+}
+
+impl ToSql<Text, Pg> for NotificationLevel {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Text, Pg> for NotificationLevel {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        let level = <String as FromSql<Text, Pg>>::from_sql(bytes)?;
+        Ok(level.parse()?)
+    }
+}
+
+impl From<NotificationLevel> for proto::NotificationLevel {
+    fn from(level: NotificationLevel) -> Self {
+        match level {
+            NotificationLevel::All => Self::All,
+            NotificationLevel::MentionsOnly => Self::MentionsOnly,
+            NotificationLevel::Muted => Self::Muted,
+        }
+    }
+}
+
+impl From<proto::NotificationLevel> for NotificationLevel {
+    fn from(level: proto::NotificationLevel) -> Self {
+        match level {
+            proto::NotificationLevel::All => Self::All,
+            proto::NotificationLevel::MentionsOnly => Self::MentionsOnly,
+            proto::NotificationLevel::Muted => Self::Muted,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::NotificationLevel;
+    use std::time::{Duration, SystemTime};
+
+    #[test]
+    fn mutes_run_out() {
+        let now = SystemTime::now();
+        let later = now + Duration::from_secs(60);
+        let earlier = now - Duration::from_secs(60);
+
+        assert_eq!(
+            NotificationLevel::Muted.at(None, now),
+            NotificationLevel::Muted
+        );
+        assert_eq!(
+            NotificationLevel::Muted.at(Some(later), now),
+            NotificationLevel::Muted
+        );
+        assert_eq!(
+            NotificationLevel::Muted.at(Some(earlier), now),
+            NotificationLevel::All
+        );
+        assert_eq!(
+            NotificationLevel::MentionsOnly.at(None, now),
+            NotificationLevel::MentionsOnly
+        );
+    }
+
+    #[test]
+    fn string_roundtrip() {
+        for level in [
+            NotificationLevel::All,
+            NotificationLevel::MentionsOnly,
+            NotificationLevel::Muted,
+        ] {
+            assert_eq!(level.as_str().parse(), Ok(level));
+        }
+        assert!("loud".parse::<NotificationLevel>().is_err());
+    }
+}
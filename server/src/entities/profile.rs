@@ -0,0 +1,202 @@
+use super::User;
+use crate::proto;
+use diesel::prelude::*;
+use std::time::SystemTime;
+use uuid::Uuid;
+
+/// The optional details a user tells about themselves. Every field may be empty.
+#[derive(
+    Queryable, Selectable, Insertable, AsChangeset, Identifiable, Associations, Clone, Debug,
+)]
+#[diesel(table_name = crate::entities::schema::profiles)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(belongs_to(User, foreign_key = user_uuid))]
+#[diesel(primary_key(user_uuid))]
+pub struct Profile {
+    pub user_uuid: Uuid,
+    pub display_name: String,
+    pub bio: String,
+    pub status_text: String,
+    pub status_emoji: String,
+    pub pronouns: String,
+    /// An IANA time zone name, like `Europe/Moscow`.
+    pub timezone: String,
+    pub updated_at: SystemTime,
+}
+
+/// A partial change to a profile, touching only the fields that are set.
+///
+/// Inserted as is, the fields that aren't set take their defaults, so it's also a valid new profile.
+#[derive(Insertable, AsChangeset, Debug)]
+#[diesel(table_name = crate::entities::schema::profiles)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(primary_key(user_uuid))]
+pub struct ProfileUpdate {
+    pub user_uuid: Uuid,
+    pub display_name: Option<String>,
+    pub bio: Option<String>,
+    pub status_text: Option<String>,
+    pub status_emoji: Option<String>,
+    pub pronouns: Option<String>,
+    pub timezone: Option<String>,
+    pub updated_at: SystemTime,
+}
+
+impl Profile {
+    // The maximum lengths of the fields in characters (they match the database columns).
+    pub const MAX_DISPLAY_NAME_LENGTH: usize = 64;
+    pub const MAX_BIO_LENGTH: usize = 512;
+    pub const MAX_STATUS_TEXT_LENGTH: usize = 128;
+    pub const MAX_STATUS_EMOJI_LENGTH: usize = 32;
+    pub const MAX_PRONOUNS_LENGTH: usize = 32;
+    pub const MAX_TIMEZONE_LENGTH: usize = 64;
+
+    /// The profile of a user that has never filled it in.
+    pub fn empty(user_uuid: Uuid) -> Self {
+        Self {
+            user_uuid,
+            display_name: String::new(),
+            bio: String::new(),
+            status_text: String::new(),
+            status_emoji: String::new(),
+            pronouns: String::new(),
+            timezone: String::new(),
+            updated_at: SystemTime::UNIX_EPOCH,
+        }
+    }
+
+    pub fn validate_display_name(display_name: &str) -> Result<(), &'static str> {
+        if display_name.chars().count() > Self::MAX_DISPLAY_NAME_LENGTH {
+            return Err("The display name is too long");
+        }
+        if display_name.chars().any(char::is_control) {
+            return Err("The display name can't contain control characters");
+        }
+        Ok(())
+    }
+
+    /// Bios may span several lines, unlike the rest of the profile.
+    pub fn validate_bio(bio: &str) -> Result<(), &'static str> {
+        if bio.chars().count() > Self::MAX_BIO_LENGTH {
+            return Err("The bio is too long");
+        }
+        if bio.chars().any(|c| c.is_control() && c != '\n') {
+            return Err("The bio can't contain control characters other than line breaks");
+        }
+        Ok(())
+    }
+
+    pub fn validate_status_text(status_text: &str) -> Result<(), &'static str> {
+        if status_text.chars().count() > Self::MAX_STATUS_TEXT_LENGTH {
+            return Err("The status is too long");
+        }
+        if status_text.chars().any(char::is_control) {
+            return Err("The status can't contain control characters");
+        }
+        Ok(())
+    }
+
+    /// Like reactions, status emojis aren't checked against any particular set of emojis.
+    pub fn validate_status_emoji(status_emoji: &str) -> Result<(), &'static str> {
+        if status_emoji.chars().count() > Self::MAX_STATUS_EMOJI_LENGTH {
+            return Err("The status emoji is too long");
+        }
+        if status_emoji
+            .chars()
+            .any(|c| c.is_whitespace() || c.is_control())
+        {
+            return Err("The status emoji can't contain whitespace or control characters");
+        }
+        Ok(())
+    }
+
+    pub fn validate_pronouns(pronouns: &str) -> Result<(), &'static str> {
+        if pronouns.chars().count() > Self::MAX_PRONOUNS_LENGTH {
+            return Err("The pronouns are too long");
+        }
+        if pronouns.chars().any(char::is_control) {
+            return Err("The pronouns can't contain control characters");
+        }
+        Ok(())
+    }
+
+    /// Ensure a time zone looks like an IANA name (`UTC`, `Europe/Moscow`, `Etc/GMT+3`).
+    ///
+    /// Names aren't checked against the actual database of time zones, as it changes
+    /// over time and clients are the ones that make use of it anyway.
+    pub fn validate_timezone(timezone: &str) -> Result<(), &'static str> {
+        if timezone.is_empty() {
+            return Ok(());
+        }
+        if timezone.len() > Self::MAX_TIMEZONE_LENGTH {
+            return Err("The time zone is too long");
+        }
+        let valid_part = |part: &str| {
+            part.starts_with(|c: char| c.is_ascii_alphabetic())
+                && part
+                    .chars()
+                    .all(|c| c.is_ascii_alphanumeric() || matches!(c, '_' | '-' | '+'))
+        };
+        if !timezone.split('/').all(valid_part) {
+            return Err("The time zone should be an IANA name, like Europe/Moscow");
+        }
+        Ok(())
+    }
+}
+
+/// Profiles are always sent along with the user they belong to.
+impl From<(User, Profile)> for proto::Profile {
+    fn from((user, profile): (User, Profile)) -> Self {
+        Self {
+            user: Some(user.into()),
+            display_name: profile.display_name,
+            bio: profile.bio,
+            status_text: profile.status_text,
+            status_emoji: profile.status_emoji,
+            pronouns: profile.pronouns,
+            timezone: profile.timezone,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Profile;
+
+    #[test]
+    fn valid_profile_fields() {
+        assert!(Profile::validate_display_name("Алиса 🐇").is_ok());
+        assert!(Profile::validate_bio("Line one\nLine two").is_ok());
+        assert!(Profile::validate_status_text("").is_ok());
+        assert!(Profile::validate_status_emoji("🏖️").is_ok());
+        assert!(Profile::validate_pronouns("they/them").is_ok());
+        for timezone in [
+            "",
+            "UTC",
+            "Europe/Moscow",
+            "America/Argentina/Buenos_Aires",
+            "Etc/GMT+3",
+        ] {
+            assert!(Profile::validate_timezone(timezone).is_ok(), "{timezone}");
+        }
+    }
+
+    #[test]
+    fn invalid_profile_fields() {
+        let too_long = "ж".repeat(Profile::MAX_BIO_LENGTH + 1);
+        assert!(Profile::validate_display_name(&too_long).is_err());
+        assert!(Profile::validate_display_name("Alice\n").is_err());
+        assert!(Profile::validate_bio(&too_long).is_err());
+        assert!(Profile::validate_bio("\u{7}").is_err());
+        assert!(Profile::validate_status_emoji("🏖️ 🏖️").is_err());
+        for timezone in [
+            "Europe/",
+            "/UTC",
+            "Europe//Moscow",
+            "Europe/Moscow\n",
+            "+03:00",
+        ] {
+            assert!(Profile::validate_timezone(timezone).is_err(), "{timezone}");
+        }
+    }
+}
@@ -0,0 +1,76 @@
+use super::{Message, User};
+use crate::proto;
+use diesel::prelude::*;
+use uuid::Uuid;
+
+#[derive(Queryable, Identifiable, Selectable, Insertable, Associations, Debug, Clone)]
+#[diesel(table_name = crate::entities::schema::message_reactions)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(belongs_to(Message, foreign_key = message_uuid))]
+#[diesel(belongs_to(User, foreign_key = user_uuid))]
+#[diesel(primary_key(message_uuid, user_uuid, emoji))]
+pub struct Reaction {
+    pub message_uuid: Uuid,
+    pub user_uuid: Uuid,
+    pub emoji: String,
+}
+
+impl Reaction {
+    /// The maximum length of a reaction in bytes (matches the database column).
+    pub const MAX_EMOJI_LENGTH: usize = 32;
+
+    /// Ensure the emoji is something that can be rendered as a reaction.
+    ///
+    /// Emojis are not checked against any particular set (there's way too many of
+    /// them, and clients may render them differently anyway), only against being
+    /// empty, too long, or containing whitespace and control characters.
+    pub fn validate_emoji(emoji: &str) -> Result<(), &'static str> {
+        if emoji.is_empty() {
+            return Err("The reaction can't be empty");
+        }
+        if emoji.len() > Self::MAX_EMOJI_LENGTH {
+            return Err("The reaction is too long");
+        }
+        if emoji.chars().any(|c| c.is_whitespace() || c.is_control()) {
+            return Err("The reaction can't contain whitespace or control characters");
+        }
+
+        Ok(())
+    }
+}
+
+impl From<Reaction> for proto::Reaction {
+    fn from(reaction: Reaction) -> Self {
+        Self {
+            message_uuid: Some(reaction.message_uuid.into()),
+            user_uuid: Some(reaction.user_uuid.into()),
+            emoji: reaction.emoji,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Reaction;
+
+    #[test]
+    fn valid_emojis() {
+        for emoji in ["👍", "🎉", "❤️", "👨‍👩‍👧‍👦", ":+1:"] {
+            assert!(
+                Reaction::validate_emoji(emoji).is_ok(),
+                "{emoji} should be valid"
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_emojis() {
+        let too_long = "👍".repeat(Reaction::MAX_EMOJI_LENGTH);
+        for emoji in ["", " ", "👍 👍", "\n", too_long.as_str()] {
+            assert!(
+                Reaction::validate_emoji(emoji).is_err(),
+                "{emoji:?} should be invalid"
+            );
+        }
+    }
+}
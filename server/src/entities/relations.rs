@@ -1,5 +1,6 @@
-use super::{Room, User};
+use super::{Message, NotificationLevel, Room, RoomRole, User};
 use diesel::prelude::*;
+use std::time::SystemTime;
 use uuid::Uuid;
 
 #[derive(Queryable, Identifiable, Selectable, Insertable, Associations, Debug, Clone)]
@@ -11,4 +12,39 @@ use uuid::Uuid;
 pub struct RoomUser {
     pub room_uuid: Uuid,
     pub user_uuid: Uuid,
+    pub role: RoomRole,
+    pub notification_level: NotificationLevel,
+    /// When a muted room starts notifying again. Muted rooms without one stay muted.
+    pub muted_until: Option<SystemTime>,
+}
+
+impl RoomUser {
+    pub const fn new(room_uuid: Uuid, user_uuid: Uuid, role: RoomRole) -> Self {
+        Self {
+            room_uuid,
+            user_uuid,
+            role,
+            notification_level: NotificationLevel::All,
+            muted_until: None,
+        }
+    }
+
+    /// The notification level the member has in effect at a certain moment.
+    pub fn notification_level_at(&self, now: SystemTime) -> NotificationLevel {
+        self.notification_level.at(self.muted_until, now)
+    }
+}
+
+/// The last message a user has read in a room.
+#[derive(Queryable, Identifiable, Selectable, Insertable, Associations, Debug, Clone)]
+#[diesel(table_name = crate::entities::schema::read_markers)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(belongs_to(User, foreign_key = user_uuid))]
+#[diesel(belongs_to(Room, foreign_key = room_uuid))]
+#[diesel(belongs_to(Message, foreign_key = message_uuid))]
+#[diesel(primary_key(user_uuid, room_uuid))]
+pub struct ReadMarker {
+    pub user_uuid: Uuid,
+    pub room_uuid: Uuid,
+    pub message_uuid: Uuid,
 }
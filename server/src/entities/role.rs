@@ -0,0 +1,139 @@
+use crate::proto;
+use diesel::deserialize::{self, FromSql, FromSqlRow};
+use diesel::expression::AsExpression;
+use diesel::pg::{Pg, PgValue};
+use diesel::serialize::{self, IsNull, Output, ToSql};
+use diesel::sql_types::Text;
+use std::fmt;
+use std::io::Write;
+use std::str::FromStr;
+
+/// The role of a user in a room, from the least to the most privileged one.
+#[derive(AsExpression, FromSqlRow, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
+#[diesel(sql_type = Text)]
+pub enum RoomRole {
+    Member,
+    Admin,
+    Owner,
+}
+
+/// Something a user may want to do in a room, that not every member is allowed to.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RoomAction {
+    /// Read the room's history, subscribe to it, or leave it.
+    View,
+    /// Send, edit and delete one's own messages, react to messages, and so on.
+    Post,
+    Invite,
+    Kick,
+    /// Change the room's name, topic or description.
+    UpdateRoom,
+    DeleteAnyMessage,
+    DeleteRoom,
+    ManageRoles,
+}
+
+impl RoomAction {
+    /// The least privileged role that is allowed to perform the action.
+    pub const fn required_role(self) -> RoomRole {
+        match self {
+            Self::View | Self::Post => RoomRole::Member,
+            Self::Invite | Self::Kick | Self::UpdateRoom | Self::DeleteAnyMessage => {
+                RoomRole::Admin
+            }
+            Self::DeleteRoom | Self::ManageRoles => RoomRole::Owner,
+        }
+    }
+}
+
+impl RoomRole {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Member => "member",
+            Self::Admin => "admin",
+            Self::Owner => "owner",
+        }
+    }
+
+    pub fn can(self, action: RoomAction) -> bool {
+        self >= action.required_role()
+    }
+}
+
+impl fmt::Display for RoomRole {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+impl FromStr for RoomRole {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "member" => Ok(Self::Member),
+            "admin" => Ok(Self::Admin),
+            "owner" => Ok(Self::Owner),
+            _ => Err("Unknown room role"),
+        }
+    }
+}
+
+impl ToSql<Text, Pg> for RoomRole {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Text, Pg> for RoomRole {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        let role = <String as FromSql<Text, Pg>>::from_sql(bytes)?;
+        Ok(role.parse()?)
+    }
+}
+
+impl From<RoomRole> for proto::RoomRole {
+    fn from(role: RoomRole) -> Self {
+        match role {
+            RoomRole::Member => Self::Member,
+            RoomRole::Admin => Self::Admin,
+            RoomRole::Owner => Self::Owner,
+        }
+    }
+}
+
+impl From<proto::RoomRole> for RoomRole {
+    fn from(role: proto::RoomRole) -> Self {
+        match role {
+            proto::RoomRole::Member => Self::Member,
+            proto::RoomRole::Admin => Self::Admin,
+            proto::RoomRole::Owner => Self::Owner,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{RoomAction, RoomRole};
+
+    #[test]
+    fn permissions() {
+        assert!(RoomRole::Member.can(RoomAction::Post));
+        assert!(!RoomRole::Member.can(RoomAction::Invite));
+        assert!(!RoomRole::Member.can(RoomAction::DeleteAnyMessage));
+        assert!(RoomRole::Admin.can(RoomAction::Kick));
+        assert!(!RoomRole::Admin.can(RoomAction::DeleteRoom));
+        assert!(!RoomRole::Admin.can(RoomAction::ManageRoles));
+        assert!(RoomRole::Owner.can(RoomAction::DeleteRoom));
+        assert!(RoomRole::Owner.can(RoomAction::View));
+    }
+
+    #[test]
+    fn string_roundtrip() {
+        for role in [RoomRole::Member, RoomRole::Admin, RoomRole::Owner] {
+            assert_eq!(role.as_str().parse(), Ok(role));
+        }
+        assert!("moderator".parse::<RoomRole>().is_err());
+    }
+}
@@ -1,8 +1,14 @@
 use crate::persistence::Connection;
-use crate::proto::ServersideRoom;
+use crate::proto::{self, RoomMetadata, ServersideRoom};
+use diesel::deserialize::{self, FromSql, FromSqlRow};
+use diesel::expression::AsExpression;
+use diesel::pg::{Pg, PgValue};
 use diesel::prelude::*;
 use diesel::r2d2::{ConnectionManager, PooledConnection};
+use diesel::serialize::{self, IsNull, Output, ToSql};
+use diesel::sql_types::Text;
 use std::fmt;
+use std::io::Write;
 use uuid::Uuid;
 
 #[derive(Queryable, Identifiable, Selectable, Insertable, Debug, Clone)]
@@ -12,16 +18,66 @@ use uuid::Uuid;
 pub struct Room {
     pub uuid: Uuid,
     pub name: String,
+    pub topic: String,
+    pub description: String,
+    pub kind: RoomKind,
+}
+
+/// Whether a room is an ordinary group chat, or a private chat between two users.
+#[derive(AsExpression, FromSqlRow, Debug, Clone, Copy, PartialEq, Eq, Hash)]
+#[diesel(sql_type = Text)]
+pub enum RoomKind {
+    Group,
+    Direct,
 }
 
 impl Room {
+    pub const MAX_NAME_LENGTH: usize = 64;
+    pub const MAX_TOPIC_LENGTH: usize = 256;
+    pub const MAX_DESCRIPTION_LENGTH: usize = 4096;
+
     pub fn new<T: Into<String>>(name: T) -> Self {
         Self {
             uuid: Uuid::new_v4(),
             name: name.into(),
+            topic: String::new(),
+            description: String::new(),
+            kind: RoomKind::Group,
         }
     }
 
+    /// Order a pair of users the way direct rooms store it, so that each pair has only one room.
+    pub fn direct_pair(user: Uuid, other_user: Uuid) -> (Uuid, Uuid) {
+        (user.min(other_user), user.max(other_user))
+    }
+
+    /// Ensure a room name is not blank and fits into the database.
+    pub fn validate_name(name: &str) -> Result<(), &'static str> {
+        if name.trim().is_empty() {
+            return Err("The room name can't be empty");
+        }
+        if name.chars().count() > Self::MAX_NAME_LENGTH {
+            return Err("The room name is too long");
+        }
+        Ok(())
+    }
+
+    /// Ensure a room topic fits into the database. Topics may be empty.
+    pub fn validate_topic(topic: &str) -> Result<(), &'static str> {
+        if topic.chars().count() > Self::MAX_TOPIC_LENGTH {
+            return Err("The room topic is too long");
+        }
+        Ok(())
+    }
+
+    /// Ensure a room description isn't unreasonably long. Descriptions may be empty.
+    pub fn validate_description(description: &str) -> Result<(), &'static str> {
+        if description.chars().count() > Self::MAX_DESCRIPTION_LENGTH {
+            return Err("The room description is too long");
+        }
+        Ok(())
+    }
+
     pub async fn get_members(
         &self,
         db_connection: &mut PooledConnection<ConnectionManager<Connection>>,
@@ -42,6 +98,60 @@ impl Room {
     }
 }
 
+impl RoomKind {
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::Group => "group",
+            Self::Direct => "direct",
+        }
+    }
+}
+
+impl ToSql<Text, Pg> for RoomKind {
+    fn to_sql<'b>(&'b self, out: &mut Output<'b, '_, Pg>) -> serialize::Result {
+        out.write_all(self.as_str().as_bytes())?;
+        Ok(IsNull::No)
+    }
+}
+
+impl FromSql<Text, Pg> for RoomKind {
+    fn from_sql(bytes: PgValue<'_>) -> deserialize::Result<Self> {
+        match <String as FromSql<Text, Pg>>::from_sql(bytes)?.as_str() {
+            "group" => Ok(Self::Group),
+            "direct" => Ok(Self::Direct),
+            _ => Err("Unknown room kind".into()),
+        }
+    }
+}
+
+impl From<RoomKind> for proto::RoomKind {
+    fn from(kind: RoomKind) -> Self {
+        match kind {
+            RoomKind::Group => Self::Group,
+            RoomKind::Direct => Self::Direct,
+        }
+    }
+}
+
+impl From<proto::RoomKind> for RoomKind {
+    fn from(kind: proto::RoomKind) -> Self {
+        match kind {
+            proto::RoomKind::Group => Self::Group,
+            proto::RoomKind::Direct => Self::Direct,
+        }
+    }
+}
+
+impl From<Room> for RoomMetadata {
+    fn from(room: Room) -> Self {
+        Self {
+            name: room.name,
+            topic: room.topic,
+            description: room.description,
+        }
+    }
+}
+
 impl fmt::Display for ServersideRoom {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         write!(
@@ -53,3 +163,34 @@ impl fmt::Display for ServersideRoom {
         Ok(())
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Room;
+    use uuid::Uuid;
+
+    #[test]
+    fn valid_metadata() {
+        assert!(Room::validate_name("general").is_ok());
+        assert!(Room::validate_name(&"й".repeat(Room::MAX_NAME_LENGTH)).is_ok());
+        assert!(Room::validate_topic("").is_ok());
+        assert!(Room::validate_description("").is_ok());
+    }
+
+    #[test]
+    fn direct_pair_is_unordered() {
+        let (a, b) = (Uuid::new_v4(), Uuid::new_v4());
+        assert_eq!(Room::direct_pair(a, b), Room::direct_pair(b, a));
+        let (low, high) = Room::direct_pair(a, b);
+        assert!(low < high);
+    }
+
+    #[test]
+    fn invalid_metadata() {
+        assert!(Room::validate_name("").is_err());
+        assert!(Room::validate_name("   ").is_err());
+        assert!(Room::validate_name(&"a".repeat(Room::MAX_NAME_LENGTH + 1)).is_err());
+        assert!(Room::validate_topic(&"a".repeat(Room::MAX_TOPIC_LENGTH + 1)).is_err());
+        assert!(Room::validate_description(&"a".repeat(Room::MAX_DESCRIPTION_LENGTH + 1)).is_err());
+    }
+}
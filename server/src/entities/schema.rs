@@ -1,12 +1,85 @@
 // @generated automatically by Diesel CLI.
 
+pub mod sql_types {
+    #[derive(diesel::query_builder::QueryId, diesel::sql_types::SqlType)]
+    #[diesel(postgres_type(name = "tsvector", schema = "pg_catalog"))]
+    pub struct Tsvector;
+}
+
 diesel::table! {
+    attachments (uuid) {
+        uuid -> Uuid,
+        uploader_uuid -> Uuid,
+        room_uuid -> Uuid,
+        message_uuid -> Nullable<Uuid>,
+        #[max_length = 255]
+        file_name -> Varchar,
+        #[max_length = 127]
+        content_type -> Varchar,
+        size -> Int8,
+        #[max_length = 64]
+        blake3_hash -> Bpchar,
+        uploaded_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    message_mentions (message_uuid, user_uuid) {
+        message_uuid -> Uuid,
+        user_uuid -> Uuid,
+    }
+}
+
+diesel::table! {
+    message_reactions (message_uuid, user_uuid, emoji) {
+        message_uuid -> Uuid,
+        user_uuid -> Uuid,
+        #[max_length = 32]
+        emoji -> Varchar,
+    }
+}
+
+diesel::table! {
+    use diesel::sql_types::*;
+    use super::sql_types::Tsvector;
+
     messages (uuid) {
         uuid -> Uuid,
         sender_uuid -> Uuid,
         room_uuid -> Uuid,
         text -> Text,
         timestamp -> Timestamp,
+        edited_at -> Nullable<Timestamp>,
+        deleted_at -> Nullable<Timestamp>,
+        reply_to -> Nullable<Uuid>,
+        search_vector -> Nullable<Tsvector>,
+    }
+}
+
+diesel::table! {
+    profiles (user_uuid) {
+        user_uuid -> Uuid,
+        #[max_length = 64]
+        display_name -> Varchar,
+        #[max_length = 512]
+        bio -> Varchar,
+        #[max_length = 128]
+        status_text -> Varchar,
+        #[max_length = 32]
+        status_emoji -> Varchar,
+        #[max_length = 32]
+        pronouns -> Varchar,
+        #[max_length = 64]
+        timezone -> Varchar,
+        updated_at -> Timestamp,
+    }
+}
+
+diesel::table! {
+    read_markers (user_uuid, room_uuid) {
+        user_uuid -> Uuid,
+        room_uuid -> Uuid,
+        message_uuid -> Uuid,
     }
 }
 
@@ -15,6 +88,13 @@ diesel::table! {
         uuid -> Uuid,
         #[max_length = 64]
         name -> Varchar,
+        #[max_length = 256]
+        topic -> Varchar,
+        description -> Text,
+        #[max_length = 16]
+        kind -> Varchar,
+        direct_user_low -> Nullable<Uuid>,
+        direct_user_high -> Nullable<Uuid>,
     }
 }
 
@@ -22,6 +102,33 @@ diesel::table! {
     rooms_users (room_uuid, user_uuid) {
         room_uuid -> Uuid,
         user_uuid -> Uuid,
+        #[max_length = 16]
+        role -> Varchar,
+        #[max_length = 16]
+        notification_level -> Varchar,
+        muted_until -> Nullable<Timestamp>,
+    }
+}
+
+diesel::table! {
+    sessions (uuid) {
+        uuid -> Uuid,
+        user_uuid -> Uuid,
+        #[max_length = 64]
+        device -> Varchar,
+        created_at -> Timestamp,
+        expires_at -> Timestamp,
+        last_used_at -> Timestamp,
+        #[max_length = 64]
+        token_hash -> Bpchar,
+    }
+}
+
+diesel::table! {
+    user_blocks (blocker_uuid, blocked_uuid) {
+        blocker_uuid -> Uuid,
+        blocked_uuid -> Uuid,
+        blocked_at -> Timestamp,
     }
 }
 
@@ -32,14 +139,37 @@ diesel::table! {
         username -> Varchar,
         #[max_length = 256]
         password -> Varchar,
-        #[max_length = 32]
-        auth_token -> Bpchar,
+        last_seen -> Nullable<Timestamp>,
     }
 }
 
+diesel::joinable!(attachments -> messages (message_uuid));
+diesel::joinable!(attachments -> rooms (room_uuid));
+diesel::joinable!(attachments -> users (uploader_uuid));
+diesel::joinable!(message_mentions -> messages (message_uuid));
+diesel::joinable!(message_mentions -> users (user_uuid));
+diesel::joinable!(message_reactions -> messages (message_uuid));
+diesel::joinable!(message_reactions -> users (user_uuid));
 diesel::joinable!(messages -> rooms (room_uuid));
 diesel::joinable!(messages -> users (sender_uuid));
+diesel::joinable!(profiles -> users (user_uuid));
+diesel::joinable!(read_markers -> messages (message_uuid));
+diesel::joinable!(read_markers -> rooms (room_uuid));
+diesel::joinable!(read_markers -> users (user_uuid));
 diesel::joinable!(rooms_users -> rooms (room_uuid));
 diesel::joinable!(rooms_users -> users (user_uuid));
+diesel::joinable!(sessions -> users (user_uuid));
 
-diesel::allow_tables_to_appear_in_same_query!(messages, rooms, rooms_users, users,);
+diesel::allow_tables_to_appear_in_same_query!(
+    attachments,
+    message_mentions,
+    message_reactions,
+    messages,
+    profiles,
+    read_markers,
+    rooms,
+    rooms_users,
+    sessions,
+    user_blocks,
+    users,
+);
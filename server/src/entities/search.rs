@@ -0,0 +1,92 @@
+use super::Message;
+use diesel::prelude::*;
+use diesel::sql_types::{Float4, Text};
+use std::fmt;
+use std::str::FromStr;
+use uuid::Uuid;
+
+/// A message that matched a full-text search query.
+#[derive(QueryableByName, Debug, Clone)]
+pub struct SearchHit {
+    #[diesel(embed)]
+    pub message: Message,
+    #[diesel(sql_type = Float4)]
+    pub rank: f32,
+    /// The matching parts of the message's text, with the matched words highlighted.
+    #[diesel(sql_type = Text)]
+    pub snippet: String,
+}
+
+/// Where the next page of search results starts: right after the hit with this rank and UUID.
+///
+/// Hits are ordered by their rank and then by their UUID, both descending, so this pair is
+/// enough to continue a search. Clients only ever see it as an opaque page token.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SearchCursor {
+    pub rank: f32,
+    pub message_uuid: Uuid,
+}
+
+impl From<&SearchHit> for SearchCursor {
+    fn from(hit: &SearchHit) -> Self {
+        Self {
+            rank: hit.rank,
+            message_uuid: hit.message.uuid,
+        }
+    }
+}
+
+impl fmt::Display for SearchCursor {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:08x}{}",
+            self.rank.to_bits(),
+            self.message_uuid.simple()
+        )
+    }
+}
+
+impl FromStr for SearchCursor {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const INVALID: &str = "Invalid page token";
+
+        let (rank, message_uuid) = s.split_at_checked(8).ok_or(INVALID)?;
+        let rank = u32::from_str_radix(rank, 16).map_err(|_| INVALID)?;
+        let message_uuid = Uuid::try_parse(message_uuid).map_err(|_| INVALID)?;
+
+        Ok(Self {
+            rank: f32::from_bits(rank),
+            message_uuid,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::SearchCursor;
+    use uuid::Uuid;
+
+    #[test]
+    fn page_token_roundtrip() {
+        let cursor = SearchCursor {
+            rank: 0.060_792_7,
+            message_uuid: Uuid::new_v4(),
+        };
+        assert_eq!(cursor.to_string().parse(), Ok(cursor));
+    }
+
+    #[test]
+    fn invalid_page_tokens() {
+        assert!("".parse::<SearchCursor>().is_err());
+        assert!("3d78fa0e".parse::<SearchCursor>().is_err());
+        assert!("not a page token at all, really"
+            .parse::<SearchCursor>()
+            .is_err());
+        assert!("ж3d78fa0e0b1a1c2d3e4f5061728394a5b6c7d8e9f"
+            .parse::<SearchCursor>()
+            .is_err());
+    }
+}
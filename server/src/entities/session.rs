@@ -0,0 +1,113 @@
+use super::{AuthToken, User};
+use crate::proto::{self, AuthPair};
+use diesel::prelude::*;
+use std::time::{Duration, SystemTime};
+use uuid::Uuid;
+
+/// A single login of a user on some device, identified by its own token.
+///
+/// Only a keyed hash of the token is stored (see [`super::TokenKey`]), the token
+/// itself is only ever known to the client.
+#[derive(Queryable, Selectable, Insertable, Identifiable, Associations, Clone, Debug)]
+#[diesel(table_name = crate::entities::schema::sessions)]
+#[diesel(check_for_backend(diesel::pg::Pg))]
+#[diesel(belongs_to(User, foreign_key = user_uuid))]
+#[diesel(primary_key(uuid))]
+pub struct Session {
+    pub uuid: Uuid,
+    pub user_uuid: Uuid,
+    pub device: String,
+    pub created_at: SystemTime,
+    pub expires_at: SystemTime,
+    pub last_used_at: SystemTime,
+    pub token_hash: String,
+}
+
+impl Session {
+    /// How long a session stays valid after logging in.
+    pub const LIFETIME: Duration = Duration::from_secs(30 * 24 * 60 * 60);
+    /// The maximum length of a device label in characters (matches the database column).
+    pub const MAX_DEVICE_LENGTH: usize = 64;
+    pub const UNKNOWN_DEVICE: &'static str = "Unknown device";
+
+    /// Start a new session. The device label is cleaned up rather than rejected,
+    /// since it's purely informational and shouldn't ever make a login fail.
+    pub fn new(user_uuid: Uuid, device: &str, token_hash: String) -> Self {
+        let now = SystemTime::now();
+        let device: String = device
+            .chars()
+            .filter(|c| !c.is_control())
+            .take(Self::MAX_DEVICE_LENGTH)
+            .collect();
+        let device = match device.trim() {
+            "" => Self::UNKNOWN_DEVICE.to_string(),
+            trimmed => trimmed.to_string(),
+        };
+
+        Self {
+            uuid: Uuid::new_v4(),
+            user_uuid,
+            device,
+            created_at: now,
+            expires_at: now + Self::LIFETIME,
+            last_used_at: now,
+            token_hash,
+        }
+    }
+
+    pub fn is_expired_at(&self, moment: SystemTime) -> bool {
+        self.expires_at <= moment
+    }
+
+    pub fn auth_pair(&self, token: AuthToken) -> AuthPair {
+        AuthPair {
+            user_uuid: Some(self.user_uuid.into()),
+            token: Some(token.into()),
+        }
+    }
+}
+
+/// Sessions are never sent with their token, and aren't marked as current by default.
+impl From<Session> for proto::Session {
+    fn from(session: Session) -> Self {
+        Self {
+            uuid: Some(session.uuid.into()),
+            device: session.device,
+            created_at: Some(session.created_at.into()),
+            expires_at: Some(session.expires_at.into()),
+            last_used_at: Some(session.last_used_at.into()),
+            current: false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Session;
+    use std::time::SystemTime;
+    use uuid::Uuid;
+
+    fn session(device: &str) -> Session {
+        Session::new(Uuid::new_v4(), device, String::new())
+    }
+
+    #[test]
+    fn device_labels() {
+        assert_eq!(session(" Laptop ").device, "Laptop");
+        assert_eq!(session("").device, Session::UNKNOWN_DEVICE);
+        assert_eq!(session("a\nb").device, "ab");
+        let long_label = "ж".repeat(100);
+        assert_eq!(
+            session(&long_label).device.chars().count(),
+            Session::MAX_DEVICE_LENGTH
+        );
+    }
+
+    #[test]
+    fn expiry() {
+        let session = session("Phone");
+        assert!(!session.is_expired_at(SystemTime::now()));
+        assert!(session.is_expired_at(session.expires_at));
+        assert!(session.is_expired_at(session.created_at + Session::LIFETIME * 2));
+    }
+}
@@ -1,7 +1,10 @@
 use crate::proto;
+use hmac::{Hmac, Mac};
 use rand_chacha::ChaCha20Rng;
 use rand_core::RngCore;
-use std::{fmt, num::ParseIntError, str::FromStr};
+use sha2::Sha256;
+use std::{env, fmt, num::ParseIntError, str::FromStr, sync::Arc};
+use subtle::ConstantTimeEq;
 
 #[derive(Debug, Clone, PartialEq, Eq)]
 #[allow(clippy::module_name_repetitions)]
@@ -18,6 +21,53 @@ impl AuthToken {
     }
 }
 
+/// The secret key that auth tokens are hashed with, so that only their hashes are ever stored.
+#[derive(Clone)]
+pub struct TokenKey {
+    key: Arc<[u8]>,
+}
+
+impl TokenKey {
+    pub fn new(key: &[u8]) -> Self {
+        Self { key: key.into() }
+    }
+
+    /// # Panics
+    ///
+    /// Panics if `$TOKEN_HASH_KEY` is not set or empty.
+    pub fn from_env() -> Self {
+        let key = env::var("TOKEN_HASH_KEY").expect("Could not read $TOKEN_HASH_KEY");
+        assert!(!key.is_empty(), "$TOKEN_HASH_KEY should not be empty");
+        Self::new(key.as_bytes())
+    }
+
+    /// A hex-encoded HMAC-SHA256 of the token (as in `pgcrypto`'s `hmac`, which migrations use).
+    pub fn hash(&self, token: &AuthToken) -> String {
+        let mut mac =
+            Hmac::<Sha256>::new_from_slice(&self.key).expect("HMAC takes keys of any size");
+        mac.update(token.to_string().as_bytes());
+        mac.finalize()
+            .into_bytes()
+            .iter()
+            .map(|byte| format!("{byte:02x}"))
+            .collect()
+    }
+
+    /// Check a token against a stored hash in constant time.
+    pub fn verify(&self, token: &AuthToken, stored_hash: &str) -> bool {
+        self.hash(token)
+            .as_bytes()
+            .ct_eq(stored_hash.as_bytes())
+            .into()
+    }
+}
+
+impl fmt::Debug for TokenKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str("TokenKey(<redacted>)")
+    }
+}
+
 impl From<AuthToken> for proto::AuthToken {
     fn from(auth_token: AuthToken) -> Self {
         Self {
@@ -68,7 +118,7 @@ impl FromStr for proto::AuthToken {
 #[cfg(test)]
 #[allow(clippy::unwrap_used)]
 mod tests {
-    use super::AuthToken;
+    use super::{AuthToken, TokenKey};
     use crate::proto;
 
     #[test]
@@ -87,4 +137,21 @@ mod tests {
 
         assert_eq!(token.to_string(), proto_token.to_string());
     }
+
+    #[test]
+    fn keyed_hash() {
+        let token = AuthToken { token: 123 };
+        let key = TokenKey::new(b"key");
+        let stored_hash = key.hash(&token);
+
+        // A standard HMAC-SHA256, so migrations can compute the same hash with `pgcrypto`.
+        assert_eq!(
+            stored_hash,
+            "9c8bfc59cbc6bc5c8e14f1141774edfb6987dcfd57027d0aca271f9c43c8b2e1"
+        );
+        assert!(key.verify(&token, &stored_hash));
+        assert!(!key.verify(&AuthToken { token: 124 }, &stored_hash));
+        assert!(!TokenKey::new(b"another key").verify(&token, &stored_hash));
+        assert!(!key.verify(&token, ""));
+    }
 }
@@ -1,8 +1,6 @@
-use crate::entities::token::AuthToken;
-use crate::proto::{self, AuthPair};
+use crate::proto;
 use diesel::prelude::*;
-use rand_chacha::ChaCha20Rng;
-use std::str::FromStr;
+use std::time::SystemTime;
 use uuid::Uuid;
 
 #[derive(Queryable, Selectable, Insertable, Identifiable, Clone, Debug)]
@@ -13,32 +11,38 @@ pub struct User {
     pub uuid: Uuid,
     pub username: String,
     pub password: String,
-    pub auth_token: String,
+    pub last_seen: Option<SystemTime>,
 }
 
 impl User {
-    pub fn new(username: String, password: String, rng: &mut ChaCha20Rng) -> Self {
+    /// The maximum length of a username in characters (matches the database column).
+    pub const MAX_USERNAME_LENGTH: usize = 64;
+    /// The placeholder account that messages of deleted accounts are attributed to.
+    ///
+    /// It's created by a migration, and can't be logged in as or added to rooms.
+    pub const DELETED_UUID: Uuid = Uuid::nil();
+
+    pub fn new(username: String, password: String) -> Self {
         Self {
             uuid: Uuid::new_v4(),
             username,
             password,
-            auth_token: AuthToken::new(rng).to_string(),
+            last_seen: None,
         }
     }
 
-    pub fn auth_token(&self) -> AuthToken {
-        AuthToken::from_str(&self.auth_token).expect("The database should only store valid UUIDs")
-    }
-
-    pub fn proto_token(&self) -> proto::AuthToken {
-        self.auth_token().into()
-    }
-
-    pub fn auth_pair(&self) -> AuthPair {
-        AuthPair {
-            user_uuid: Some(self.uuid.into()),
-            token: Some(self.proto_token()),
+    /// Ensure a username is something that can be shown to other users and fits into the database.
+    pub fn validate_username(username: &str) -> Result<(), &'static str> {
+        if username.trim().is_empty() {
+            return Err("The username can't be empty");
+        }
+        if username.chars().count() > Self::MAX_USERNAME_LENGTH {
+            return Err("The username is too long");
         }
+        if username.chars().any(char::is_control) {
+            return Err("The username can't contain control characters");
+        }
+        Ok(())
     }
 }
 
@@ -50,3 +54,19 @@ impl From<User> for proto::User {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::User;
+
+    #[test]
+    fn usernames() {
+        assert!(User::validate_username("alice").is_ok());
+        assert!(User::validate_username("Алиса из Зазеркалья").is_ok());
+        assert!(User::validate_username(&"ж".repeat(User::MAX_USERNAME_LENGTH)).is_ok());
+        assert!(User::validate_username("").is_err());
+        assert!(User::validate_username("  ").is_err());
+        assert!(User::validate_username("bob\n").is_err());
+        assert!(User::validate_username(&"ж".repeat(User::MAX_USERNAME_LENGTH + 1)).is_err());
+    }
+}
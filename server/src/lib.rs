@@ -39,10 +39,15 @@
 pub mod auth;
 pub mod channel;
 pub mod entities;
+pub mod lockout;
+pub mod password;
 pub mod persistence;
+pub mod ratelimit;
 pub mod services;
 
 use crate::auth::Authenticator;
+use crate::entities::TokenKey;
+use crate::lockout::LoginGuard;
 use crate::persistence::create_persistence_pool;
 use crate::proto::chat_server::ChatServer;
 use crate::proto::registry_server::RegistryServer;
@@ -76,14 +81,16 @@ impl TCPChat {
 
         // Set up needed external resources and an authenticator.
         let persistence_pool = create_persistence_pool();
-        let interceptor = Authenticator::new(persistence_pool.clone());
+        let token_key = TokenKey::from_env();
+        let interceptor = Authenticator::new(persistence_pool.clone(), token_key.clone());
 
         // Set up gRPC services.
         let chat = Chat::new(persistence_pool.clone())
             .await
             .expect("Could not initialize a chat instance");
         let chat = ChatServer::with_interceptor(chat, interceptor.clone());
-        let registry = Registry::with_persistence_pool(persistence_pool.clone());
+        let login_guard = LoginGuard::from_env().expect("Could not set up login protection");
+        let registry = Registry::new(persistence_pool.clone(), token_key, login_guard);
         let registry = RegistryServer::new(registry);
 
         let identity = Identity::from_pem(CERT, KEY);
@@ -0,0 +1,363 @@
+//! Brute-force protection for logins.
+//!
+//! Failed logins are counted per username and per peer address in Redis. Once either
+//! has failed [`LoginPolicy::backoff_after`] times, every further failure doubles how
+//! long the next attempt has to wait, and after [`LoginPolicy::lockout_after`] failures
+//! the account is locked altogether for [`LoginPolicy::lockout`]. Peers are only ever
+//! slowed down, never locked out, since a single address may be shared by many users.
+//!
+//! Every attempt is counted as a failure up front, before the (slow) password check, so
+//! that a burst of concurrent guesses can't all get in before any of them is counted. A
+//! successful login then forgets the failures of the username, and takes its own attempt
+//! back from the peer, whose earlier failures are left to expire.
+//!
+//! Usernames are tracked whether or not such an account exists, so that the responses
+//! don't tell which usernames are taken.
+//!
+//! The counters share Redis with the chat service's caches, but not their key prefixes,
+//! so restarting the server doesn't hand out a fresh guess budget or unlock accounts.
+
+use crate::ratelimit::try_again_later;
+use redis::aio::MultiplexedConnection;
+use redis::Script;
+use redis::{Client, RedisResult};
+use std::env;
+use std::net::IpAddr;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::time::Duration;
+use tonic::Status;
+
+/// When to start slowing down and locking out failed logins.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct LoginPolicy {
+    /// How many failures in a row are let through without any delay.
+    pub backoff_after: u32,
+    /// How many failures in a row lock the account.
+    pub lockout_after: u32,
+    /// The delay after the first failure past [`Self::backoff_after`].
+    pub base_delay: Duration,
+    pub max_delay: Duration,
+    /// How long an account stays locked, which is also how long failures are remembered.
+    pub lockout: Duration,
+}
+
+impl Default for LoginPolicy {
+    fn default() -> Self {
+        Self {
+            backoff_after: 3,
+            lockout_after: 10,
+            base_delay: Duration::from_secs(1),
+            max_delay: Duration::from_secs(5 * 60),
+            lockout: Duration::from_secs(15 * 60),
+        }
+    }
+}
+
+impl LoginPolicy {
+    /// Read the policy from `$LOGIN_BACKOFF_AFTER`, `$LOGIN_LOCKOUT_AFTER` and
+    /// `$LOGIN_LOCKOUT_SECS`, using the defaults for whatever isn't set.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any of the variables is set to something that isn't a positive number.
+    pub fn from_env() -> Self {
+        fn var<T: FromStr + Default + PartialEq>(name: &str) -> Option<T> {
+            let value = env::var(name).ok()?;
+            match value.trim().parse() {
+                Ok(value) if value != T::default() => Some(value),
+                _ => panic!("${name} should be a positive number"),
+            }
+        }
+
+        let default = Self::default();
+        let policy = Self {
+            backoff_after: var("LOGIN_BACKOFF_AFTER").unwrap_or(default.backoff_after),
+            lockout_after: var("LOGIN_LOCKOUT_AFTER").unwrap_or(default.lockout_after),
+            lockout: var("LOGIN_LOCKOUT_SECS").map_or(default.lockout, Duration::from_secs),
+            ..default
+        };
+        tracing::debug!(message = "Login policy", ?policy);
+        policy
+    }
+
+    /// How long to hold off the next attempt after this many failures in a row, if at all.
+    pub fn delay_after(&self, failures: u32) -> Option<Duration> {
+        let doublings = failures.checked_sub(self.backoff_after)?;
+        let factor = 1_u32.checked_shl(doublings).unwrap_or(u32::MAX);
+        Some(self.base_delay.saturating_mul(factor).min(self.max_delay))
+    }
+
+    pub const fn locks_out_after(&self, failures: u32) -> bool {
+        failures >= self.lockout_after
+    }
+
+    /// The delays after [`Self::backoff_after`] failures and on, up to the first one that
+    /// hits [`Self::max_delay`], which then applies to any number of failures past it.
+    pub fn backoff_schedule(&self) -> Vec<Duration> {
+        let mut schedule = vec![];
+        for failures in self.backoff_after.. {
+            let Some(delay) = self.delay_after(failures) else {
+                break;
+            };
+            schedule.push(delay);
+            if delay >= self.max_delay {
+                break;
+            }
+        }
+        schedule
+    }
+}
+
+/// Who a login attempt is counted against.
+#[derive(Debug, Clone, Copy)]
+enum Subject<'a> {
+    Username(&'a str),
+    Peer(IpAddr),
+}
+
+impl Subject<'_> {
+    /// The username (or address) goes last, so that no choice of it can clash with another key.
+    fn key(self, kind: &str) -> String {
+        match self {
+            Self::Username(username) => format!("login:{kind}:user:{username}"),
+            Self::Peer(address) => format!("login:{kind}:peer:{address}"),
+        }
+    }
+}
+
+/// Keeps track of failed logins and turns away attempts that come too soon.
+///
+/// If Redis can't be reached, logins are let through rather than failed.
+#[derive(Debug, Clone)]
+pub struct LoginGuard {
+    cache_client: Client,
+    policy: LoginPolicy,
+}
+
+impl LoginGuard {
+    pub const fn new(cache_client: Client, policy: LoginPolicy) -> Self {
+        Self {
+            cache_client,
+            policy,
+        }
+    }
+
+    /// Connect to `$KV_URL` and read the policy with [`LoginPolicy::from_env`].
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if `$KV_URL` isn't a valid Redis URL.
+    ///
+    /// # Panics
+    ///
+    /// Panics if `$KV_URL` isn't set, or the policy is invalid.
+    pub fn from_env() -> RedisResult<Self> {
+        let cache_client = Client::open(env::var("KV_URL").expect("Could not read $KV_URL"))?;
+        Ok(Self::new(cache_client, LoginPolicy::from_env()))
+    }
+
+    /// Checks whether an attempt may go ahead and, if so, counts it as a failure right away,
+    /// slowing down or locking out whatever attempts come after it. Returns `{1, ttl}` if the
+    /// account is locked, `{2, ttl}` if the username or the peer is backing off, `{0, 0}` otherwise.
+    ///
+    /// `KEYS` are the lock, failures and backoff keys of the username, then the failures and
+    /// backoff keys of the peer, if any. `ARGV` are [`LoginPolicy::lockout_after`], the lockout
+    /// in milliseconds, [`LoginPolicy::backoff_after`] and [`LoginPolicy::backoff_schedule`].
+    const RESERVE_SCRIPT: &'static str = r"
+        local lockout_after = tonumber(ARGV[1])
+        local lockout_ms = tonumber(ARGV[2])
+        local backoff_after = tonumber(ARGV[3])
+
+        local locked_ttl = redis.call('PTTL', KEYS[1])
+        if locked_ttl > 0 then
+            return {1, locked_ttl}
+        end
+        local backoff_ttl = 0
+        for i = 3, #KEYS, 2 do
+            backoff_ttl = math.max(backoff_ttl, redis.call('PTTL', KEYS[i]))
+        end
+        if backoff_ttl > 0 then
+            return {2, backoff_ttl}
+        end
+
+        for i = 2, #KEYS, 2 do
+            local failures = redis.call('INCR', KEYS[i])
+            redis.call('PEXPIRE', KEYS[i], lockout_ms)
+            if i == 2 and failures >= lockout_after then
+                redis.call('SET', KEYS[1], failures, 'PX', lockout_ms)
+                redis.call('DEL', KEYS[2], KEYS[3])
+            elseif failures >= backoff_after and #ARGV > 3 then
+                local delay = ARGV[math.min(4 + failures - backoff_after, #ARGV)]
+                redis.call('SET', KEYS[i + 1], failures, 'PX', delay)
+            end
+        end
+        return {0, 0}
+    ";
+
+    /// Make sure a login attempt may go ahead at all, and count it as a failure until
+    /// [`Self::record_success`] says otherwise. Call this before checking the password.
+    ///
+    /// # Errors
+    ///
+    /// Returns `resource_exhausted` with a `retry-after` (in seconds) if the account is
+    /// locked, or if the username or the peer has to wait after failing too many times.
+    pub async fn check(&self, username: &str, peer: Option<IpAddr>) -> Result<(), Status> {
+        static SCRIPT: OnceLock<Script> = OnceLock::new();
+
+        let Some(mut cache) = self.acquire_cache_connection().await else {
+            return Ok(());
+        };
+
+        let user = Subject::Username(username);
+        let mut invocation = SCRIPT
+            .get_or_init(|| Script::new(Self::RESERVE_SCRIPT))
+            .prepare_invoke();
+        invocation
+            .key(user.key("locked"))
+            .key(user.key("failures"))
+            .key(user.key("backoff"))
+            .arg(self.policy.lockout_after)
+            .arg(millis(self.policy.lockout))
+            .arg(self.policy.backoff_after)
+            .arg(
+                self.policy
+                    .backoff_schedule()
+                    .into_iter()
+                    .map(millis)
+                    .collect::<Vec<_>>(),
+            );
+        if let Some(peer) = peer.map(Subject::Peer) {
+            invocation
+                .key(peer.key("failures"))
+                .key(peer.key("backoff"));
+        }
+        let (outcome, ttl): (u8, i64) = match invocation.invoke_async(&mut cache).await {
+            Ok(result) => result,
+            Err(error) => {
+                tracing::error!(message = "Couldn't check for failed logins", ?error);
+                return Ok(());
+            }
+        };
+        let wait = Duration::from_millis(u64::try_from(ttl).unwrap_or_default());
+
+        match outcome {
+            1 => {
+                tracing::warn!(message = "Turned away a login to a locked account", %username, ?peer, locked_for_secs = wait.as_secs());
+                Err(try_again_later(
+                    "The account is temporarily locked after too many failed logins",
+                    wait,
+                ))
+            }
+            2 => {
+                tracing::warn!(message = "Turned away a login during backoff", %username, ?peer, backoff_ms = wait.as_millis());
+                Err(try_again_later("Too many failed logins", wait))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// Forget the failed logins of the username, and take the attempt [`Self::check`]
+    /// counted back from the peer.
+    ///
+    /// The peer's earlier failures are left to expire, so that logging into an account
+    /// of one's own doesn't let an address keep guessing the passwords of others.
+    pub async fn record_success(&self, username: &str, peer: Option<IpAddr>) {
+        static SCRIPT: OnceLock<Script> = OnceLock::new();
+
+        let Some(mut cache) = self.acquire_cache_connection().await else {
+            return;
+        };
+
+        let user = Subject::Username(username);
+        let mut invocation = SCRIPT
+            .get_or_init(|| Script::new(Self::UNDO_SCRIPT))
+            .prepare_invoke();
+        invocation
+            .key(user.key("locked"))
+            .key(user.key("failures"))
+            .key(user.key("backoff"));
+        if let Some(peer) = peer.map(Subject::Peer) {
+            invocation.key(peer.key("failures"));
+        }
+        if let Err(error) = invocation.invoke_async::<_, ()>(&mut cache).await {
+            tracing::error!(message = "Couldn't reset failed logins", %username, ?error);
+        }
+    }
+
+    /// Deletes the username's keys (`KEYS[1..3]`) and decrements the peer's failures (`KEYS[4]`).
+    const UNDO_SCRIPT: &'static str = r"
+        redis.call('DEL', KEYS[1], KEYS[2], KEYS[3])
+        if KEYS[4] and redis.call('DECR', KEYS[4]) <= 0 then
+            redis.call('DEL', KEYS[4])
+        end
+    ";
+
+    async fn acquire_cache_connection(&self) -> Option<MultiplexedConnection> {
+        self.cache_client
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(|error| {
+                tracing::error!(message = "Couldn't acquire a cache connection", ?error)
+            })
+            .ok()
+    }
+}
+
+fn millis(duration: Duration) -> u64 {
+    u64::try_from(duration.as_millis()).unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{LoginPolicy, Subject};
+    use crate::services::chat::Chat;
+    use std::net::{IpAddr, Ipv4Addr};
+    use std::time::Duration;
+
+    #[test]
+    fn exponential_backoff() {
+        let policy = LoginPolicy::default();
+        assert_eq!(policy.delay_after(1), None);
+        assert_eq!(policy.delay_after(2), None);
+        assert_eq!(policy.delay_after(3), Some(Duration::from_secs(1)));
+        assert_eq!(policy.delay_after(4), Some(Duration::from_secs(2)));
+        assert_eq!(policy.delay_after(6), Some(Duration::from_secs(8)));
+        assert_eq!(policy.delay_after(20), Some(policy.max_delay));
+        assert_eq!(policy.delay_after(u32::MAX), Some(policy.max_delay));
+    }
+
+    #[test]
+    fn backoff_schedule() {
+        let policy = LoginPolicy::default();
+        let schedule = policy.backoff_schedule();
+        assert_eq!(schedule.first(), Some(&Duration::from_secs(1)));
+        assert_eq!(schedule.get(1), Some(&Duration::from_secs(2)));
+        assert_eq!(schedule.last(), Some(&policy.max_delay));
+        assert_eq!(schedule.len(), 10);
+    }
+
+    #[test]
+    fn lockout_threshold() {
+        let policy = LoginPolicy::default();
+        assert!(!policy.locks_out_after(policy.lockout_after - 1));
+        assert!(policy.locks_out_after(policy.lockout_after));
+        assert!(policy.locks_out_after(policy.lockout_after + 1));
+    }
+
+    #[test]
+    fn keys_survive_chat_startup() {
+        let subjects = [
+            Subject::Username("rooms:"),
+            Subject::Peer(IpAddr::V4(Ipv4Addr::LOCALHOST)),
+        ];
+        for subject in subjects {
+            for kind in ["locked", "failures", "backoff"] {
+                let key = subject.key(kind);
+                assert!(Chat::REBUILT_KEY_PREFIXES
+                    .iter()
+                    .all(|prefix| !key.starts_with(prefix)));
+            }
+        }
+    }
+}
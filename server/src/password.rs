@@ -0,0 +1,118 @@
+//! Password hashing.
+//!
+//! Passwords are hashed with Argon2id and stored as PHC strings, which carry the salt
+//! and the parameters of the hash along with it. Accounts registered before that have
+//! an unsalted BLAKE3 hash instead, which is still accepted, but reported as one that
+//! should be replaced with a fresh hash.
+
+use argon2::password_hash::rand_core::OsRng;
+use argon2::password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString};
+use argon2::{Argon2, Params};
+use std::sync::OnceLock;
+
+/// The outcome of checking a password against a stored hash.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verification {
+    Invalid,
+    /// The password is correct. If the stored hash is outdated (a legacy BLAKE3 hash,
+    /// or Argon2 with other parameters), it should be replaced with [`hash`].
+    Valid {
+        needs_rehash: bool,
+    },
+}
+
+/// Hash a password with a random salt into a PHC string.
+///
+/// # Errors
+///
+/// Returns an error if the password is too long to be hashed.
+pub fn hash(password: &str) -> Result<String, argon2::password_hash::Error> {
+    let salt = SaltString::generate(&mut OsRng);
+    let hash = Argon2::default().hash_password(password.as_bytes(), &salt)?;
+    Ok(hash.to_string())
+}
+
+/// Check a password against a stored hash, be it a PHC string or a legacy BLAKE3 hash.
+pub fn verify(password: &str, stored_hash: &str) -> Verification {
+    // Legacy hashes are bare hex strings, while PHC strings always start with a `$`.
+    if let Ok(legacy_hash) = blake3::Hash::from_hex(stored_hash) {
+        // `blake3::Hash` compares in constant time.
+        return if blake3::hash(password.as_bytes()) == legacy_hash {
+            Verification::Valid { needs_rehash: true }
+        } else {
+            Verification::Invalid
+        };
+    }
+
+    let Ok(parsed_hash) = PasswordHash::new(stored_hash) else {
+        tracing::error!(message = "Found a malformed password hash");
+        return Verification::Invalid;
+    };
+    if Argon2::default()
+        .verify_password(password.as_bytes(), &parsed_hash)
+        .is_err()
+    {
+        return Verification::Invalid;
+    }
+
+    let current_algorithm = parsed_hash.algorithm == argon2::Algorithm::default().ident();
+    let current_params = Params::try_from(&parsed_hash).is_ok_and(|params| {
+        let default = Params::default();
+        (params.m_cost(), params.t_cost(), params.p_cost())
+            == (default.m_cost(), default.t_cost(), default.p_cost())
+    });
+    Verification::Valid {
+        needs_rehash: !(current_algorithm && current_params),
+    }
+}
+
+/// Spend as much time as [`verify`] would, without checking anything.
+///
+/// Makes logging in as a user that doesn't exist as slow as with a wrong password,
+/// so that response times don't tell which usernames are taken.
+pub fn verify_nothing(password: &str) {
+    static DUMMY_HASH: OnceLock<String> = OnceLock::new();
+
+    let dummy_hash = DUMMY_HASH.get_or_init(|| hash("").unwrap_or_default());
+    let _ = verify(password, dummy_hash);
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::{hash, verify, Verification};
+
+    #[test]
+    fn hash_roundtrip() {
+        let stored_hash = hash("correct horse battery staple").unwrap();
+        assert!(stored_hash.starts_with("$argon2id$"));
+        assert_eq!(
+            verify("correct horse battery staple", &stored_hash),
+            Verification::Valid {
+                needs_rehash: false
+            }
+        );
+        assert_eq!(verify("Tr0ub4dor&3", &stored_hash), Verification::Invalid);
+    }
+
+    #[test]
+    fn hashes_are_salted() {
+        assert_ne!(hash("hunter2").unwrap(), hash("hunter2").unwrap());
+    }
+
+    #[test]
+    fn legacy_hash_needs_rehash() {
+        let legacy_hash = blake3::hash(b"hunter2").to_string();
+        assert_eq!(
+            verify("hunter2", &legacy_hash),
+            Verification::Valid { needs_rehash: true }
+        );
+        assert_eq!(verify("hunter3", &legacy_hash), Verification::Invalid);
+    }
+
+    #[test]
+    fn malformed_hash_is_rejected() {
+        assert_eq!(verify("", ""), Verification::Invalid);
+        assert_eq!(verify("hunter2", "hunter2"), Verification::Invalid);
+    }
+}
@@ -0,0 +1,285 @@
+//! Per-user rate limiting.
+//!
+//! Every rate-limited RPC has its own token bucket per user, stored in Redis so that
+//! the limits hold across server instances. A bucket holds up to `capacity` tokens and
+//! refills completely over `period`, and each call takes a single token out of it.
+
+use redis::aio::MultiplexedConnection;
+use redis::Script;
+use std::collections::HashMap;
+use std::str::FromStr;
+use std::sync::OnceLock;
+use std::time::Duration;
+use std::{env, fmt};
+use tonic::metadata::MetadataMap;
+use tonic::{Code, Status};
+use uuid::Uuid;
+
+/// An RPC that has its own rate limit.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LimitedRpc {
+    SendMessage,
+    EditMessage,
+    SearchMessages,
+    UploadAttachment,
+    CreateRoom,
+    CreateRoomWithUser,
+    AnalyzeRoom,
+    ChangePassword,
+    ChangeUsername,
+    DeleteAccount,
+    UpdateProfile,
+}
+
+impl LimitedRpc {
+    pub const ALL: [Self; 11] = [
+        Self::SendMessage,
+        Self::EditMessage,
+        Self::SearchMessages,
+        Self::UploadAttachment,
+        Self::CreateRoom,
+        Self::CreateRoomWithUser,
+        Self::AnalyzeRoom,
+        Self::ChangePassword,
+        Self::ChangeUsername,
+        Self::DeleteAccount,
+        Self::UpdateProfile,
+    ];
+
+    pub const fn as_str(self) -> &'static str {
+        match self {
+            Self::SendMessage => "send_message",
+            Self::EditMessage => "edit_message",
+            Self::SearchMessages => "search_messages",
+            Self::UploadAttachment => "upload_attachment",
+            Self::CreateRoom => "create_room",
+            Self::CreateRoomWithUser => "create_room_with_user",
+            Self::AnalyzeRoom => "analyze_room",
+            Self::ChangePassword => "change_password",
+            Self::ChangeUsername => "change_username",
+            Self::DeleteAccount => "delete_account",
+            Self::UpdateProfile => "update_profile",
+        }
+    }
+
+    /// The limit used unless it's overridden with `$RATE_LIMIT_<RPC>`.
+    pub const fn default_limit(self) -> RateLimit {
+        match self {
+            Self::SendMessage | Self::EditMessage => RateLimit::new(30, 30),
+            Self::SearchMessages => RateLimit::new(20, 60),
+            Self::UploadAttachment => RateLimit::new(10, 60),
+            Self::CreateRoom | Self::CreateRoomWithUser => RateLimit::new(10, 600),
+            Self::AnalyzeRoom => RateLimit::new(3, 300),
+            // These check the user's password, so they mustn't allow guessing it.
+            Self::ChangePassword | Self::DeleteAccount => RateLimit::new(5, 900),
+            Self::ChangeUsername => RateLimit::new(5, 3600),
+            Self::UpdateProfile => RateLimit::new(10, 60),
+        }
+    }
+
+    fn env_var(self) -> String {
+        format!("RATE_LIMIT_{}", self.as_str().to_uppercase())
+    }
+}
+
+/// A token bucket that holds up to `capacity` tokens and refills completely over `period`.
+///
+/// Written as `<capacity>/<seconds>s`, like `30/60s` for 30 calls a minute.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct RateLimit {
+    pub capacity: u32,
+    pub period: Duration,
+}
+
+impl RateLimit {
+    pub const fn new(capacity: u32, period_secs: u64) -> Self {
+        Self {
+            capacity,
+            period: Duration::from_secs(period_secs),
+        }
+    }
+
+    /// How long it takes for a single token to come back.
+    fn refill_interval(self) -> Duration {
+        self.period / self.capacity
+    }
+}
+
+impl fmt::Display for RateLimit {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}/{}s", self.capacity, self.period.as_secs())
+    }
+}
+
+impl FromStr for RateLimit {
+    type Err = &'static str;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        const INVALID: &str = "Rate limits look like `<capacity>/<seconds>s`";
+
+        let (capacity, period) = s.trim().split_once('/').ok_or(INVALID)?;
+        let capacity: u32 = capacity.parse().map_err(|_| INVALID)?;
+        let period: u64 = period
+            .strip_suffix('s')
+            .and_then(|p| p.parse().ok())
+            .ok_or(INVALID)?;
+        if capacity == 0 || period == 0 {
+            return Err("Rate limits can't be zero");
+        }
+
+        Ok(Self::new(capacity, period))
+    }
+}
+
+/// The rate limits of every [`LimitedRpc`].
+#[derive(Debug, Clone)]
+pub struct RateLimiter {
+    limits: HashMap<LimitedRpc, RateLimit>,
+}
+
+impl RateLimiter {
+    /// Takes a token out of the bucket, refilling it first. Returns how many milliseconds
+    /// to wait for a token if the bucket is empty, or zero if the call may go ahead.
+    ///
+    /// Redis' own clock is used, so that every server instance agrees on the time.
+    const TOKEN_BUCKET_SCRIPT: &'static str = r"
+        local capacity = tonumber(ARGV[1])
+        local refill_ms = tonumber(ARGV[2])
+        local time = redis.call('TIME')
+        local now = tonumber(time[1]) * 1000 + math.floor(tonumber(time[2]) / 1000)
+
+        local bucket = redis.call('HMGET', KEYS[1], 'tokens', 'updated')
+        local tokens = tonumber(bucket[1]) or capacity
+        local updated = tonumber(bucket[2]) or now
+        tokens = math.min(capacity, tokens + math.max(0, now - updated) / refill_ms)
+
+        local wait = 0
+        if tokens >= 1 then
+            tokens = tokens - 1
+        else
+            wait = math.ceil((1 - tokens) * refill_ms)
+        end
+
+        redis.call('HSET', KEYS[1], 'tokens', tostring(tokens), 'updated', now)
+        redis.call('PEXPIRE', KEYS[1], math.ceil(capacity * refill_ms))
+        return wait
+    ";
+
+    /// Read the limits from `$RATE_LIMIT_<RPC>` variables, like `$RATE_LIMIT_SEND_MESSAGE`.
+    ///
+    /// # Panics
+    ///
+    /// Panics if any of the variables is set to something that isn't a valid [`RateLimit`].
+    pub fn from_env() -> Self {
+        let limits = LimitedRpc::ALL
+            .into_iter()
+            .map(|rpc| {
+                let limit = match env::var(rpc.env_var()) {
+                    Ok(limit) => limit
+                        .parse()
+                        .unwrap_or_else(|error| panic!("Invalid ${}: {error}", rpc.env_var())),
+                    Err(_) => rpc.default_limit(),
+                };
+                tracing::debug!(message = "Rate limit", rpc = rpc.as_str(), %limit);
+                (rpc, limit)
+            })
+            .collect();
+
+        Self { limits }
+    }
+
+    /// Spend one call of the user's budget for the RPC.
+    ///
+    /// # Errors
+    ///
+    /// Returns `resource_exhausted` with a `retry-after` (in seconds) if the budget is spent.
+    /// If Redis can't be reached, the call is let through rather than failed.
+    pub async fn check(
+        &self,
+        cache: &mut MultiplexedConnection,
+        user: &Uuid,
+        rpc: LimitedRpc,
+    ) -> Result<(), Status> {
+        static SCRIPT: OnceLock<Script> = OnceLock::new();
+
+        let limit = self
+            .limits
+            .get(&rpc)
+            .copied()
+            .unwrap_or_else(|| rpc.default_limit());
+        let refill_ms = limit.refill_interval().as_millis().max(1);
+        let key = format!("ratelimit:{}:{}", rpc.as_str(), user.simple());
+
+        let wait_ms: u64 = match SCRIPT
+            .get_or_init(|| Script::new(Self::TOKEN_BUCKET_SCRIPT))
+            .key(key)
+            .arg(limit.capacity)
+            .arg(u64::try_from(refill_ms).unwrap_or(u64::MAX))
+            .invoke_async(cache)
+            .await
+        {
+            Ok(wait_ms) => wait_ms,
+            Err(error) => {
+                tracing::error!(message = "Couldn't check the rate limit", ?error);
+                return Ok(());
+            }
+        };
+        if wait_ms == 0 {
+            return Ok(());
+        }
+
+        tracing::warn!(message = "Rate limited a user", ?user, rpc = rpc.as_str(), %wait_ms);
+        Err(throttled(rpc, Duration::from_millis(wait_ms)))
+    }
+}
+
+fn throttled(rpc: LimitedRpc, wait: Duration) -> Status {
+    try_again_later(&format!("Too many {} calls", rpc.as_str()), wait)
+}
+
+/// A `resource_exhausted` status telling the client how many seconds to wait
+/// (rounded up) in its message and in the `retry-after` metadata.
+pub(crate) fn try_again_later(message: &str, wait: Duration) -> Status {
+    let retry_after = wait.as_secs() + u64::from(wait.subsec_nanos() > 0);
+
+    let mut metadata = MetadataMap::new();
+    metadata.insert("retry-after", retry_after.into());
+    Status::with_metadata(
+        Code::ResourceExhausted,
+        format!("{message}, try again in {retry_after}s"),
+        metadata,
+    )
+}
+
+#[cfg(test)]
+#[allow(clippy::unwrap_used)]
+mod tests {
+    use super::{throttled, LimitedRpc, RateLimit};
+    use std::time::Duration;
+
+    #[test]
+    fn parse_limits() {
+        assert_eq!("30/60s".parse(), Ok(RateLimit::new(30, 60)));
+        assert_eq!(" 1/1s ".parse(), Ok(RateLimit::new(1, 1)));
+        assert!("30/60".parse::<RateLimit>().is_err());
+        assert!("30".parse::<RateLimit>().is_err());
+        assert!("0/60s".parse::<RateLimit>().is_err());
+        assert!("30/0s".parse::<RateLimit>().is_err());
+        assert!("-1/60s".parse::<RateLimit>().is_err());
+    }
+
+    #[test]
+    fn default_limits_roundtrip() {
+        for rpc in LimitedRpc::ALL {
+            let limit = rpc.default_limit();
+            assert_eq!(limit.to_string().parse(), Ok(limit));
+        }
+    }
+
+    #[test]
+    fn retry_after_rounds_up() {
+        let status = throttled(LimitedRpc::SendMessage, Duration::from_millis(1500));
+        assert_eq!(status.code(), tonic::Code::ResourceExhausted);
+        assert_eq!(status.metadata().get("retry-after").unwrap(), "2");
+    }
+}
@@ -1,24 +1,53 @@
 use crate::auth::AuthenticatedRequest;
-use crate::channel::DisconnectChannel;
-use crate::entities::{Message, Room, RoomUser, User};
-use crate::proto::serverside_user_event::Event;
+use crate::channel::{DisconnectChannel, Outbox};
+use crate::entities::{Attachment, Message, Reaction, Room, RoomKind, RoomUser, User};
+use crate::entities::{
+    Mention, NotificationLevel, Profile, ProfileUpdate, RoomAction, RoomRole, SearchCursor,
+    SearchHit, Session, UserBlock,
+};
+use crate::password::Verification;
+use crate::proto::attachment_download_chunk::Chunk as DownloadChunk;
+use crate::proto::attachment_upload_chunk::Chunk as UploadChunk;
+use crate::proto::client_frame::Frame as ClientFrameKind;
+use crate::proto::server_frame::Frame as ServerFrameKind;
+use crate::proto::serverside_room_event::Event as RoomEvent;
+use crate::proto::serverside_user_event::Event as UserEvent;
 use crate::proto::user_lookup_request::Identifier;
+use crate::proto::{AccountDeletionRequest, PasswordChangeRequest, UsernameChangeRequest};
+use crate::proto::{AttachmentDownloadChunk, AttachmentUploadChunk, ClientFrame, ServerFrame};
 use crate::proto::{ClientsideMessage, ClientsideRoom, MessageList, RoomList};
-use crate::proto::{RoomWithUserCreationRequest, UserLookupRequest};
+use crate::proto::{
+    MemberRoleRequest, MentionListRequest, RoomMembershipRequest, RoomWithUserCreationRequest,
+};
+use crate::proto::{MessageCursor, MessageEditRequest, MessagePageRequest, MessageThread};
+use crate::proto::{MessageSearchHit, MessageSearchRequest, MessageSearchResults};
+use crate::proto::{PageDirection, Presence, ProfileUpdateRequest};
+use crate::proto::{ReactionCount, ReactionRequest, ReadMarkerRequest, RoomNotificationSettings};
+use crate::proto::{RoomUpdateRequest, SessionList, TypingRequest};
 use crate::proto::{ServersideMessage, ServersideRoom, ServersideRoomEvent, ServersideUserEvent};
+use crate::proto::{UserList, UserLookupRequest, UserTyping};
+use crate::ratelimit::{LimitedRpc, RateLimiter};
+use crate::services::registry::Registry;
 use crate::{channel, persistence, proto};
 use diesel::r2d2::{ConnectionManager, PooledConnection};
 use diesel::PgConnection;
+use futures::StreamExt;
 use itertools::Itertools;
 use ollama_rs::generation::completion::request::GenerationRequest;
 use ollama_rs::Ollama;
 use redis::aio::MultiplexedConnection;
 use redis::{AsyncCommands, Client, RedisResult};
-use std::collections::HashMap;
+use std::collections::{HashMap, HashSet};
 use std::env;
-use tokio::sync::{broadcast, mpsc, oneshot};
+use std::fmt::Debug;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::sync::{broadcast, mpsc, oneshot, Mutex};
+use tokio::time::Instant;
 use tokio_util::sync::CancellationToken;
-use tonic::{Request, Response, Status};
+use tonic::{Request, Response, Status, Streaming};
 use tracing::instrument;
 use uuid::Uuid;
 
@@ -28,9 +57,20 @@ pub struct Chat {
     persistence_pool: persistence::ConnectionPool,
     cache_client: redis::Client,
 
-    // Message passing channel.
-    message_tx: broadcast::Sender<Message>,
+    // Message passing channels.
+    room_event_tx: broadcast::Sender<ServersideRoomEvent>,
     user_event_tx: broadcast::Sender<ServersideUserEvent>,
+
+    // When the typing state of each (room, user) pair expires.
+    typing_deadlines: Arc<Mutex<HashMap<(Uuid, Uuid), Instant>>>,
+
+    // How many `SubscribeToUser` and `Connect` streams each online user has open.
+    online_users: Arc<Mutex<HashMap<Uuid, usize>>>,
+
+    // Where the contents of attachments are stored, one file per attachment.
+    attachments_dir: PathBuf,
+
+    rate_limiter: RateLimiter,
 }
 
 #[tonic::async_trait]
@@ -88,7 +128,7 @@ impl proto::chat_server::Chat for Chat {
         &self,
         request: Request<proto::Uuid>,
     ) -> Result<Response<ServersideRoom>, Status> {
-        let _originator: Uuid = request
+        let originator: Uuid = request
             .get_originator_uuid()
             .expect("The authenticator should not let anonymous requests through");
 
@@ -112,18 +152,7 @@ impl proto::chat_server::Chat for Chat {
                 Status::internal(msg)
             })?;
 
-        let members: Vec<proto::Uuid> = db_room
-            .get_members(&mut db)
-            .await
-            .into_iter()
-            .map(|u| u.into())
-            .collect();
-
-        let serverside_room = ServersideRoom {
-            uuid: Some(db_room.uuid.into()),
-            name: db_room.name,
-            members,
-        };
+        let serverside_room = self.serverside_room(db_room, originator).await?;
 
         Ok(Response::new(serverside_room))
     }
@@ -138,7 +167,7 @@ impl proto::chat_server::Chat for Chat {
         let mut cache = self.acquire_cache_connection().await?;
 
         let room_uuids: Vec<Uuid> = cache
-            .lrange(originator, 0, -1)
+            .lrange(Self::rooms_key(&originator), 0, -1)
             .await
             .unwrap_or_else(|error| {
                 tracing::error!(message = "Couldn't get membership from cache", ?error);
@@ -150,6 +179,7 @@ impl proto::chat_server::Chat for Chat {
 
         let db_rooms: Vec<Room> = rooms
             .filter(uuid.eq_any(room_uuids))
+            .select(Room::as_select())
             .load::<Room>(&mut db)
             .map_err(|error| {
                 let msg = "Couldn't load rooms from the database";
@@ -159,28 +189,13 @@ impl proto::chat_server::Chat for Chat {
 
         let serverside_rooms_future: Vec<_> = db_rooms
             .into_iter()
-            .map(|db_room| async move {
-                let mut db = self
-                    .acquire_database_connection()
-                    .await
-                    .expect("Couldn't acquire a database connection");
-
-                let members: Vec<proto::Uuid> = db_room
-                    .get_members(&mut db)
-                    .await
-                    .iter()
-                    .map(|u| proto::Uuid::from(*u))
-                    .collect();
-
-                ServersideRoom {
-                    uuid: Some(db_room.uuid.into()),
-                    name: db_room.name,
-                    members,
-                }
-            })
+            .map(|db_room| self.serverside_room(db_room, originator))
             .collect();
 
-        let serverside_rooms = futures::future::join_all(serverside_rooms_future).await;
+        let serverside_rooms = futures::future::join_all(serverside_rooms_future)
+            .await
+            .into_iter()
+            .collect::<Result<Vec<_>, _>>()?;
 
         tracing::info!(message = "Sending a list of rooms", user = ?originator, count = %serverside_rooms.len());
 
@@ -204,19 +219,8 @@ impl proto::chat_server::Chat for Chat {
             .map_err(|_| Status::invalid_argument("Invalid room UUID"))?;
 
         // Ensure the user is a member of the rooms he's fetching messages from.
-        if !self
-            .check_room_membership(&originator_uuid, &requested_room_uuid)
-            .await?
-        {
-            tracing::warn!(
-                message = "User tried to fetch messages from a room he's not a member of",
-                user = ?originator_uuid,
-                room = ?requested_room_uuid
-            );
-            return Err(Status::permission_denied(
-                "You are not a member of this room",
-            ));
-        }
+        self.authorize(&originator_uuid, &requested_room_uuid, RoomAction::View)
+            .await?;
 
         let mut db = self.acquire_database_connection().await?;
 
@@ -225,6 +229,8 @@ impl proto::chat_server::Chat for Chat {
 
         let room_messages: Vec<Message> = messages
             .filter(room_uuid.eq(requested_room_uuid))
+            .order((timestamp.asc(), uuid.asc()))
+            .select(Message::as_select())
             .load::<Message>(&mut db)
             .map_err(|error| {
                 let msg = "Couldn't fetch messages from database";
@@ -232,530 +238,3859 @@ impl proto::chat_server::Chat for Chat {
                 Status::internal(msg)
             })?;
 
-        let serverside_messages: Vec<ServersideMessage> =
-            room_messages.into_iter().map(|m| m.into()).collect();
+        let serverside_messages = Self::into_serverside_messages(&mut db, room_messages).await?;
 
         tracing::info!(message = "Sending a list of messages", user = ?originator_uuid, count = %serverside_messages.len());
 
         Ok(Response::new(MessageList {
             messages: serverside_messages,
+            next_cursor: None,
         }))
     }
 
     #[instrument(skip_all)]
-    async fn send_message(
+    async fn list_messages_page(
         &self,
-        request: Request<ClientsideMessage>,
-    ) -> Result<Response<()>, Status> {
-        let message = Message::try_from(request)?;
+        request: Request<MessagePageRequest>,
+    ) -> Result<Response<MessageList>, Status> {
+        let originator_uuid = request
+            .get_originator_uuid()
+            .expect("The authenticator should not let anonymous requests through");
 
-        // Ensure the user isn't sending a message to a room he's not a member of.
-        if !self
-            .check_room_membership(&message.sender_uuid, &message.room_uuid)
-            .await?
-        {
-            tracing::warn!(
-                message = "User tried to send a message to a room he's not a member of",
-                user = ?&message.sender_uuid,
-                room = ?&message.room_uuid
-            );
-            return Err(Status::permission_denied(
-                "You're not a member of this room",
-            ));
-        }
+        let page_request = request.into_inner();
+        let direction = page_request.direction();
+        let requested_room_uuid: Uuid = page_request
+            .room_uuid
+            .and_then(|u| u.try_into().ok())
+            .ok_or(Status::invalid_argument("Invalid room UUID"))?;
 
-        tracing::info!(message = "Received new message", sender = ?message.sender_uuid, room = ?message.room_uuid);
+        // Ensure the user is a member of the rooms he's fetching messages from.
+        self.authorize(&originator_uuid, &requested_room_uuid, RoomAction::View)
+            .await?;
 
-        // Store the message in the database and mirror it to all receivers.
-        {
-            use crate::entities::schema::messages::dsl::*;
-            use diesel::prelude::*;
+        let page_size = match page_request.page_size {
+            0 => Self::DEFAULT_PAGE_SIZE,
+            size => size.min(Self::MAX_PAGE_SIZE),
+        };
 
-            let mut conn = self.acquire_database_connection().await?;
-            let _ = diesel::insert_into(messages)
-                .values(&message)
-                .execute(&mut conn)
-                .map_err(|error| {
-                    tracing::error!(message = "Could not store message!", ?error);
-                    Status::internal("Could not send the message due to an internal error")
-                })?;
+        let mut db = self.acquire_database_connection().await?;
 
-            match self.message_tx.send(message) {
-                Ok(recv_count) => tracing::trace!(message = "Broadcasting room event", ?recv_count),
-                Err(error) => {
-                    if self.message_tx.receiver_count() > 0 {
-                        tracing::error!(message = "Could not broadcast room event", ?error);
-                    } else {
-                        tracing::trace!(message = "No subscribers for room event");
-                    }
+        use crate::entities::schema::messages::dsl::*;
+        use crate::proto::message_cursor::Position;
+        use diesel::prelude::*;
+
+        // Resolve the cursor into a point in time, with an optional tie-breaking UUID.
+        let cursor: Option<(SystemTime, Option<Uuid>)> =
+            match page_request.cursor.and_then(|c| c.position) {
+                None => None,
+                Some(Position::Timestamp(proto_timestamp)) => {
+                    let cursor_timestamp = SystemTime::try_from(proto_timestamp)
+                        .map_err(|_| Status::invalid_argument("Invalid cursor timestamp"))?;
+                    Some((cursor_timestamp, None))
+                }
+                Some(Position::MessageUuid(proto_uuid)) => {
+                    let cursor_uuid = Uuid::try_from(proto_uuid)
+                        .map_err(|_| Status::invalid_argument("Invalid cursor message UUID"))?;
+                    let cursor_timestamp: SystemTime = messages
+                        .find(cursor_uuid)
+                        .filter(room_uuid.eq(requested_room_uuid))
+                        .select(timestamp)
+                        .first(&mut db)
+                        .optional()
+                        .map_err(|error| {
+                            let msg = "Couldn't fetch the cursor message from database";
+                            tracing::error!(message = msg, ?error);
+                            Status::internal(msg)
+                        })?
+                        .ok_or(Status::not_found("No such message in this room"))?;
+                    Some((cursor_timestamp, Some(cursor_uuid)))
                 }
+            };
+
+        // Fetch one extra message to find out whether there's a next page.
+        let query = messages
+            .filter(room_uuid.eq(requested_room_uuid))
+            .select(Message::as_select())
+            .limit(i64::from(page_size) + 1)
+            .into_boxed();
+        let query = match direction {
+            PageDirection::Backward => {
+                let query = match cursor {
+                    Some((ts, Some(id))) => {
+                        query.filter(timestamp.lt(ts).or(timestamp.eq(ts).and(uuid.lt(id))))
+                    }
+                    Some((ts, None)) => query.filter(timestamp.lt(ts)),
+                    None => query,
+                };
+                query.order((timestamp.desc(), uuid.desc()))
+            }
+            PageDirection::Forward => {
+                let query = match cursor {
+                    Some((ts, Some(id))) => {
+                        query.filter(timestamp.gt(ts).or(timestamp.eq(ts).and(uuid.gt(id))))
+                    }
+                    Some((ts, None)) => query.filter(timestamp.gt(ts)),
+                    None => query,
+                };
+                query.order((timestamp.asc(), uuid.asc()))
             }
+        };
+
+        let mut page: Vec<Message> = query.load::<Message>(&mut db).map_err(|error| {
+            let msg = "Couldn't fetch messages from database";
+            tracing::error!(message = msg, ?error);
+            Status::internal(msg)
+        })?;
+
+        let page_size = page_size as usize;
+        let has_next_page = page.len() > page_size;
+        page.truncate(page_size);
+        let next_cursor = page
+            .last()
+            .filter(|_| has_next_page)
+            .map(|last_message| MessageCursor {
+                position: Some(Position::MessageUuid(last_message.uuid.into())),
+            });
+
+        // Pages are always sent in chronological order.
+        if direction == PageDirection::Backward {
+            page.reverse();
         }
 
-        Ok(Response::new(()))
-    }
+        let serverside_messages = Self::into_serverside_messages(&mut db, page).await?;
 
-    #[instrument(skip_all)]
-    async fn create_room(
-        &self,
-        request: Request<ClientsideRoom>,
-    ) -> Result<Response<proto::Uuid>, Status> {
-        let room = request.into_inner();
-        let room_uuid = self.create_room(room).await?;
+        tracing::info!(message = "Sending a page of messages", user = ?originator_uuid, count = %serverside_messages.len(), ?direction);
 
-        Ok(Response::new(room_uuid.into()))
+        Ok(Response::new(MessageList {
+            messages: serverside_messages,
+            next_cursor,
+        }))
     }
 
     #[instrument(skip_all)]
-    async fn create_room_with_user(
+    async fn list_thread(
         &self,
-        request: Request<RoomWithUserCreationRequest>,
-    ) -> Result<Response<proto::Uuid>, Status> {
+        request: Request<proto::Uuid>,
+    ) -> Result<Response<MessageThread>, Status> {
         let originator_uuid = request
             .get_originator_uuid()
-            .map_err(|err| Status::internal(err.to_string()))?;
+            .expect("The authenticator should not let anonymous requests through");
 
-        let possible_interlocutor_uuid = request
+        let requested_message_uuid: Uuid = request
             .into_inner()
-            .user_uuid
-            .and_then(|u| Uuid::try_from(u).ok())
-            .ok_or(Status::invalid_argument("Invalid interlocutor UUID"))?;
+            .try_into()
+            .map_err(|_| Status::invalid_argument("Invalid message UUID"))?;
 
         let mut db = self.acquire_database_connection().await?;
 
-        // Import some traits and methods to interact with the ORM.
-        use crate::entities::schema::users::dsl::*;
+        use crate::entities::schema::messages::dsl::*;
         use diesel::prelude::*;
 
-        let interlocutor = users
-            .find(possible_interlocutor_uuid)
-            .select(User::as_select())
-            .first(&mut db)
-            .optional()
-            .map_err(|err| Status::internal(err.to_string()))?
-            .ok_or(Status::internal("No such user"))?;
+        let fetch_error = |error| {
+            let msg = "Couldn't fetch messages from database";
+            tracing::error!(message = msg, ?error);
+            Status::internal(msg)
+        };
 
-        let originator = users
-            .find(originator_uuid)
-            .select(User::as_select())
+        let requested_message: Message = messages
+            .find(requested_message_uuid)
+            .select(Message::as_select())
             .first(&mut db)
             .optional()
-            .map_err(|err| Status::internal(err.to_string()))?
-            .ok_or(Status::internal("No such user"))?;
+            .map_err(fetch_error)?
+            .ok_or(Status::not_found("No such message"))?;
 
-        let room_name = format!(
-            "Private chat between {} and {}",
-            originator.username, interlocutor.username
-        );
-        let private_room_uuid = self
-            .create_room(ClientsideRoom {
-                name: room_name,
-                members: vec![interlocutor.uuid.into(), originator_uuid.into()],
-            })
-            .await?;
+        // Ensure the user is a member of the room he's fetching the thread from.
+        self.authorize(
+            &originator_uuid,
+            &requested_message.room_uuid,
+            RoomAction::View,
+        )
+        .await?;
 
-        Ok(Response::new(private_room_uuid.into()))
-    }
+        let root: Message = match requested_message.reply_to {
+            None => requested_message,
+            Some(root_uuid) => messages
+                .find(root_uuid)
+                .select(Message::as_select())
+                .first(&mut db)
+                .map_err(fetch_error)?,
+        };
+        let replies: Vec<Message> = messages
+            .filter(reply_to.eq(root.uuid))
+            .order((timestamp.asc(), uuid.asc()))
+            .select(Message::as_select())
+            .load(&mut db)
+            .map_err(fetch_error)?;
 
-    type SubscribeToRoomStream = DisconnectChannel<Result<ServersideRoomEvent, Status>>;
+        let thread: Vec<Message> = std::iter::once(root).chain(replies).collect();
+        let mut replies = Self::into_serverside_messages(&mut db, thread).await?;
+        let root = replies.remove(0);
+
+        tracing::info!(message = "Sending a thread", user = ?originator_uuid, count = %replies.len());
+
+        Ok(Response::new(MessageThread {
+            root: Some(root),
+            replies,
+        }))
+    }
 
     #[instrument(skip_all)]
-    async fn subscribe_to_room(
+    async fn search_messages(
         &self,
-        request: Request<proto::Uuid>,
-    ) -> Result<Response<Self::SubscribeToRoomStream>, Status> {
-        let subscriber: Uuid = request
+        request: Request<MessageSearchRequest>,
+    ) -> Result<Response<MessageSearchResults>, Status> {
+        let originator_uuid = request
             .get_originator_uuid()
             .expect("The authenticator should not let anonymous requests through");
+        self.throttle(&originator_uuid, LimitedRpc::SearchMessages)
+            .await?;
 
-        let subscribed_room: Uuid = request.into_inner().try_into().map_err(|error| {
-            let msg = "The room UUID is invalid";
-            tracing::trace!(message = msg, ?error);
-            Status::invalid_argument(msg)
-        })?;
-
-        // Ensure the user is a member of the room he's subscribing to.
-        if !self
-            .check_room_membership(&subscriber, &subscribed_room)
-            .await?
-        {
-            tracing::warn!(
-                message = "User tried to subscribe to a room he's not a member of",
-                ?subscriber,
-                room = ?subscribed_room
-            );
-            return Err(Status::permission_denied(
-                "You are not a member of this room",
-            ));
+        let search = request.into_inner();
+        if search.query.trim().is_empty() {
+            return Err(Status::invalid_argument("The search query can't be empty"));
         }
 
-        // The 'streamer' thread (see below) needs a cache connection.
-        let mut cache = self.acquire_cache_connection().await?;
-
-        // NOTE: Read this.
-        //
-        // There are a total of 3 channels involved in this whole streaming thing:
-        // - An internal `broadcast` channel that transfers messages from `SendMessage` RPC calls;
-        // - A `DisconnectChannel`, which holds another 2 channels inside:
-        //   - A `mpsc` Tokio channel, which performs gRPC streaming;
-        //   - A `oneshot` Tokio channel, which fires when the client disconnects.
-
-        let (grpc_tx, grpc_rx) = mpsc::channel(4);
-        let (disconnect_tx, disconnect_rx) = oneshot::channel();
-        let disconnect_channel = channel::DisconnectChannel {
-            disconnect_tx: Some(disconnect_tx),
-            grpc_rx,
+        let sender_filter: Option<Uuid> = search
+            .sender_uuid
+            .map(Uuid::try_from)
+            .transpose()
+            .map_err(|_| Status::invalid_argument("Invalid sender UUID"))?;
+        let sent_after: Option<SystemTime> = search
+            .sent_after
+            .map(SystemTime::try_from)
+            .transpose()
+            .map_err(|_| Status::invalid_argument("Invalid start of the time range"))?;
+        let sent_before: Option<SystemTime> = search
+            .sent_before
+            .map(SystemTime::try_from)
+            .transpose()
+            .map_err(|_| Status::invalid_argument("Invalid end of the time range"))?;
+        let cursor: Option<SearchCursor> = match search.page_token.as_str() {
+            "" => None,
+            token => Some(token.parse().map_err(Status::invalid_argument)?),
+        };
+        let page_size = match search.page_size {
+            0 => Self::DEFAULT_PAGE_SIZE,
+            requested => requested.min(Self::MAX_PAGE_SIZE),
         };
 
-        let mut message_rx = self.message_tx.subscribe();
-        tracing::info!(message = "New room subscriber", room = ?subscribed_room);
-
-        // The logic for the streaming thread, extracted into a variable to help rustfmt.
-        let streaming_closure = async move {
-            while let Ok(msg) = message_rx.recv().await {
-                let message_room = msg.room_uuid;
-                let subscriber_rooms: Vec<Uuid> = cache
-                    .lrange(subscriber, 0, -1)
-                    .await
-                    .unwrap_or_else(|error| {
-                        tracing::error!(
-                            message = "Could not retrieve membership from cache",
-                            ?subscriber,
-                            ?error
-                        );
-                        vec![]
-                    });
-
-                // Check that the user is a member of the room and that he's subscribed to the rooms the message is from.
-                if subscriber_rooms.contains(&message_room) && subscribed_room == message_room {
-                    use proto::serverside_room_event::Event;
-                    let new_message = Event::NewMessage(msg.into());
-                    let event = ServersideRoomEvent {
-                        room_uuid: Some(subscribed_room.into()),
-                        event: Some(new_message),
-                    };
-
-                    let send_result = grpc_tx.send(Ok(event)).await;
-                    if send_result.is_err() {
-                        tracing::warn!(
-                            message = "A message was sent, but nobody is subscribed to the channel"
-                        )
-                    }
+        // Only ever search the rooms the user is a member of.
+        let mut cache = self.acquire_cache_connection().await?;
+        let member_rooms: Vec<Uuid> = cache
+            .lrange(Self::rooms_key(&originator_uuid), 0, -1)
+            .await
+            .map_err(|error| {
+                let msg = "Couldn't get membership from cache";
+                tracing::error!(message = msg, ?error);
+                Status::internal(msg)
+            })?;
+        let searched_rooms: Vec<Uuid> = match search.room_uuid {
+            None => member_rooms,
+            Some(proto_uuid) => {
+                let room = Uuid::try_from(proto_uuid)
+                    .map_err(|_| Status::invalid_argument("Invalid room UUID"))?;
+                if !member_rooms.contains(&room) {
+                    return Err(Status::permission_denied(
+                        "User is not a member of the room",
+                    ));
                 }
+                vec![room]
             }
         };
 
-        // The 'canceller' thread will cancel this token when the client disconnects.
-        let token = CancellationToken::new();
-        let token_clone = token.clone();
+        let mut db = self.acquire_database_connection().await?;
 
-        // This is the 'canceller' thread.
-        //
-        // This task will cancel the token when the client disconnects, which will shutdown
-        // the streaming thread (see below) and cause the broadcast::Receiver to drop.
-        tokio::spawn(async move {
-            let _ = disconnect_rx.await;
-            tracing::debug!(message = "Client disconnected, stopping message streaming");
-            token.cancel();
-        });
+        use diesel::prelude::*;
+        use diesel::sql_types::{Array, BigInt, Float4, Nullable, Text, Timestamp};
 
-        // This is the 'streamer' thread.
-        //
-        // This thread will receive all messages sent via the `SendMessage` RPC call, and
-        // mirror them to all subsribers. Without a canceller thread, a cancellation token
-        // and a hacky DisconnectChannel, this thread would never terminate, meaning there
-        // would soon be a thousand of hanging broadcast::Receivers with no real client.
-        tokio::spawn(async move {
-            tokio::select! {
-                _ = token_clone.cancelled() => {}
-                _ = streaming_closure => {}
-            }
-        });
+        // Fetch one more hit than requested to know whether there's another page.
+        let mut hits: Vec<SearchHit> = diesel::sql_query(Self::SEARCH_QUERY)
+            .bind::<Text, _>(&search.query)
+            .bind::<Array<diesel::sql_types::Uuid>, _>(&searched_rooms)
+            .bind::<Nullable<diesel::sql_types::Uuid>, _>(sender_filter)
+            .bind::<Nullable<Timestamp>, _>(sent_after)
+            .bind::<Nullable<Timestamp>, _>(sent_before)
+            .bind::<Nullable<Float4>, _>(cursor.map(|c| c.rank))
+            .bind::<Nullable<diesel::sql_types::Uuid>, _>(cursor.map(|c| c.message_uuid))
+            .bind::<BigInt, _>(i64::from(page_size) + 1)
+            .load(&mut db)
+            .map_err(|error| {
+                let msg = "Couldn't search messages in the database";
+                tracing::error!(message = msg, ?error);
+                Status::internal(msg)
+            })?;
 
-        Ok(Response::new(disconnect_channel))
-    }
+        let page_size = page_size as usize;
+        let next_page_token = if hits.len() > page_size {
+            hits.truncate(page_size);
+            hits.last().map(|hit| SearchCursor::from(hit).to_string())
+        } else {
+            None
+        }
+        .unwrap_or_default();
 
-    type SubscribeToUserStream = DisconnectChannel<Result<ServersideUserEvent, Status>>;
+        let (db_messages, ranked_snippets): (Vec<Message>, Vec<(f32, String)>) = hits
+            .into_iter()
+            .map(|hit| (hit.message, (hit.rank, hit.snippet)))
+            .unzip();
+        let hits: Vec<MessageSearchHit> = Self::into_serverside_messages(&mut db, db_messages)
+            .await?
+            .into_iter()
+            .zip(ranked_snippets)
+            .map(|(message, (rank, snippet))| MessageSearchHit {
+                message: Some(message),
+                snippet,
+                rank,
+            })
+            .collect();
+
+        tracing::info!(message = "Sending search results", user = ?originator_uuid, count = %hits.len());
+
+        Ok(Response::new(MessageSearchResults {
+            hits,
+            next_page_token,
+        }))
+    }
 
     #[instrument(skip_all)]
-    async fn subscribe_to_user(
+    async fn list_mentions(
         &self,
-        request: Request<()>,
-    ) -> Result<Response<Self::SubscribeToUserStream>, Status> {
-        let user_uuid: Uuid = request
+        request: Request<MentionListRequest>,
+    ) -> Result<Response<MessageList>, Status> {
+        let originator_uuid = request
             .get_originator_uuid()
             .expect("The authenticator should not let anonymous requests through");
 
-        let (grpc_tx, grpc_rx) = mpsc::channel(4);
-        let (disconnect_tx, disconnect_rx) = oneshot::channel();
-        let disconnect_channel = DisconnectChannel {
-            disconnect_tx: Some(disconnect_tx),
-            grpc_rx,
+        let page_request = request.into_inner();
+        let page_size = match page_request.page_size {
+            0 => Self::DEFAULT_PAGE_SIZE,
+            size => size.min(Self::MAX_PAGE_SIZE),
         };
 
-        let mut user_event_rx = self.user_event_tx.subscribe();
-        let streaming_closure = async move {
-            while let Ok(event) = user_event_rx.recv().await {
-                if event
-                    .user_uuid
-                    .clone()
-                    .is_some_and(|event_user_uuid| user_uuid == event_user_uuid)
-                {
-                    let send_result = grpc_tx.send(Ok(event)).await;
-                    if send_result.is_err() {
-                        tracing::trace!(message = "A user event occurred, but nobody is subscribed")
-                    }
-                }
-            }
-        };
+        let mut db = self.acquire_database_connection().await?;
 
-        let token = CancellationToken::new();
-        let token_clone = token.clone();
+        use crate::entities::schema::{message_mentions, messages, rooms_users, user_blocks};
+        use crate::proto::message_cursor::Position;
+        use diesel::prelude::*;
 
-        // Spawn the "canceller" thread.
-        tokio::spawn(async move {
-            let _ = disconnect_rx.await;
-            tracing::debug!(message = "Client disconnected, stopping user event streaming");
-            token.cancel();
-        });
+        // Resolve the cursor into a point in time, with an optional tie-breaking UUID.
+        let cursor: Option<(SystemTime, Option<Uuid>)> =
+            match page_request.cursor.and_then(|c| c.position) {
+                None => None,
+                Some(Position::Timestamp(proto_timestamp)) => {
+                    let cursor_timestamp = SystemTime::try_from(proto_timestamp)
+                        .map_err(|_| Status::invalid_argument("Invalid cursor timestamp"))?;
+                    Some((cursor_timestamp, None))
+                }
+                Some(Position::MessageUuid(proto_uuid)) => {
+                    let cursor_uuid = Uuid::try_from(proto_uuid)
+                        .map_err(|_| Status::invalid_argument("Invalid cursor message UUID"))?;
+                    let cursor_timestamp: SystemTime = message_mentions::table
+                        .inner_join(messages::table)
+                        .filter(message_mentions::user_uuid.eq(originator_uuid))
+                        .filter(messages::uuid.eq(cursor_uuid))
+                        .select(messages::timestamp)
+                        .first(&mut db)
+                        .optional()
+                        .map_err(|error| {
+                            let msg = "Couldn't fetch the cursor message from database";
+                            tracing::error!(message = msg, ?error);
+                            Status::internal(msg)
+                        })?
+                        .ok_or(Status::not_found("No such mention"))?;
+                    Some((cursor_timestamp, Some(cursor_uuid)))
+                }
+            };
 
-        // Spawn the "streamer" thread.
-        tokio::spawn(async move {
-            tokio::select! {
-                _ = token_clone.cancelled() => {}
-                _ = streaming_closure => {}
-            }
-        });
+        let member_rooms = rooms_users::table
+            .filter(rooms_users::user_uuid.eq(originator_uuid))
+            .select(rooms_users::room_uuid);
+        let blocked_users = user_blocks::table
+            .filter(user_blocks::blocker_uuid.eq(originator_uuid))
+            .select(user_blocks::blocked_uuid);
 
-        Ok(Response::new(disconnect_channel))
-    }
+        // Fetch one extra mention to find out whether there's a next page.
+        let query = message_mentions::table
+            .inner_join(messages::table)
+            .filter(message_mentions::user_uuid.eq(originator_uuid))
+            .filter(messages::deleted_at.is_null())
+            .filter(messages::room_uuid.eq_any(member_rooms))
+            .filter(diesel::dsl::not(
+                messages::sender_uuid.eq_any(blocked_users),
+            ))
+            .select(Message::as_select())
+            .order((messages::timestamp.desc(), messages::uuid.desc()))
+            .limit(i64::from(page_size) + 1)
+            .into_boxed();
+        let query = match cursor {
+            Some((ts, Some(id))) => query.filter(
+                messages::timestamp
+                    .lt(ts)
+                    .or(messages::timestamp.eq(ts).and(messages::uuid.lt(id))),
+            ),
+            Some((ts, None)) => query.filter(messages::timestamp.lt(ts)),
+            None => query,
+        };
 
-    #[instrument(skip_all, fields(user_uuid, room_uuid))]
-    async fn analyze_room(
+        let mut page: Vec<Message> = query.load(&mut db).map_err(|error| {
+            let msg = "Couldn't fetch mentions from database";
+            tracing::error!(message = msg, ?error);
+            Status::internal(msg)
+        })?;
+
+        let page_size = page_size as usize;
+        let has_next_page = page.len() > page_size;
+        page.truncate(page_size);
+        let next_cursor = page
+            .last()
+            .filter(|_| has_next_page)
+            .map(|last_message| MessageCursor {
+                position: Some(Position::MessageUuid(last_message.uuid.into())),
+            });
+
+        // Pages are always sent in chronological order.
+        page.reverse();
+        let serverside_messages = Self::into_serverside_messages(&mut db, page).await?;
+
+        tracing::info!(message = "Sending a page of mentions", user = ?originator_uuid, count = %serverside_messages.len());
+
+        Ok(Response::new(MessageList {
+            messages: serverside_messages,
+            next_cursor,
+        }))
+    }
+
+    #[instrument(skip_all)]
+    async fn send_message(
+        &self,
+        request: Request<ClientsideMessage>,
+    ) -> Result<Response<()>, Status> {
+        let attachment_uuids: Vec<Uuid> = request
+            .get_ref()
+            .attachments
+            .iter()
+            .cloned()
+            .map(Uuid::try_from)
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|_| Status::invalid_argument("Invalid attachment UUID"))?
+            .into_iter()
+            .unique()
+            .collect();
+        if attachment_uuids.len() > Attachment::MAX_PER_MESSAGE {
+            return Err(Status::invalid_argument(format!(
+                "A message can't carry more than {} attachments",
+                Attachment::MAX_PER_MESSAGE
+            )));
+        }
+
+        let mut message = Message::try_from(request)?;
+        self.throttle(&message.sender_uuid, LimitedRpc::SendMessage)
+            .await?;
+
+        // Ensure the user isn't sending a message to a room he's not a member of.
+        self.authorize(&message.sender_uuid, &message.room_uuid, RoomAction::Post)
+            .await?;
+
+        // Ensure replies stay in the same room, and keep threads one level deep.
+        if let Some(parent_uuid) = message.reply_to {
+            use crate::entities::schema::messages::dsl::*;
+            use diesel::prelude::*;
+
+            let mut conn = self.acquire_database_connection().await?;
+            let parent: Message = messages
+                .find(parent_uuid)
+                .select(Message::as_select())
+                .first(&mut conn)
+                .optional()
+                .map_err(|error| {
+                    let msg = "Couldn't fetch the replied-to message from database";
+                    tracing::error!(message = msg, ?error);
+                    Status::internal(msg)
+                })?
+                .ok_or(Status::not_found("The replied-to message does not exist"))?;
+
+            if parent.room_uuid != message.room_uuid {
+                tracing::warn!(
+                    message = "User tried to reply to a message from another room",
+                    user = ?message.sender_uuid,
+                    room = ?message.room_uuid,
+                    parent_room = ?parent.room_uuid
+                );
+                return Err(Status::invalid_argument(
+                    "Can't reply to a message from another room",
+                ));
+            }
+            if parent.is_deleted() {
+                return Err(Status::failed_precondition(
+                    "The replied-to message was deleted",
+                ));
+            }
+
+            message.reply_to = Some(parent.reply_to.unwrap_or(parent.uuid));
+        }
+
+        let mentioned_members = self.mentioned_members(&message).await?;
+
+        tracing::info!(message = "Received new message", sender = ?message.sender_uuid, room = ?message.room_uuid, mentions = %mentioned_members.len());
+
+        // Store the message in the database along with its mentions, claiming its attachments,
+        // and mirror it to all receivers.
+        {
+            use crate::entities::schema::{attachments, message_mentions, messages};
+            use diesel::prelude::*;
+
+            let mut conn = self.acquire_database_connection().await?;
+            let mut sent_attachments: Vec<Attachment> = conn
+                .transaction(|db| {
+                    diesel::insert_into(messages::table)
+                        .values(&message)
+                        .execute(db)?;
+                    let mentions: Vec<Mention> = mentioned_members
+                        .iter()
+                        .map(|member| Mention::new(message.uuid, member.user_uuid))
+                        .collect();
+                    diesel::insert_into(message_mentions::table)
+                        .values(&mentions)
+                        .execute(db)?;
+                    if attachment_uuids.is_empty() {
+                        return Ok(vec![]);
+                    }
+
+                    // Only unsent attachments the sender uploaded to this very room can be claimed.
+                    let claimed: Vec<Attachment> = diesel::update(
+                        attachments::table
+                            .filter(attachments::uuid.eq_any(&attachment_uuids))
+                            .filter(attachments::uploader_uuid.eq(message.sender_uuid))
+                            .filter(attachments::room_uuid.eq(message.room_uuid))
+                            .filter(attachments::message_uuid.is_null()),
+                    )
+                    .set(attachments::message_uuid.eq(message.uuid))
+                    .returning(Attachment::as_returning())
+                    .get_results(db)?;
+                    if claimed.len() != attachment_uuids.len() {
+                        return Err(diesel::result::Error::RollbackTransaction);
+                    }
+
+                    Ok(claimed)
+                })
+                .map_err(|error| match error {
+                    diesel::result::Error::RollbackTransaction => Status::invalid_argument(
+                        "Some attachments don't exist, belong to someone else or were already sent",
+                    ),
+                    error => {
+                        tracing::error!(message = "Could not store message!", ?error);
+                        Status::internal("Could not send the message due to an internal error")
+                    }
+                })?;
+            sent_attachments.sort_by_key(|attachment| {
+                attachment_uuids
+                    .iter()
+                    .position(|requested| *requested == attachment.uuid)
+            });
+
+            let room = message.room_uuid;
+            let mut serverside_message = ServersideMessage::from(message);
+            serverside_message.attachments = sent_attachments.into_iter().map(Into::into).collect();
+            // Mentions in muted rooms still end up in the inbox, they just don't notify.
+            let now = SystemTime::now();
+            let notified_members = mentioned_members
+                .iter()
+                .filter(|member| member.notification_level_at(now) != NotificationLevel::Muted);
+            for member in notified_members {
+                broadcast(
+                    &self.user_event_tx,
+                    ServersideUserEvent {
+                        user_uuid: Some(member.user_uuid.into()),
+                        event: Some(UserEvent::Mentioned(serverside_message.clone())),
+                    },
+                );
+            }
+            let event = ServersideRoomEvent {
+                room_uuid: Some(room.into()),
+                event: Some(RoomEvent::NewMessage(serverside_message)),
+            };
+            broadcast(&self.room_event_tx, event);
+        }
+
+        Ok(Response::new(()))
+    }
+
+    #[instrument(skip_all)]
+    async fn upload_attachment(
+        &self,
+        request: Request<Streaming<AttachmentUploadChunk>>,
+    ) -> Result<Response<proto::Attachment>, Status> {
+        let uploader = request
+            .get_originator_uuid()
+            .expect("The authenticator should not let anonymous requests through");
+        self.throttle(&uploader, LimitedRpc::UploadAttachment)
+            .await?;
+
+        let mut chunks = request.into_inner();
+        let Some(AttachmentUploadChunk {
+            chunk: Some(UploadChunk::Metadata(metadata)),
+        }) = chunks.message().await?
+        else {
+            return Err(Status::invalid_argument(
+                "The first chunk of an upload must carry the attachment's metadata",
+            ));
+        };
+
+        let room: Uuid = metadata
+            .room_uuid
+            .and_then(|u| u.try_into().ok())
+            .ok_or(Status::invalid_argument("Invalid room UUID"))?;
+        self.authorize(&uploader, &room, RoomAction::Post).await?;
+
+        Attachment::validate_file_name(&metadata.file_name).map_err(Status::invalid_argument)?;
+        Attachment::validate_content_type(&metadata.content_type)
+            .map_err(Status::invalid_argument)?;
+        let content_type = match metadata.content_type.as_str() {
+            "" => Attachment::DEFAULT_CONTENT_TYPE.to_string(),
+            _ => metadata.content_type,
+        };
+
+        // Unsent attachments take up space until they expire, so there's only so much of them
+        // a user may have at once.
+        let unsent_size = self.unsent_attachments_size(uploader).await?;
+        if unsent_size >= Attachment::MAX_UNSENT_SIZE {
+            return Err(Self::unsent_attachments_exhausted());
+        }
+
+        // Receive the contents into a partial file first, so a failed upload never leaves
+        // anything behind under the attachment's real path.
+        let attachment_uuid = Uuid::new_v4();
+        let path = self.attachment_path(&attachment_uuid);
+        let partial_path = path.with_extension("part");
+        let (size, hash) = match Self::receive_attachment(&mut chunks, &partial_path).await {
+            Ok((size, _)) if unsent_size + size > Attachment::MAX_UNSENT_SIZE => {
+                let _ = tokio::fs::remove_file(&partial_path).await;
+                return Err(Self::unsent_attachments_exhausted());
+            }
+            Ok(received) => received,
+            Err(status) => {
+                let _ = tokio::fs::remove_file(&partial_path).await;
+                return Err(status);
+            }
+        };
+        tokio::fs::rename(&partial_path, &path)
+            .await
+            .map_err(|error| {
+                let msg = "Couldn't store the attachment";
+                tracing::error!(message = msg, ?error);
+                Status::internal(msg)
+            })?;
+
+        let attachment = Attachment {
+            uuid: attachment_uuid,
+            uploader_uuid: uploader,
+            room_uuid: room,
+            message_uuid: None,
+            file_name: metadata.file_name,
+            content_type,
+            size: i64::try_from(size).unwrap_or(i64::MAX),
+            blake3_hash: hash,
+            uploaded_at: SystemTime::now(),
+        };
+
+        {
+            use crate::entities::schema::attachments::dsl::*;
+            use diesel::prelude::*;
+
+            let mut db = self.acquire_database_connection().await?;
+            if let Err(error) = diesel::insert_into(attachments)
+                .values(&attachment)
+                .execute(&mut db)
+            {
+                let _ = tokio::fs::remove_file(&path).await;
+                tracing::error!(message = "Could not store the attachment!", ?error);
+                return Err(Status::internal(
+                    "Could not upload the attachment due to an internal error",
+                ));
+            }
+        }
+
+        tracing::info!(message = "Attachment uploaded", user = ?uploader, ?room, %size);
+
+        Ok(Response::new(attachment.into()))
+    }
+
+    type DownloadAttachmentStream = DisconnectChannel<Result<AttachmentDownloadChunk, Status>>;
+
+    #[instrument(skip_all)]
+    async fn download_attachment(
         &self,
         request: Request<proto::Uuid>,
-    ) -> Result<Response<proto::RoomAnalysisResponse>, Status> {
-        let req_originator: Uuid = request
+    ) -> Result<Response<Self::DownloadAttachmentStream>, Status> {
+        let originator_uuid = request
             .get_originator_uuid()
             .expect("The authenticator should not let anonymous requests through");
-        tracing::Span::current().record("user_uuid", req_originator.to_string());
 
-        let req_room_uuid: Uuid = request
+        let requested_attachment_uuid: Uuid = request
             .into_inner()
             .try_into()
-            .map_err(|_| Status::invalid_argument("Invalid room UUID"))?;
-        tracing::Span::current().record("room_uuid", req_room_uuid.to_string());
+            .map_err(|_| Status::invalid_argument("Invalid attachment UUID"))?;
 
-        if !self
-            .check_room_membership(&req_originator, &req_room_uuid)
-            .await?
-        {
-            tracing::warn!(message = "User tried to LLM-analyze a room he's not a member of");
+        let attachment: Attachment = {
+            use crate::entities::schema::attachments::dsl::*;
+            use diesel::prelude::*;
+
+            let mut db = self.acquire_database_connection().await?;
+            attachments
+                .find(requested_attachment_uuid)
+                .select(Attachment::as_select())
+                .first(&mut db)
+                .optional()
+                .map_err(|error| {
+                    let msg = "Couldn't fetch the attachment from database";
+                    tracing::error!(message = msg, ?error);
+                    Status::internal(msg)
+                })?
+                .ok_or(Status::not_found("No such attachment"))?
+        };
+
+        // Ensure the user is a member of the room the attachment was uploaded to.
+        self.authorize(&originator_uuid, &attachment.room_uuid, RoomAction::View)
+            .await?;
+
+        let mut file = tokio::fs::File::open(self.attachment_path(&attachment.uuid))
+            .await
+            .map_err(|error| {
+                let msg = "Couldn't read the attachment";
+                tracing::error!(message = msg, ?error);
+                Status::internal(msg)
+            })?;
+
+        tracing::info!(message = "Sending an attachment", user = ?originator_uuid, attachment = ?attachment.uuid);
+
+        let (grpc_tx, grpc_rx) = mpsc::channel(4);
+        tokio::spawn(async move {
+            let metadata = AttachmentDownloadChunk {
+                chunk: Some(DownloadChunk::Attachment(attachment.into())),
+            };
+            if grpc_tx.send(Ok(metadata)).await.is_err() {
+                return;
+            }
+
+            let mut buffer = vec![0; Attachment::CHUNK_SIZE];
+            loop {
+                let chunk = match file.read(&mut buffer).await {
+                    Ok(0) => break,
+                    Ok(read) => Ok(AttachmentDownloadChunk {
+                        chunk: Some(DownloadChunk::Data(buffer[..read].to_vec())),
+                    }),
+                    Err(error) => {
+                        let msg = "Couldn't read the attachment";
+                        tracing::error!(message = msg, ?error);
+                        Err(Status::internal(msg))
+                    }
+                };
+
+                // Stop reading once the client is gone or the file couldn't be read.
+                let failed = chunk.is_err();
+                if grpc_tx.send(chunk).await.is_err() || failed {
+                    break;
+                }
+            }
+        });
+
+        Ok(Response::new(DisconnectChannel {
+            disconnect_tx: None,
+            grpc_rx,
+        }))
+    }
+
+    #[instrument(skip_all)]
+    async fn edit_message(
+        &self,
+        request: Request<MessageEditRequest>,
+    ) -> Result<Response<()>, Status> {
+        let originator_uuid = request
+            .get_originator_uuid()
+            .expect("The authenticator should not let anonymous requests through");
+        self.throttle(&originator_uuid, LimitedRpc::EditMessage)
+            .await?;
+
+        let edit = request.into_inner();
+        let edited_message_uuid: Uuid = edit
+            .message_uuid
+            .and_then(|u| u.try_into().ok())
+            .ok_or(Status::invalid_argument("Invalid message UUID"))?;
+
+        let mut db = self.acquire_database_connection().await?;
+
+        use crate::entities::schema::messages::dsl::*;
+        use diesel::prelude::*;
+
+        let original_message: Message = messages
+            .find(edited_message_uuid)
+            .select(Message::as_select())
+            .first(&mut db)
+            .optional()
+            .map_err(|error| {
+                let msg = "Couldn't fetch the message from database";
+                tracing::error!(message = msg, ?error);
+                Status::internal(msg)
+            })?
+            .ok_or(Status::not_found("No such message"))?;
+
+        // Ensure the user is editing his own message, in a room he's still a member of.
+        if original_message.sender_uuid != originator_uuid {
+            tracing::warn!(
+                message = "User tried to edit someone else's message",
+                user = ?originator_uuid,
+                message_uuid = ?edited_message_uuid
+            );
             return Err(Status::permission_denied(
-                "You're not a member of this room",
+                "You can only edit your own messages",
+            ));
+        }
+        self.authorize(
+            &originator_uuid,
+            &original_message.room_uuid,
+            RoomAction::Post,
+        )
+        .await?;
+        if original_message.is_deleted() {
+            return Err(Status::failed_precondition("This message was deleted"));
+        }
+
+        let edited_message: Message = diesel::update(messages.find(edited_message_uuid))
+            .set((text.eq(edit.text), edited_at.eq(Some(SystemTime::now()))))
+            .returning(Message::as_returning())
+            .get_result(&mut db)
+            .map_err(|error| {
+                tracing::error!(message = "Could not store the edited message!", ?error);
+                Status::internal("Could not edit the message due to an internal error")
+            })?;
+
+        tracing::info!(message = "Edited a message", sender = ?originator_uuid, room = ?edited_message.room_uuid);
+
+        let event = ServersideRoomEvent {
+            room_uuid: Some(edited_message.room_uuid.into()),
+            event: Some(RoomEvent::MessageEdited(edited_message.into())),
+        };
+        broadcast(&self.room_event_tx, event);
+
+        Ok(Response::new(()))
+    }
+
+    #[instrument(skip_all)]
+    async fn delete_message(&self, request: Request<proto::Uuid>) -> Result<Response<()>, Status> {
+        let originator_uuid = request
+            .get_originator_uuid()
+            .expect("The authenticator should not let anonymous requests through");
+
+        let deleted_message_uuid: Uuid = request
+            .into_inner()
+            .try_into()
+            .map_err(|_| Status::invalid_argument("Invalid message UUID"))?;
+
+        let mut db = self.acquire_database_connection().await?;
+
+        use crate::entities::schema::messages::dsl::*;
+        use diesel::prelude::*;
+
+        let original_message: Message = messages
+            .find(deleted_message_uuid)
+            .select(Message::as_select())
+            .first(&mut db)
+            .optional()
+            .map_err(|error| {
+                let msg = "Couldn't fetch the message from database";
+                tracing::error!(message = msg, ?error);
+                Status::internal(msg)
+            })?
+            .ok_or(Status::not_found("No such message"))?;
+
+        // Anyone may delete their own messages, but only admins may delete someone else's.
+        let required_action = if original_message.sender_uuid == originator_uuid {
+            RoomAction::Post
+        } else {
+            RoomAction::DeleteAnyMessage
+        };
+        self.authorize(
+            &originator_uuid,
+            &original_message.room_uuid,
+            required_action,
+        )
+        .await?;
+        if original_message.is_deleted() {
+            return Err(Status::failed_precondition(
+                "This message was already deleted",
             ));
         }
 
-        tracing::trace!(message = "Collecting messages for an LLM analysis");
+        // Turn the message into a tombstone, keeping its UUID and timestamp. Tombstones can't be
+        // reacted to and don't carry any attachments, so those go away in the same transaction.
+        let (tombstone, deleted_attachments): (Message, Vec<Uuid>) = db
+            .transaction(|db| {
+                use crate::entities::schema::{attachments, message_reactions};
+
+                let tombstone = diesel::update(messages.find(deleted_message_uuid))
+                    .set((text.eq(""), deleted_at.eq(Some(SystemTime::now()))))
+                    .returning(Message::as_returning())
+                    .get_result(db)?;
+                diesel::delete(
+                    message_reactions::table
+                        .filter(message_reactions::message_uuid.eq(deleted_message_uuid)),
+                )
+                .execute(db)?;
+                let deleted_attachments = diesel::delete(
+                    attachments::table.filter(attachments::message_uuid.eq(deleted_message_uuid)),
+                )
+                .returning(attachments::uuid)
+                .get_results(db)?;
+
+                diesel::QueryResult::Ok((tombstone, deleted_attachments))
+            })
+            .map_err(|error| {
+                tracing::error!(message = "Could not delete the message!", ?error);
+                Status::internal("Could not delete the message due to an internal error")
+            })?;
+        self.remove_attachment_files(&deleted_attachments).await;
+
+        tracing::info!(message = "Deleted a message", user = ?originator_uuid, room = ?tombstone.room_uuid);
+
+        let event = ServersideRoomEvent {
+            room_uuid: Some(tombstone.room_uuid.into()),
+            event: Some(RoomEvent::MessageDeleted(tombstone.into())),
+        };
+        broadcast(&self.room_event_tx, event);
+
+        Ok(Response::new(()))
+    }
+
+    #[instrument(skip_all)]
+    async fn add_reaction(
+        &self,
+        request: Request<ReactionRequest>,
+    ) -> Result<Response<()>, Status> {
+        let (reaction, reaction_room) = self.reaction_from_request(request).await?;
+        let mut db = self.acquire_database_connection().await?;
+
+        use crate::entities::schema::message_reactions::dsl::*;
+        use diesel::prelude::*;
+
+        let inserted_count = diesel::insert_into(message_reactions)
+            .values(&reaction)
+            .on_conflict_do_nothing()
+            .execute(&mut db)
+            .map_err(|error| {
+                tracing::error!(message = "Could not store the reaction!", ?error);
+                Status::internal("Could not add the reaction due to an internal error")
+            })?;
+
+        // Only broadcast reactions that weren't there already.
+        if inserted_count > 0 {
+            tracing::info!(message = "Added a reaction", user = ?reaction.user_uuid, message_uuid = ?reaction.message_uuid);
+            let event = ServersideRoomEvent {
+                room_uuid: Some(reaction_room.into()),
+                event: Some(RoomEvent::ReactionAdded(reaction.into())),
+            };
+            broadcast(&self.room_event_tx, event);
+        }
+
+        Ok(Response::new(()))
+    }
+
+    #[instrument(skip_all)]
+    async fn remove_reaction(
+        &self,
+        request: Request<ReactionRequest>,
+    ) -> Result<Response<()>, Status> {
+        let (reaction, reaction_room) = self.reaction_from_request(request).await?;
+        let mut db = self.acquire_database_connection().await?;
+
+        use crate::entities::schema::message_reactions::dsl::*;
+        use diesel::prelude::*;
+
+        let deleted_count = diesel::delete(message_reactions.find((
+            reaction.message_uuid,
+            reaction.user_uuid,
+            &reaction.emoji,
+        )))
+        .execute(&mut db)
+        .map_err(|error| {
+            tracing::error!(message = "Could not delete the reaction!", ?error);
+            Status::internal("Could not remove the reaction due to an internal error")
+        })?;
+
+        // Only broadcast reactions that were actually there.
+        if deleted_count > 0 {
+            tracing::info!(message = "Removed a reaction", user = ?reaction.user_uuid, message_uuid = ?reaction.message_uuid);
+            let event = ServersideRoomEvent {
+                room_uuid: Some(reaction_room.into()),
+                event: Some(RoomEvent::ReactionRemoved(reaction.into())),
+            };
+            broadcast(&self.room_event_tx, event);
+        }
+
+        Ok(Response::new(()))
+    }
+
+    #[instrument(skip_all)]
+    async fn mark_read(&self, request: Request<ReadMarkerRequest>) -> Result<Response<()>, Status> {
+        let originator_uuid = request
+            .get_originator_uuid()
+            .expect("The authenticator should not let anonymous requests through");
+
+        let marker_request = request.into_inner();
+        let marked_room_uuid: Uuid = marker_request
+            .room_uuid
+            .and_then(|u| u.try_into().ok())
+            .ok_or(Status::invalid_argument("Invalid room UUID"))?;
+        let marked_message_uuid: Uuid = marker_request
+            .message_uuid
+            .and_then(|u| u.try_into().ok())
+            .ok_or(Status::invalid_argument("Invalid message UUID"))?;
+
+        self.authorize(&originator_uuid, &marked_room_uuid, RoomAction::View)
+            .await?;
+
+        let mut db = self.acquire_database_connection().await?;
+
+        use crate::entities::schema::messages;
+        use diesel::prelude::*;
+
+        let marked_message: (Uuid, SystemTime) = messages::table
+            .find(marked_message_uuid)
+            .filter(messages::room_uuid.eq(marked_room_uuid))
+            .select((messages::uuid, messages::timestamp))
+            .first(&mut db)
+            .optional()
+            .map_err(|error| {
+                let msg = "Couldn't fetch the message from database";
+                tracing::error!(message = msg, ?error);
+                Status::internal(msg)
+            })?
+            .ok_or(Status::not_found("No such message in this room"))?;
+
+        // Read markers only ever move forward, which the upsert itself checks so that
+        // concurrent calls can't move a marker back.
+        use diesel::sql_types::Timestamp;
+        let _ = diesel::sql_query(Self::MARK_READ_QUERY)
+            .bind::<diesel::sql_types::Uuid, _>(originator_uuid)
+            .bind::<diesel::sql_types::Uuid, _>(marked_room_uuid)
+            .bind::<diesel::sql_types::Uuid, _>(marked_message.0)
+            .bind::<Timestamp, _>(marked_message.1)
+            .execute(&mut db)
+            .map_err(|error| {
+                tracing::error!(message = "Could not store the read marker!", ?error);
+                Status::internal("Could not mark the messages as read due to an internal error")
+            })?;
+
+        tracing::debug!(message = "Moved a read marker", user = ?originator_uuid, room = ?marked_room_uuid);
+
+        Ok(Response::new(()))
+    }
+
+    #[instrument(skip_all)]
+    async fn set_typing(&self, request: Request<TypingRequest>) -> Result<Response<()>, Status> {
+        let originator_uuid = request
+            .get_originator_uuid()
+            .expect("The authenticator should not let anonymous requests through");
+
+        let typing_request = request.into_inner();
+        let typing_room_uuid: Uuid = typing_request
+            .room_uuid
+            .and_then(|u| u.try_into().ok())
+            .ok_or(Status::invalid_argument("Invalid room UUID"))?;
+
+        self.authorize(&originator_uuid, &typing_room_uuid, RoomAction::Post)
+            .await?;
+
+        let key = (typing_room_uuid, originator_uuid);
+        let mut deadlines = self.typing_deadlines.lock().await;
+        let was_typing = if typing_request.typing {
+            deadlines
+                .insert(key, Instant::now() + Self::TYPING_TIMEOUT)
+                .is_some()
+        } else {
+            deadlines.remove(&key).is_some()
+        };
+        drop(deadlines);
+
+        // Refreshing the typing state is not worth telling anyone about.
+        if was_typing != typing_request.typing {
+            broadcast(
+                &self.room_event_tx,
+                Self::typing_event(typing_room_uuid, originator_uuid, typing_request.typing),
+            );
+        }
+
+        // Stop the typing state if the client doesn't refresh it in time.
+        if typing_request.typing {
+            let deadlines = Arc::clone(&self.typing_deadlines);
+            let room_event_tx = self.room_event_tx.clone();
+            tokio::spawn(async move {
+                tokio::time::sleep(Self::TYPING_TIMEOUT).await;
+                let mut deadlines = deadlines.lock().await;
+                if deadlines
+                    .get(&key)
+                    .is_some_and(|deadline| *deadline <= Instant::now())
+                {
+                    deadlines.remove(&key);
+                    drop(deadlines);
+                    tracing::trace!(message = "Typing state expired", room = ?key.0, user = ?key.1);
+                    broadcast(&room_event_tx, Self::typing_event(key.0, key.1, false));
+                }
+            });
+        }
+
+        Ok(Response::new(()))
+    }
+
+    #[instrument(skip_all)]
+    async fn create_room(
+        &self,
+        request: Request<ClientsideRoom>,
+    ) -> Result<Response<proto::Uuid>, Status> {
+        let creator = request
+            .get_originator_uuid()
+            .expect("The authenticator should not let anonymous requests through");
+        self.throttle(&creator, LimitedRpc::CreateRoom).await?;
+
+        let room = request.into_inner();
+        let room_uuid = self.create_room(room, creator, None).await?;
+
+        Ok(Response::new(room_uuid.into()))
+    }
+
+    #[instrument(skip_all)]
+    async fn create_room_with_user(
+        &self,
+        request: Request<RoomWithUserCreationRequest>,
+    ) -> Result<Response<proto::Uuid>, Status> {
+        let originator_uuid = request
+            .get_originator_uuid()
+            .map_err(|err| Status::internal(err.to_string()))?;
+        self.throttle(&originator_uuid, LimitedRpc::CreateRoomWithUser)
+            .await?;
+
+        let possible_interlocutor_uuid = request
+            .into_inner()
+            .user_uuid
+            .and_then(|u| Uuid::try_from(u).ok())
+            .ok_or(Status::invalid_argument("Invalid interlocutor UUID"))?;
+
+        if possible_interlocutor_uuid == originator_uuid {
+            return Err(Status::invalid_argument(
+                "You can't create a private chat with yourself",
+            ));
+        }
+
+        let interlocutor = self.room_member(possible_interlocutor_uuid).await?;
+        let originator = self.room_member(originator_uuid).await?;
+        if self.has_blocked(interlocutor.uuid, originator_uuid).await? {
+            tracing::warn!(message = "Blocked user tried to start a private chat", blocker = ?interlocutor.uuid);
+            return Err(Status::permission_denied(
+                "You can't start a private chat with this user",
+            ));
+        }
+
+        // Reuse the existing chat between the two users, if there is one.
+        let direct_pair = Room::direct_pair(originator_uuid, interlocutor.uuid);
+        if let Some(existing_room_uuid) = self.find_direct_room(direct_pair).await? {
+            return self
+                .rejoin_direct_room(existing_room_uuid, direct_pair)
+                .await
+                .map(|()| Response::new(existing_room_uuid.into()));
+        }
+
+        let room_name: String = format!(
+            "Private chat between {} and {}",
+            originator.username, interlocutor.username
+        )
+        .chars()
+        .take(Room::MAX_NAME_LENGTH)
+        .collect();
+        let creation_result = self
+            .create_room(
+                ClientsideRoom {
+                    name: room_name,
+                    members: vec![interlocutor.uuid.into(), originator_uuid.into()],
+                },
+                originator_uuid,
+                Some(direct_pair),
+            )
+            .await;
+
+        // Someone might've created the same chat concurrently, the database won't let both of them in.
+        let private_room_uuid = match creation_result {
+            Err(status) if status.code() == tonic::Code::AlreadyExists => {
+                self.find_direct_room(direct_pair).await?.ok_or(status)?
+            }
+            result => result?,
+        };
+
+        Ok(Response::new(private_room_uuid.into()))
+    }
+
+    type ConnectStream = DisconnectChannel<Result<ServerFrame, Status>>;
+
+    #[instrument(skip_all)]
+    async fn connect(
+        &self,
+        request: Request<Streaming<ClientFrame>>,
+    ) -> Result<Response<Self::ConnectStream>, Status> {
+        let user_uuid: Uuid = request
+            .get_originator_uuid()
+            .expect("The authenticator should not let anonymous requests through");
+        let mut client_frames = request.into_inner();
+
+        let (grpc_tx, grpc_rx) = mpsc::channel(4);
+        let (disconnect_tx, disconnect_rx) = oneshot::channel();
+        let disconnect_channel = DisconnectChannel {
+            disconnect_tx: Some(disconnect_tx),
+            grpc_rx,
+        };
+
+        // The 'streamer' thread (see below) needs a cache connection to check membership and blocks.
+        let mut cache = self.acquire_cache_connection().await?;
+        let mut room_event_rx = self.room_event_tx.subscribe();
+        let mut user_event_rx = self.user_event_tx.subscribe();
+        self.open_user_stream(user_uuid).await;
+        tracing::info!(message = "New connection", ?user_uuid);
+
+        // Unlike the other streams, this one serves both room and user events, as well as
+        // whatever the client sends, all from a single task.
+        let streaming_closure = async move {
+            let mut subscribed_rooms: HashSet<Uuid> = HashSet::new();
+            let mut outbox = Outbox::new(Self::MAX_QUEUED_FRAMES, Self::MAX_UNACKNOWLEDGED_FRAMES);
+
+            loop {
+                // Events keep coming in while the client is behind on acknowledging frames,
+                // only sending them out waits (see `Outbox`), so the receivers never lag.
+                let frame = tokio::select! {
+                    client_frame = client_frames.message() => match client_frame {
+                        Ok(Some(ClientFrame { frame: Some(client_frame) })) => match client_frame {
+                            ClientFrameKind::Subscribe(room) => {
+                                let subscription = Self::subscribe_connection(
+                                    &mut cache,
+                                    user_uuid,
+                                    &mut subscribed_rooms,
+                                    room,
+                                );
+                                Some(subscription.await)
+                            }
+                            ClientFrameKind::Unsubscribe(proto_uuid) => {
+                                if let Ok(room) = Uuid::try_from(proto_uuid) {
+                                    subscribed_rooms.remove(&room);
+                                }
+                                None
+                            }
+                            ClientFrameKind::Ack(acked_sequence) => {
+                                outbox.acknowledge(acked_sequence);
+                                None
+                            }
+                        },
+                        Ok(Some(ClientFrame { frame: None })) => None,
+                        Ok(None) => break,
+                        Err(error) => {
+                            tracing::debug!(message = "A connection failed", ?user_uuid, ?error);
+                            break;
+                        }
+                    },
+
+                    event = room_event_rx.recv() => match event {
+                        Ok(event) => Self::streams_room_event(
+                            &mut cache,
+                            user_uuid,
+                            &subscribed_rooms,
+                            &event,
+                        )
+                        .await
+                        .then_some(ServerFrameKind::RoomEvent(event)),
+                        Err(error) => {
+                            Self::end_lagging_connection(&grpc_tx, user_uuid, error).await;
+                            break;
+                        }
+                    },
+
+                    event = user_event_rx.recv() => match event {
+                        Ok(event) => Self::addressed_user_event(&mut cache, user_uuid, event)
+                            .await
+                            .map(ServerFrameKind::UserEvent),
+                        Err(error) => {
+                            Self::end_lagging_connection(&grpc_tx, user_uuid, error).await;
+                            break;
+                        }
+                    },
+
+                    permit = grpc_tx.reserve(), if outbox.is_ready() => {
+                        let Ok(permit) = permit else {
+                            break;
+                        };
+                        if let Some((sequence, frame)) = outbox.pop() {
+                            permit.send(Ok(ServerFrame {
+                                sequence,
+                                frame: Some(frame),
+                            }));
+                        }
+                        None
+                    }
+                };
+
+                let Some(frame) = frame else {
+                    continue;
+                };
+                if outbox.push(frame).is_err() {
+                    tracing::warn!(
+                        message = "A connection fell behind, closing it",
+                        ?user_uuid,
+                        queued = outbox.len()
+                    );
+                    let _ = grpc_tx
+                        .send(Err(Status::data_loss(
+                            "Too many events went unacknowledged, reconnect to catch up",
+                        )))
+                        .await;
+                    break;
+                }
+            }
+        };
+
+        let token = CancellationToken::new();
+        let token_clone = token.clone();
+
+        // Spawn the "canceller" thread, which also takes the user offline once their last stream closes.
+        let online_users = Arc::clone(&self.online_users);
+        let persistence_pool = self.persistence_pool.clone();
+        let user_event_tx = self.user_event_tx.clone();
+        tokio::spawn(async move {
+            let _ = disconnect_rx.await;
+            tracing::debug!(message = "Client disconnected, closing the connection");
+            token.cancel();
+            Self::close_user_stream(&online_users, &persistence_pool, &user_event_tx, user_uuid)
+                .await;
+        });
+
+        // Spawn the "streamer" thread.
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = token_clone.cancelled() => {}
+                _ = streaming_closure => {}
+            }
+        });
+
+        Ok(Response::new(disconnect_channel))
+    }
+
+    type SubscribeToRoomStream = DisconnectChannel<Result<ServersideRoomEvent, Status>>;
+
+    #[instrument(skip_all)]
+    async fn invite_to_room(
+        &self,
+        request: Request<RoomMembershipRequest>,
+    ) -> Result<Response<()>, Status> {
+        let (inviter_uuid, invited_room_uuid, invitee_uuid) = self
+            .membership_from_request(request, RoomAction::Invite)
+            .await?;
+        self.ensure_group_room(&invited_room_uuid).await?;
+
+        if self
+            .check_room_membership(&invitee_uuid, &invited_room_uuid)
+            .await?
+        {
+            return Err(Status::already_exists(
+                "The user is already a member of this room",
+            ));
+        }
+        if self.has_blocked(invitee_uuid, inviter_uuid).await? {
+            tracing::warn!(message = "Blocked user tried to invite the blocker to a room", blocker = ?invitee_uuid);
+            return Err(Status::permission_denied("You can't invite this user"));
+        }
+
+        self.add_room_member(invited_room_uuid, invitee_uuid)
+            .await?;
+        tracing::info!(message = "User invited to room", inviter = ?inviter_uuid, invitee = ?invitee_uuid, room = ?invited_room_uuid);
+
+        Ok(Response::new(()))
+    }
+
+    #[instrument(skip_all)]
+    async fn kick_from_room(
+        &self,
+        request: Request<RoomMembershipRequest>,
+    ) -> Result<Response<()>, Status> {
+        let (kicker_uuid, room_uuid, kicked_uuid) = self
+            .membership_from_request(request, RoomAction::Kick)
+            .await?;
+        self.ensure_group_room(&room_uuid).await?;
+
+        if kicker_uuid == kicked_uuid {
+            return Err(Status::invalid_argument(
+                "You can't kick yourself, leave the room instead",
+            ));
+        }
+
+        // Admins can't kick each other (or the owner), only members.
+        let kicker_role = self.room_role(&kicker_uuid, &room_uuid).await?;
+        let Some(kicked_role) = self.room_role(&kicked_uuid, &room_uuid).await? else {
+            return Err(Status::not_found("The user is not a member of this room"));
+        };
+        if kicker_role.is_none_or(|kicker_role| kicked_role >= kicker_role) {
+            return Err(Status::permission_denied(
+                "You can only kick members with a lower role than yours",
+            ));
+        }
+
+        self.remove_room_member(room_uuid, kicked_uuid).await?;
+        broadcast(
+            &self.user_event_tx,
+            ServersideUserEvent {
+                user_uuid: Some(kicked_uuid.into()),
+                event: Some(UserEvent::KickedFromRoom(room_uuid.into())),
+            },
+        );
+        tracing::info!(message = "User kicked from room", kicker = ?kicker_uuid, kicked = ?kicked_uuid, room = ?room_uuid);
+
+        Ok(Response::new(()))
+    }
+
+    #[instrument(skip_all)]
+    async fn leave_room(&self, request: Request<proto::Uuid>) -> Result<Response<()>, Status> {
+        let originator_uuid = request
+            .get_originator_uuid()
+            .expect("The authenticator should not let anonymous requests through");
+
+        let left_room_uuid: Uuid = request
+            .into_inner()
+            .try_into()
+            .map_err(|_| Status::invalid_argument("Invalid room UUID"))?;
+
+        self.authorize(&originator_uuid, &left_room_uuid, RoomAction::View)
+            .await?;
+        if self.room_role(&originator_uuid, &left_room_uuid).await? == Some(RoomRole::Owner) {
+            return Err(Status::failed_precondition(
+                "The owner can't leave the room, delete it instead",
+            ));
+        }
+
+        self.remove_room_member(left_room_uuid, originator_uuid)
+            .await?;
+        tracing::info!(message = "User left room", user = ?originator_uuid, room = ?left_room_uuid);
+
+        Ok(Response::new(()))
+    }
+
+    #[instrument(skip_all)]
+    async fn set_member_role(
+        &self,
+        request: Request<MemberRoleRequest>,
+    ) -> Result<Response<()>, Status> {
+        let originator_uuid = request
+            .get_originator_uuid()
+            .expect("The authenticator should not let anonymous requests through");
+
+        let role_request = request.into_inner();
+        let managed_room_uuid: Uuid = role_request
+            .room_uuid
+            .and_then(|u| u.try_into().ok())
+            .ok_or(Status::invalid_argument("Invalid room UUID"))?;
+        let managed_user_uuid: Uuid = role_request
+            .user_uuid
+            .and_then(|u| u.try_into().ok())
+            .ok_or(Status::invalid_argument("Invalid user UUID"))?;
+        let new_role: RoomRole = proto::RoomRole::try_from(role_request.role)
+            .map_err(|_| Status::invalid_argument("Invalid room role"))?
+            .into();
+
+        self.authorize(
+            &originator_uuid,
+            &managed_room_uuid,
+            RoomAction::ManageRoles,
+        )
+        .await?;
+        if new_role == RoomRole::Owner {
+            return Err(Status::invalid_argument(
+                "The ownership of a room can't be given away",
+            ));
+        }
+        if managed_user_uuid == originator_uuid {
+            return Err(Status::invalid_argument("You can't change your own role"));
+        }
+        if self
+            .room_role(&managed_user_uuid, &managed_room_uuid)
+            .await?
+            .is_none()
+        {
+            return Err(Status::not_found("The user is not a member of this room"));
+        }
+
+        let mut db = self.acquire_database_connection().await?;
+
+        use crate::entities::schema::rooms_users::dsl::*;
+        use diesel::prelude::*;
+
+        let _ = diesel::update(rooms_users.find((managed_room_uuid, managed_user_uuid)))
+            .set(role.eq(new_role))
+            .execute(&mut db)
+            .map_err(|error| {
+                tracing::error!(message = "Could not change the member's role!", ?error);
+                Status::internal("Could not change the member's role due to an internal error")
+            })?;
+
+        tracing::info!(message = "Changed the role of a room member", room = ?managed_room_uuid, user = ?managed_user_uuid, role = %new_role);
+
+        Ok(Response::new(()))
+    }
+
+    #[instrument(skip_all)]
+    async fn update_room(
+        &self,
+        request: Request<RoomUpdateRequest>,
+    ) -> Result<Response<()>, Status> {
+        let originator_uuid = request
+            .get_originator_uuid()
+            .expect("The authenticator should not let anonymous requests through");
+
+        let update = request.into_inner();
+        let updated_room_uuid: Uuid = update
+            .room_uuid
+            .and_then(|u| u.try_into().ok())
+            .ok_or(Status::invalid_argument("Invalid room UUID"))?;
+
+        self.authorize(&originator_uuid, &updated_room_uuid, RoomAction::UpdateRoom)
+            .await?;
+
+        let mut db = self.acquire_database_connection().await?;
+
+        use crate::entities::schema::rooms::dsl::*;
+        use diesel::prelude::*;
+
+        let mut room: Room = rooms
+            .find(updated_room_uuid)
+            .select(Room::as_select())
+            .first(&mut db)
+            .map_err(|error| {
+                let msg = "Couldn't fetch the room from database";
+                tracing::error!(message = msg, ?error);
+                Status::internal(msg)
+            })?;
+
+        if let Some(new_name) = update.name {
+            Room::validate_name(&new_name).map_err(Status::invalid_argument)?;
+            room.name = new_name;
+        }
+        if let Some(new_topic) = update.topic {
+            Room::validate_topic(&new_topic).map_err(Status::invalid_argument)?;
+            room.topic = new_topic;
+        }
+        if let Some(new_description) = update.description {
+            Room::validate_description(&new_description).map_err(Status::invalid_argument)?;
+            room.description = new_description;
+        }
+
+        let updated_room: Room = diesel::update(rooms.find(updated_room_uuid))
+            .set((
+                name.eq(room.name),
+                topic.eq(room.topic),
+                description.eq(room.description),
+            ))
+            .returning(Room::as_returning())
+            .get_result(&mut db)
+            .map_err(|error| {
+                tracing::error!(message = "Could not update the room!", ?error);
+                Status::internal("Could not update the room due to an internal error")
+            })?;
+
+        tracing::info!(message = "Updated room", room = ?updated_room_uuid, user = ?originator_uuid);
+
+        broadcast(
+            &self.room_event_tx,
+            ServersideRoomEvent {
+                room_uuid: Some(updated_room_uuid.into()),
+                event: Some(RoomEvent::RoomUpdated(updated_room.into())),
+            },
+        );
+
+        Ok(Response::new(()))
+    }
+
+    #[instrument(skip_all)]
+    async fn delete_room(&self, request: Request<proto::Uuid>) -> Result<Response<()>, Status> {
+        let originator_uuid = request
+            .get_originator_uuid()
+            .expect("The authenticator should not let anonymous requests through");
+
+        let deleted_room_uuid: Uuid = request
+            .into_inner()
+            .try_into()
+            .map_err(|_| Status::invalid_argument("Invalid room UUID"))?;
+
+        self.authorize(&originator_uuid, &deleted_room_uuid, RoomAction::DeleteRoom)
+            .await?;
+
+        let mut db = self.acquire_database_connection().await?;
+        let mut cache = self.acquire_cache_connection().await?;
+
+        use crate::entities::schema::{attachments, message_mentions, message_reactions};
+        use crate::entities::schema::{messages, read_markers};
+        use crate::entities::schema::{rooms, rooms_users};
+        use diesel::prelude::*;
+
+        // Nothing references a room on cascade, so everything has to be deleted by hand.
+        let (members, deleted_attachments): (Vec<Uuid>, Vec<Uuid>) = db
+            .transaction(|db| {
+                let deleted_attachments = diesel::delete(
+                    attachments::table.filter(attachments::room_uuid.eq(deleted_room_uuid)),
+                )
+                .returning(attachments::uuid)
+                .get_results(db)?;
+                let room_messages = messages::table
+                    .filter(messages::room_uuid.eq(deleted_room_uuid))
+                    .select(messages::uuid);
+                diesel::delete(
+                    message_reactions::table
+                        .filter(message_reactions::message_uuid.eq_any(room_messages)),
+                )
+                .execute(db)?;
+                diesel::delete(
+                    message_mentions::table
+                        .filter(message_mentions::message_uuid.eq_any(room_messages)),
+                )
+                .execute(db)?;
+                diesel::delete(
+                    read_markers::table.filter(read_markers::room_uuid.eq(deleted_room_uuid)),
+                )
+                .execute(db)?;
+                diesel::delete(messages::table.filter(messages::room_uuid.eq(deleted_room_uuid)))
+                    .execute(db)?;
+                let members = diesel::delete(
+                    rooms_users::table.filter(rooms_users::room_uuid.eq(deleted_room_uuid)),
+                )
+                .returning(rooms_users::user_uuid)
+                .get_results(db)?;
+                diesel::delete(rooms::table.find(deleted_room_uuid)).execute(db)?;
+
+                diesel::QueryResult::Ok((members, deleted_attachments))
+            })
+            .map_err(|error| {
+                tracing::error!(message = "Could not delete the room!", ?error);
+                Status::internal("Could not delete the room due to an internal error")
+            })?;
+        self.remove_attachment_files(&deleted_attachments).await;
+
+        for member in members {
+            let _: () = cache
+                .lrem(Self::rooms_key(&member), 0, deleted_room_uuid)
+                .await
+                .map_err(|error| {
+                    let msg = "Could not update membership cache";
+                    tracing::error!(message = msg, ?error);
+                    Status::internal(msg)
+                })?;
+            broadcast(
+                &self.user_event_tx,
+                ServersideUserEvent {
+                    user_uuid: Some(member.into()),
+                    event: Some(UserEvent::KickedFromRoom(deleted_room_uuid.into())),
+                },
+            );
+        }
+
+        tracing::info!(message = "Deleted room", room = ?deleted_room_uuid, owner = ?originator_uuid);
+
+        Ok(Response::new(()))
+    }
+
+    #[instrument(skip_all)]
+    async fn set_room_notification_level(
+        &self,
+        request: Request<RoomNotificationSettings>,
+    ) -> Result<Response<()>, Status> {
+        let originator_uuid = request
+            .get_originator_uuid()
+            .expect("The authenticator should not let anonymous requests through");
+
+        let settings = request.into_inner();
+        let configured_room_uuid: Uuid = settings
+            .room_uuid
+            .clone()
+            .and_then(|u| u.try_into().ok())
+            .ok_or(Status::invalid_argument("Invalid room UUID"))?;
+        let new_level: NotificationLevel = proto::NotificationLevel::try_from(settings.level)
+            .map_err(|_| Status::invalid_argument("Invalid notification level"))?
+            .into();
+        let mute_end: Option<SystemTime> = settings
+            .muted_until
+            .clone()
+            .map(SystemTime::try_from)
+            .transpose()
+            .map_err(|_| Status::invalid_argument("Invalid end of the mute"))?;
+        if let Some(mute_end) = mute_end {
+            if new_level != NotificationLevel::Muted {
+                return Err(Status::invalid_argument(
+                    "Only muted rooms can have an end of the mute",
+                ));
+            }
+            if mute_end <= SystemTime::now() {
+                return Err(Status::invalid_argument("The mute would already be over"));
+            }
+        }
+
+        self.authorize(&originator_uuid, &configured_room_uuid, RoomAction::View)
+            .await?;
+
+        let mut db = self.acquire_database_connection().await?;
+
+        use crate::entities::schema::rooms_users::dsl::*;
+        use diesel::prelude::*;
+
+        let _ = diesel::update(rooms_users.find((configured_room_uuid, originator_uuid)))
+            .set((notification_level.eq(new_level), muted_until.eq(mute_end)))
+            .execute(&mut db)
+            .map_err(|error| {
+                let msg = "Couldn't save the notification settings";
+                tracing::error!(message = msg, ?error);
+                Status::internal(msg)
+            })?;
+
+        tracing::info!(message = "Changed notification settings", user = ?originator_uuid, room = ?configured_room_uuid, level = %new_level, ?mute_end);
+
+        // Let the user's other devices know.
+        broadcast(
+            &self.user_event_tx,
+            ServersideUserEvent {
+                user_uuid: Some(originator_uuid.into()),
+                event: Some(UserEvent::NotificationsChanged(settings)),
+            },
+        );
+
+        Ok(Response::new(()))
+    }
+
+    #[instrument(skip_all)]
+    async fn get_presence(
+        &self,
+        request: Request<proto::Uuid>,
+    ) -> Result<Response<Presence>, Status> {
+        let originator_uuid = request
+            .get_originator_uuid()
+            .expect("The authenticator should not let anonymous requests through");
+
+        let requested_user: Uuid = request
+            .into_inner()
+            .try_into()
+            .map_err(|_| Status::invalid_argument("Invalid user UUID"))?;
+
+        // Presence is only shared with the people who would be sent its changes.
+        if requested_user != originator_uuid {
+            let mut cache = self.acquire_cache_connection().await?;
+            if !Self::shares_room(&mut cache, originator_uuid, requested_user).await
+                || Self::hides_author(&mut cache, originator_uuid, Some(requested_user)).await
+            {
+                return Err(Status::permission_denied(
+                    "You don't share a room with this user",
+                ));
+            }
+        }
+
+        let mut db = self.acquire_database_connection().await?;
+
+        use crate::entities::schema::users::dsl::*;
+        use diesel::prelude::*;
+
+        let user_last_seen: Option<SystemTime> = users
+            .find(requested_user)
+            .select(last_seen)
+            .first(&mut db)
+            .optional()
+            .map_err(|error| {
+                let msg = "Couldn't fetch the user from database";
+                tracing::error!(message = msg, ?error);
+                Status::internal(msg)
+            })?
+            .ok_or(Status::not_found("No such user"))?;
+
+        let online = self.online_users.lock().await.contains_key(&requested_user);
+
+        Ok(Response::new(Presence {
+            user_uuid: Some(requested_user.into()),
+            online,
+            last_seen: user_last_seen.filter(|_| !online).map(Into::into),
+        }))
+    }
+
+    #[instrument(skip_all)]
+    async fn get_profile(
+        &self,
+        request: Request<proto::Uuid>,
+    ) -> Result<Response<proto::Profile>, Status> {
+        let requested_user: Uuid = request
+            .into_inner()
+            .try_into()
+            .map_err(|_| Status::invalid_argument("Invalid user UUID"))?;
+
+        self.user_profile(requested_user).await.map(Response::new)
+    }
+
+    #[instrument(skip_all)]
+    async fn update_profile(
+        &self,
+        request: Request<ProfileUpdateRequest>,
+    ) -> Result<Response<proto::Profile>, Status> {
+        let originator_uuid = request
+            .get_originator_uuid()
+            .expect("The authenticator should not let anonymous requests through");
+        self.throttle(&originator_uuid, LimitedRpc::UpdateProfile)
+            .await?;
+
+        let update = request.into_inner();
+        let mut db = self.acquire_database_connection().await?;
+
+        use crate::entities::schema::profiles::dsl::*;
+        use diesel::prelude::*;
+
+        if let Some(new_display_name) = &update.display_name {
+            Profile::validate_display_name(new_display_name).map_err(Status::invalid_argument)?;
+        }
+        if let Some(new_bio) = &update.bio {
+            Profile::validate_bio(new_bio).map_err(Status::invalid_argument)?;
+        }
+        if let Some(new_status_text) = &update.status_text {
+            Profile::validate_status_text(new_status_text).map_err(Status::invalid_argument)?;
+        }
+        if let Some(new_status_emoji) = &update.status_emoji {
+            Profile::validate_status_emoji(new_status_emoji).map_err(Status::invalid_argument)?;
+        }
+        if let Some(new_pronouns) = &update.pronouns {
+            Profile::validate_pronouns(new_pronouns).map_err(Status::invalid_argument)?;
+        }
+        if let Some(new_timezone) = &update.timezone {
+            Profile::validate_timezone(new_timezone).map_err(Status::invalid_argument)?;
+        }
+
+        // Only the fields that were sent get written, so concurrent updates of different
+        // fields don't overwrite each other.
+        let profile_update = ProfileUpdate {
+            user_uuid: originator_uuid,
+            display_name: update.display_name,
+            bio: update.bio,
+            status_text: update.status_text,
+            status_emoji: update.status_emoji,
+            pronouns: update.pronouns,
+            timezone: update.timezone,
+            updated_at: SystemTime::now(),
+        };
+        diesel::insert_into(profiles)
+            .values(&profile_update)
+            .on_conflict(user_uuid)
+            .do_update()
+            .set(&profile_update)
+            .execute(&mut db)
+            .map_err(|error| {
+                tracing::error!(message = "Could not update the profile!", ?error);
+                Status::internal("Could not update the profile due to an internal error")
+            })?;
+        tracing::info!(message = "Updated profile", user = ?originator_uuid);
+
+        let updated_profile = self.user_profile(originator_uuid).await?;
+        self.broadcast_profile(originator_uuid, &updated_profile);
+
+        Ok(Response::new(updated_profile))
+    }
+
+    #[instrument(skip_all)]
+    async fn block_user(&self, request: Request<proto::Uuid>) -> Result<Response<()>, Status> {
+        let originator_uuid = request
+            .get_originator_uuid()
+            .expect("The authenticator should not let anonymous requests through");
+
+        let blocked_user: Uuid = request
+            .into_inner()
+            .try_into()
+            .map_err(|_| Status::invalid_argument("Invalid user UUID"))?;
+        if blocked_user == originator_uuid {
+            return Err(Status::invalid_argument("You can't block yourself"));
+        }
+        self.room_member(blocked_user).await?;
+
+        let mut db = self.acquire_database_connection().await?;
+        let mut cache = self.acquire_cache_connection().await?;
+
+        use crate::entities::schema::user_blocks::dsl::*;
+        use diesel::prelude::*;
+
+        let _ = diesel::insert_into(user_blocks)
+            .values(&UserBlock::new(originator_uuid, blocked_user))
+            .on_conflict_do_nothing()
+            .execute(&mut db)
+            .map_err(|error| {
+                tracing::error!(message = "Could not save the block!", ?error);
+                Status::internal("Could not block the user due to an internal error")
+            })?;
+        let _: () = cache
+            .sadd(Self::blocks_key(&originator_uuid), blocked_user)
+            .await
+            .map_err(|error| {
+                let msg = "Could not update the block cache";
+                tracing::error!(message = msg, ?error);
+                Status::internal(msg)
+            })?;
+        tracing::info!(message = "User blocked another user", blocker = ?originator_uuid, blocked = ?blocked_user);
+
+        Ok(Response::new(()))
+    }
+
+    #[instrument(skip_all)]
+    async fn unblock_user(&self, request: Request<proto::Uuid>) -> Result<Response<()>, Status> {
+        let originator_uuid = request
+            .get_originator_uuid()
+            .expect("The authenticator should not let anonymous requests through");
+
+        let unblocked_user: Uuid = request
+            .into_inner()
+            .try_into()
+            .map_err(|_| Status::invalid_argument("Invalid user UUID"))?;
+
+        let mut db = self.acquire_database_connection().await?;
+        let mut cache = self.acquire_cache_connection().await?;
+
+        use crate::entities::schema::user_blocks::dsl::*;
+        use diesel::prelude::*;
+
+        let deleted_count = diesel::delete(user_blocks.find((originator_uuid, unblocked_user)))
+            .execute(&mut db)
+            .map_err(|error| {
+                tracing::error!(message = "Could not delete the block!", ?error);
+                Status::internal("Could not unblock the user due to an internal error")
+            })?;
+        if deleted_count == 0 {
+            return Err(Status::not_found("The user is not blocked"));
+        }
+        let _: () = cache
+            .srem(Self::blocks_key(&originator_uuid), unblocked_user)
+            .await
+            .map_err(|error| {
+                let msg = "Could not update the block cache";
+                tracing::error!(message = msg, ?error);
+                Status::internal(msg)
+            })?;
+        tracing::info!(message = "User unblocked another user", blocker = ?originator_uuid, unblocked = ?unblocked_user);
+
+        Ok(Response::new(()))
+    }
+
+    #[instrument(skip_all)]
+    async fn list_blocked_users(&self, request: Request<()>) -> Result<Response<UserList>, Status> {
+        let originator_uuid = request
+            .get_originator_uuid()
+            .expect("The authenticator should not let anonymous requests through");
+
+        let mut db = self.acquire_database_connection().await?;
+
+        use crate::entities::schema::{user_blocks, users};
+        use diesel::prelude::*;
+
+        let blocked_users: Vec<User> = user_blocks::table
+            .inner_join(users::table.on(users::uuid.eq(user_blocks::blocked_uuid)))
+            .filter(user_blocks::blocker_uuid.eq(originator_uuid))
+            .order((user_blocks::blocked_at.desc(), users::uuid.asc()))
+            .select(User::as_select())
+            .load(&mut db)
+            .map_err(|error| {
+                let msg = "Couldn't fetch blocked users from database";
+                tracing::error!(message = msg, ?error);
+                Status::internal(msg)
+            })?;
+
+        Ok(Response::new(UserList {
+            users: blocked_users.into_iter().map(Into::into).collect(),
+        }))
+    }
+
+    #[instrument(skip_all)]
+    async fn logout(&self, request: Request<()>) -> Result<Response<()>, Status> {
+        let originator_uuid = request
+            .get_originator_uuid()
+            .expect("The authenticator should not let anonymous requests through");
+        let current_session = request
+            .get_session_uuid()
+            .expect("The authenticator should attach a session to every request");
+
+        self.end_session(originator_uuid, current_session).await?;
+        tracing::info!(message = "User logged out", user = ?originator_uuid);
+
+        Ok(Response::new(()))
+    }
+
+    #[instrument(skip_all)]
+    async fn list_sessions(&self, request: Request<()>) -> Result<Response<SessionList>, Status> {
+        let originator_uuid = request
+            .get_originator_uuid()
+            .expect("The authenticator should not let anonymous requests through");
+        let current_session = request
+            .get_session_uuid()
+            .expect("The authenticator should attach a session to every request");
+
+        let mut db = self.acquire_database_connection().await?;
+
+        use crate::entities::schema::sessions::dsl::*;
+        use diesel::prelude::*;
+
+        let user_sessions: Vec<Session> = sessions
+            .filter(user_uuid.eq(originator_uuid))
+            .filter(expires_at.gt(SystemTime::now()))
+            .order((last_used_at.desc(), uuid.asc()))
+            .select(Session::as_select())
+            .load(&mut db)
+            .map_err(|error| {
+                let msg = "Couldn't fetch sessions from database";
+                tracing::error!(message = msg, ?error);
+                Status::internal(msg)
+            })?;
+
+        let sessions_list = user_sessions
+            .into_iter()
+            .map(|session| {
+                let current = session.uuid == current_session;
+                proto::Session {
+                    current,
+                    ..session.into()
+                }
+            })
+            .collect();
+
+        Ok(Response::new(SessionList {
+            sessions: sessions_list,
+        }))
+    }
+
+    #[instrument(skip_all)]
+    async fn revoke_session(&self, request: Request<proto::Uuid>) -> Result<Response<()>, Status> {
+        let originator_uuid = request
+            .get_originator_uuid()
+            .expect("The authenticator should not let anonymous requests through");
+
+        let revoked_session: Uuid = request
+            .into_inner()
+            .try_into()
+            .map_err(|_| Status::invalid_argument("Invalid session UUID"))?;
+
+        if !self.end_session(originator_uuid, revoked_session).await? {
+            return Err(Status::not_found("No such session"));
+        }
+        tracing::info!(message = "Session revoked", user = ?originator_uuid, session = ?revoked_session);
+
+        Ok(Response::new(()))
+    }
+
+    #[instrument(skip_all)]
+    async fn change_password(
+        &self,
+        request: Request<PasswordChangeRequest>,
+    ) -> Result<Response<()>, Status> {
+        let originator_uuid = request
+            .get_originator_uuid()
+            .expect("The authenticator should not let anonymous requests through");
+        let current_session = request
+            .get_session_uuid()
+            .expect("The authenticator should attach a session to every request");
+        self.throttle(&originator_uuid, LimitedRpc::ChangePassword)
+            .await?;
+
+        let PasswordChangeRequest {
+            old_password,
+            new_password,
+        } = request.into_inner();
+        if new_password.is_empty() {
+            return Err(Status::invalid_argument("The new password can't be empty"));
+        }
+        self.verify_password(originator_uuid, old_password).await?;
+        let new_hash = Registry::hash_password(new_password).await?;
+
+        let mut db = self.acquire_database_connection().await?;
+
+        use crate::entities::schema::{sessions, users};
+        use diesel::prelude::*;
+
+        // Whoever might've learned the old password shouldn't stay logged in with it.
+        let ended_sessions = db
+            .transaction(|db| {
+                diesel::update(users::table.find(originator_uuid))
+                    .set(users::password.eq(new_hash))
+                    .execute(db)?;
+                diesel::delete(
+                    sessions::table
+                        .filter(sessions::user_uuid.eq(originator_uuid))
+                        .filter(sessions::uuid.ne(current_session)),
+                )
+                .execute(db)
+            })
+            .map_err(|error| {
+                let msg = "Couldn't store the new password in database";
+                tracing::error!(message = msg, ?error);
+                Status::internal(msg)
+            })?;
+        tracing::info!(message = "User changed their password", user = ?originator_uuid, %ended_sessions);
+
+        Ok(Response::new(()))
+    }
+
+    #[instrument(skip_all)]
+    async fn change_username(
+        &self,
+        request: Request<UsernameChangeRequest>,
+    ) -> Result<Response<()>, Status> {
+        let originator_uuid = request
+            .get_originator_uuid()
+            .expect("The authenticator should not let anonymous requests through");
+        self.throttle(&originator_uuid, LimitedRpc::ChangeUsername)
+            .await?;
+
+        let new_username = request.into_inner().username;
+        User::validate_username(&new_username).map_err(Status::invalid_argument)?;
+
+        let mut db = self.acquire_database_connection().await?;
+
+        use crate::entities::schema::users::dsl::*;
+        use diesel::prelude::*;
+        use diesel::result::{DatabaseErrorKind, Error::DatabaseError};
+
+        // Usernames are unique in the database itself, so a concurrent rename or registration
+        // can't sneak in between a check and the update.
+        diesel::update(users.find(originator_uuid))
+            .set(username.eq(&new_username))
+            .execute(&mut db)
+            .map_err(|error| match error {
+                DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+                    let msg = "Such user already exists";
+                    tracing::warn!(message = msg, username = ?new_username);
+                    Status::already_exists(msg)
+                }
+                error => {
+                    let msg = "Couldn't store the new username in database";
+                    tracing::error!(message = msg, ?error);
+                    Status::internal(msg)
+                }
+            })?;
+        tracing::info!(message = "User changed their username", user = ?originator_uuid, username = ?new_username);
+
+        let updated_profile = self.user_profile(originator_uuid).await?;
+        self.broadcast_profile(originator_uuid, &updated_profile);
+
+        Ok(Response::new(()))
+    }
+
+    #[instrument(skip_all)]
+    async fn delete_account(
+        &self,
+        request: Request<AccountDeletionRequest>,
+    ) -> Result<Response<()>, Status> {
+        let originator_uuid = request
+            .get_originator_uuid()
+            .expect("The authenticator should not let anonymous requests through");
+        self.throttle(&originator_uuid, LimitedRpc::DeleteAccount)
+            .await?;
+
+        let typed_password = request.into_inner().password;
+        self.verify_password(originator_uuid, typed_password)
+            .await?;
+
+        let deleted_user = self.room_member(originator_uuid).await?;
+        let mut db = self.acquire_database_connection().await?;
+        let mut cache = self.acquire_cache_connection().await?;
+
+        use crate::entities::schema::{attachments, message_mentions, message_reactions};
+        use crate::entities::schema::{messages, read_markers};
+        use crate::entities::schema::{profiles, rooms, rooms_users, sessions, user_blocks, users};
+        use diesel::prelude::*;
+
+        // Nothing references a user on cascade either, so everything is either handed
+        // over to the placeholder account or deleted by hand.
+        let (left_rooms, blockers, deleted_attachments): (Vec<Uuid>, Vec<Uuid>, Vec<Uuid>) = db
+            .transaction(|db| {
+                let deleted_attachments = diesel::delete(
+                    attachments::table
+                        .filter(attachments::uploader_uuid.eq(originator_uuid))
+                        .filter(attachments::message_uuid.is_null()),
+                )
+                .returning(attachments::uuid)
+                .get_results(db)?;
+                diesel::update(
+                    attachments::table.filter(attachments::uploader_uuid.eq(originator_uuid)),
+                )
+                .set(attachments::uploader_uuid.eq(User::DELETED_UUID))
+                .execute(db)?;
+                diesel::update(messages::table.filter(messages::sender_uuid.eq(originator_uuid)))
+                    .set(messages::sender_uuid.eq(User::DELETED_UUID))
+                    .execute(db)?;
+                diesel::delete(
+                    message_reactions::table
+                        .filter(message_reactions::user_uuid.eq(originator_uuid)),
+                )
+                .execute(db)?;
+                diesel::delete(
+                    message_mentions::table.filter(message_mentions::user_uuid.eq(originator_uuid)),
+                )
+                .execute(db)?;
+                diesel::delete(
+                    read_markers::table.filter(read_markers::user_uuid.eq(originator_uuid)),
+                )
+                .execute(db)?;
+                diesel::delete(sessions::table.filter(sessions::user_uuid.eq(originator_uuid)))
+                    .execute(db)?;
+                diesel::delete(profiles::table.find(originator_uuid)).execute(db)?;
+                diesel::delete(
+                    user_blocks::table.filter(user_blocks::blocker_uuid.eq(originator_uuid)),
+                )
+                .execute(db)?;
+                let blockers: Vec<Uuid> = diesel::delete(
+                    user_blocks::table.filter(user_blocks::blocked_uuid.eq(originator_uuid)),
+                )
+                .returning(user_blocks::blocker_uuid)
+                .get_results(db)?;
+
+                // A direct room can't be between a user and nobody, so it becomes a group room,
+                // and the other user may then start a new chat with whoever takes the username.
+                diesel::update(
+                    rooms::table.filter(
+                        rooms::direct_user_low
+                            .eq(originator_uuid)
+                            .or(rooms::direct_user_high.eq(originator_uuid)),
+                    ),
+                )
+                .set((
+                    rooms::kind.eq(RoomKind::Group),
+                    rooms::direct_user_low.eq(None::<Uuid>),
+                    rooms::direct_user_high.eq(None::<Uuid>),
+                ))
+                .execute(db)?;
+                let inherited_rooms = diesel::sql_query(Self::INHERIT_OWNERSHIP_QUERY)
+                    .bind::<diesel::sql_types::Uuid, _>(originator_uuid)
+                    .execute(db)?;
+                tracing::debug!(message = "Handed over owned rooms", %inherited_rooms);
+                let left_rooms = diesel::delete(
+                    rooms_users::table.filter(rooms_users::user_uuid.eq(originator_uuid)),
+                )
+                .returning(rooms_users::room_uuid)
+                .get_results(db)?;
+                diesel::delete(users::table.find(originator_uuid)).execute(db)?;
+
+                diesel::QueryResult::Ok((left_rooms, blockers, deleted_attachments))
+            })
+            .map_err(|error| {
+                tracing::error!(message = "Could not delete the account!", ?error);
+                Status::internal("Could not delete the account due to an internal error")
+            })?;
+        self.remove_attachment_files(&deleted_attachments).await;
+
+        let _: () = cache
+            .del(&[
+                Self::rooms_key(&originator_uuid),
+                Self::blocks_key(&originator_uuid),
+            ])
+            .await
+            .map_err(|error| {
+                let msg = "Could not update membership cache";
+                tracing::error!(message = msg, ?error);
+                Status::internal(msg)
+            })?;
+        for blocker in blockers {
+            let _: () = cache
+                .srem(Self::blocks_key(&blocker), originator_uuid)
+                .await
+                .map_err(|error| {
+                    let msg = "Could not update the block cache";
+                    tracing::error!(message = msg, ?error);
+                    Status::internal(msg)
+                })?;
+        }
+        for room in left_rooms {
+            broadcast(
+                &self.room_event_tx,
+                ServersideRoomEvent {
+                    room_uuid: Some(room.into()),
+                    event: Some(RoomEvent::UserLeft(deleted_user.clone().into())),
+                },
+            );
+        }
+
+        tracing::info!(message = "Deleted account", user = ?originator_uuid, username = ?deleted_user.username);
+
+        Ok(Response::new(()))
+    }
+
+    #[instrument(skip_all)]
+    async fn subscribe_to_room(
+        &self,
+        request: Request<proto::Uuid>,
+    ) -> Result<Response<Self::SubscribeToRoomStream>, Status> {
+        let subscriber: Uuid = request
+            .get_originator_uuid()
+            .expect("The authenticator should not let anonymous requests through");
+
+        let subscribed_room: Uuid = request.into_inner().try_into().map_err(|error| {
+            let msg = "The room UUID is invalid";
+            tracing::trace!(message = msg, ?error);
+            Status::invalid_argument(msg)
+        })?;
+
+        // Ensure the user is a member of the room he's subscribing to.
+        self.authorize(&subscriber, &subscribed_room, RoomAction::View)
+            .await?;
+
+        // The 'streamer' thread (see below) needs a cache connection.
+        let mut cache = self.acquire_cache_connection().await?;
+
+        // NOTE: Read this.
+        //
+        // There are a total of 3 channels involved in this whole streaming thing:
+        // - An internal `broadcast` channel that transfers events from `SendMessage` and other RPC calls;
+        // - A `DisconnectChannel`, which holds another 2 channels inside:
+        //   - A `mpsc` Tokio channel, which performs gRPC streaming;
+        //   - A `oneshot` Tokio channel, which fires when the client disconnects.
+
+        let (grpc_tx, grpc_rx) = mpsc::channel(4);
+        let (disconnect_tx, disconnect_rx) = oneshot::channel();
+        let disconnect_channel = channel::DisconnectChannel {
+            disconnect_tx: Some(disconnect_tx),
+            grpc_rx,
+        };
+
+        let mut room_event_rx = self.room_event_tx.subscribe();
+        tracing::info!(message = "New room subscriber", room = ?subscribed_room);
+
+        // The logic for the streaming thread, extracted into a variable to help rustfmt.
+        let streaming_closure = async move {
+            let subscribed_rooms = HashSet::from([subscribed_room]);
+            loop {
+                let event = match room_event_rx.recv().await {
+                    Ok(event) => event,
+                    Err(error) => {
+                        Self::end_lagging_connection(&grpc_tx, subscriber, error).await;
+                        break;
+                    }
+                };
+                if !Self::streams_room_event(&mut cache, subscriber, &subscribed_rooms, &event)
+                    .await
+                {
+                    continue;
+                }
+
+                let send_result = grpc_tx.send(Ok(event)).await;
+                if send_result.is_err() {
+                    tracing::warn!(
+                        message = "A message was sent, but nobody is subscribed to the channel"
+                    )
+                }
+            }
+        };
+
+        // The 'canceller' thread will cancel this token when the client disconnects.
+        let token = CancellationToken::new();
+        let token_clone = token.clone();
+
+        // This is the 'canceller' thread.
+        //
+        // This task will cancel the token when the client disconnects, which will shutdown
+        // the streaming thread (see below) and cause the broadcast::Receiver to drop.
+        tokio::spawn(async move {
+            let _ = disconnect_rx.await;
+            tracing::debug!(message = "Client disconnected, stopping message streaming");
+            token.cancel();
+        });
+
+        // This is the 'streamer' thread.
+        //
+        // This thread will receive all room events (such as messages sent via the `SendMessage`
+        // RPC call), and mirror them to all subsribers. Without a canceller thread, a cancellation token
+        // and a hacky DisconnectChannel, this thread would never terminate, meaning there
+        // would soon be a thousand of hanging broadcast::Receivers with no real client.
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = token_clone.cancelled() => {}
+                _ = streaming_closure => {}
+            }
+        });
+
+        Ok(Response::new(disconnect_channel))
+    }
+
+    type SubscribeToUserStream = DisconnectChannel<Result<ServersideUserEvent, Status>>;
+
+    #[instrument(skip_all)]
+    async fn subscribe_to_user(
+        &self,
+        request: Request<()>,
+    ) -> Result<Response<Self::SubscribeToUserStream>, Status> {
+        let user_uuid: Uuid = request
+            .get_originator_uuid()
+            .expect("The authenticator should not let anonymous requests through");
+
+        let (grpc_tx, grpc_rx) = mpsc::channel(4);
+        let (disconnect_tx, disconnect_rx) = oneshot::channel();
+        let disconnect_channel = DisconnectChannel {
+            disconnect_tx: Some(disconnect_tx),
+            grpc_rx,
+        };
+
+        // The 'streamer' thread (see below) needs a cache connection to filter out blocked users.
+        let mut cache = self.acquire_cache_connection().await?;
+        let mut user_event_rx = self.user_event_tx.subscribe();
+        self.open_user_stream(user_uuid).await;
+
+        let streaming_closure = async move {
+            loop {
+                let event = match user_event_rx.recv().await {
+                    Ok(event) => event,
+                    Err(error) => {
+                        Self::end_lagging_connection(&grpc_tx, user_uuid, error).await;
+                        break;
+                    }
+                };
+                let Some(event) = Self::addressed_user_event(&mut cache, user_uuid, event).await
+                else {
+                    continue;
+                };
+
+                let send_result = grpc_tx.send(Ok(event)).await;
+                if send_result.is_err() {
+                    tracing::trace!(message = "A user event occurred, but nobody is subscribed")
+                }
+            }
+        };
+
+        let token = CancellationToken::new();
+        let token_clone = token.clone();
+
+        // Spawn the "canceller" thread, which also takes the user offline once their last stream closes.
+        let online_users = Arc::clone(&self.online_users);
+        let persistence_pool = self.persistence_pool.clone();
+        let user_event_tx = self.user_event_tx.clone();
+        tokio::spawn(async move {
+            let _ = disconnect_rx.await;
+            tracing::debug!(message = "Client disconnected, stopping user event streaming");
+            token.cancel();
+            Self::close_user_stream(&online_users, &persistence_pool, &user_event_tx, user_uuid)
+                .await;
+        });
+
+        // Spawn the "streamer" thread.
+        tokio::spawn(async move {
+            tokio::select! {
+                _ = token_clone.cancelled() => {}
+                _ = streaming_closure => {}
+            }
+        });
+
+        Ok(Response::new(disconnect_channel))
+    }
+
+    #[instrument(skip_all, fields(user_uuid, room_uuid))]
+    async fn analyze_room(
+        &self,
+        request: Request<proto::Uuid>,
+    ) -> Result<Response<proto::RoomAnalysisResponse>, Status> {
+        let req_originator: Uuid = request
+            .get_originator_uuid()
+            .expect("The authenticator should not let anonymous requests through");
+        tracing::Span::current().record("user_uuid", req_originator.to_string());
+        self.throttle(&req_originator, LimitedRpc::AnalyzeRoom)
+            .await?;
+
+        let req_room_uuid: Uuid = request
+            .into_inner()
+            .try_into()
+            .map_err(|_| Status::invalid_argument("Invalid room UUID"))?;
+        tracing::Span::current().record("room_uuid", req_room_uuid.to_string());
+
+        self.authorize(&req_originator, &req_room_uuid, RoomAction::View)
+            .await?;
+
+        tracing::trace!(message = "Collecting messages for an LLM analysis");
+
+        let mut db = self.acquire_database_connection().await?;
+
+        let messages: Vec<Message> = {
+            use crate::entities::schema::messages::dsl::*;
+            use diesel::prelude::*;
+
+            // Deleted messages have no text, and shouldn't be shown to the LLM at all.
+            messages
+                .filter(room_uuid.eq(req_room_uuid))
+                .filter(deleted_at.is_null())
+                .order(timestamp.asc())
+                .select(Message::as_select())
+                .load::<Message>(&mut db)
+                .map_err(|error| {
+                    let message = "Couldn't load messages from the database";
+                    tracing::error!(message = message, ?error);
+                    Status::internal(message)
+                })?
+        };
+
+        let usernames: HashMap<Uuid, String> = messages
+            .iter()
+            .map(|message| message.sender_uuid)
+            .unique()
+            .map(|user_uuid| {
+                use crate::entities::schema::users::dsl::*;
+                use diesel::prelude::*;
+
+                (
+                    user_uuid,
+                    users
+                        .find(user_uuid)
+                        .select(username)
+                        .first(&mut db)
+                        .expect("Foreign key can't point to a non-existing primary key"),
+                )
+            })
+            .collect();
+
+        let formatted_messages: Vec<String> = messages
+            .into_iter()
+            .map(|message| {
+                let username = usernames
+                    .get(&message.sender_uuid)
+                    .cloned()
+                    .unwrap_or_default();
+                format!("\n{username}: {}", message.text)
+            })
+            .collect();
+
+        // Connect to the running LLM.
+        let expect_message = "$LLM_HOST and $LLM_PORT should point to an LLM instance!";
+        let host = format!("http://{}", env::var("LLM_HOST").expect(expect_message));
+        let port = env::var("LLM_PORT")
+            .expect(expect_message)
+            .parse::<u16>()
+            .expect(expect_message);
+        let llm = Ollama::new(host, port);
+
+        tracing::trace!(message = "Connected to LLM, forming a prompt");
+
+        let model = "llama3".to_string();
+        let mut prompt = r#"
+            Hey. Here are a bunch of messages from a chat room, along with the user's names.
+            I want you to analyze the messages and figure out what topics were discussed, as
+            well as look for pattterns in the messages and provide insignt into the users'
+            mood and intentions. Provide additional information about the same things the users
+            were talking about. Your response will be used as a mean of improving the users'
+            experience, and also be a part of a real-time behavioral analysis. Stay concise,
+            go straight to the point, no introductions. Here are the messages:
+        "#
+        .to_string();
+
+        for message in formatted_messages.iter() {
+            prompt.push_str(message);
+        }
+
+        tracing::trace!(message = "Awaiting LLM response...");
+
+        let response = llm
+            .generate(GenerationRequest::new(model, prompt))
+            .await
+            .map_err(|error| {
+                let message = "Local LLM returned an error";
+                tracing::error!(message = message, ?error);
+                Status::internal(message)
+            })?
+            .response;
+
+        tracing::info!(message = "LLM analysis complete");
+
+        Ok(Response::new(proto::RoomAnalysisResponse { response }))
+    }
+}
+
+impl Chat {
+    const INTERNAL_CHANNEL_CAPACITY: usize = 16;
+    const DEFAULT_PAGE_SIZE: u32 = 50;
+    const MAX_PAGE_SIZE: u32 = 200;
+    const TYPING_TIMEOUT: Duration = Duration::from_secs(5);
+    const ATTACHMENT_SWEEP_INTERVAL: Duration = Duration::from_secs(10 * 60);
+    /// How many frames a `Connect` stream may have in flight before the client acknowledges them.
+    const MAX_UNACKNOWLEDGED_FRAMES: u64 = 64;
+    /// How many frames a `Connect` stream may hold back while waiting for acknowledgements.
+    const MAX_QUEUED_FRAMES: usize = 1024;
+    /// The prefixes of the cache keys that are thrown away and rebuilt from the database on startup.
+    pub(crate) const REBUILT_KEY_PREFIXES: [&'static str; 2] = ["rooms:", "blocks:"];
+    /// How many stale cache keys are deleted with a single command on startup.
+    const CACHE_CLEAR_BATCH_SIZE: usize = 512;
+
+    /// Ranks the non-deleted messages of some rooms against a `websearch_to_tsquery` query,
+    /// optionally filtered by sender and time range, and continues after a [`SearchCursor`].
+    const SEARCH_QUERY: &'static str = "
+        SELECT hits.*,
+               ts_headline('simple', hits.text, websearch_to_tsquery('simple', $1),
+                           'StartSel=\"**\", StopSel=\"**\", MaxFragments=2') AS snippet
+        FROM (
+            SELECT m.uuid, m.sender_uuid, m.room_uuid, m.text, m.timestamp,
+                   m.edited_at, m.deleted_at, m.reply_to,
+                   ts_rank(m.search_vector, websearch_to_tsquery('simple', $1)) AS rank
+            FROM messages AS m
+            WHERE m.search_vector @@ websearch_to_tsquery('simple', $1)
+              AND m.deleted_at IS NULL
+              AND m.room_uuid = ANY($2)
+              AND ($3::uuid IS NULL OR m.sender_uuid = $3)
+              AND ($4::timestamp IS NULL OR m.timestamp >= $4)
+              AND ($5::timestamp IS NULL OR m.timestamp < $5)
+        ) AS hits
+        WHERE $6::real IS NULL OR (hits.rank, hits.uuid) < ($6, $7::uuid)
+        ORDER BY hits.rank DESC, hits.uuid DESC
+        LIMIT $8";
+
+    /// Makes someone else the owner of every room a user owns, preferring admins, and then
+    /// whoever has spoken in the room first (rooms don't remember when members joined).
+    const INHERIT_OWNERSHIP_QUERY: &'static str = "
+        UPDATE rooms_users AS ru
+        SET role = 'owner'
+        FROM (
+            SELECT DISTINCT ON (member.room_uuid) member.room_uuid, member.user_uuid
+            FROM rooms_users AS member
+            JOIN rooms_users AS owner
+                ON owner.room_uuid = member.room_uuid
+               AND owner.user_uuid = $1
+               AND owner.role = 'owner'
+            LEFT JOIN messages AS m
+                ON m.room_uuid = member.room_uuid AND m.sender_uuid = member.user_uuid
+            WHERE member.user_uuid <> $1
+            GROUP BY member.room_uuid, member.user_uuid, member.role
+            ORDER BY member.room_uuid, member.role = 'admin' DESC,
+                     MIN(m.timestamp) ASC NULLS LAST, member.user_uuid
+        ) AS heirs
+        WHERE ru.room_uuid = heirs.room_uuid AND ru.user_uuid = heirs.user_uuid";
+
+    /// Upserts a read marker, only replacing an existing one that points at an older message.
+    const MARK_READ_QUERY: &'static str = "
+        INSERT INTO read_markers AS rm (user_uuid, room_uuid, message_uuid)
+        VALUES ($1, $2, $3)
+        ON CONFLICT (user_uuid, room_uuid) DO UPDATE
+        SET message_uuid = EXCLUDED.message_uuid
+        WHERE EXISTS (
+            SELECT 1 FROM messages AS m
+            WHERE m.uuid = rm.message_uuid
+              AND (m.timestamp, m.uuid) < ($4::timestamp, $3::uuid)
+        )";
+
+    pub async fn new(persistence_pool: persistence::ConnectionPool) -> RedisResult<Self> {
+        let cache_client = Client::open(env::var("KV_URL").expect("Could not read $KV_URL"))?;
+        let mut cache = cache_client.get_multiplexed_async_connection().await?;
+
+        // Clear the membership and block caches, which get rebuilt from the database below.
+        // Nothing else may go: rate limits and login lockouts live in the same Redis, and
+        // they have to survive restarts.
+        for prefix in Self::REBUILT_KEY_PREFIXES {
+            let stale_keys: Vec<String> = cache
+                .scan_match::<_, String>(format!("{prefix}*"))
+                .await?
+                .collect()
+                .await;
+            for keys in stale_keys.chunks(Self::CACHE_CLEAR_BATCH_SIZE) {
+                let _: () = cache.del(keys).await.map_err(|error| {
+                    tracing::error!(message = "Could not clear the membership cache", ?error);
+                    error
+                })?;
+            }
+        }
+
+        // Acquire a connection to the database.
+        let mut db = persistence_pool
+            .get()
+            .expect("Couldn't acquire a database connection");
+
+        use crate::entities::schema::rooms_users::dsl::*;
+        use crate::entities::schema::users::dsl::*;
+        use diesel::prelude::*;
+
+        // Get a list of registered users.
+        let user_uuids = users
+            .select(uuid)
+            .load::<Uuid>(&mut db)
+            .unwrap_or_else(|err| {
+                tracing::error!(message = "Could not get a list of users from the DB", ?err);
+                vec![]
+            });
+
+        // Set up the membership cache.
+        for user in user_uuids.iter() {
+            tracing::debug!(message = "Setting up membership cache", ?user);
+            let rooms: Vec<Uuid> = rooms_users
+                .filter(user_uuid.eq(user))
+                .select(room_uuid)
+                .load::<Uuid>(&mut db)
+                .unwrap_or_default();
+            for room in rooms.iter() {
+                let _: () = cache.rpush(Self::rooms_key(user), room).await?;
+            }
+        }
+
+        // Set up the block cache.
+        let blocks: Vec<UserBlock> = crate::entities::schema::user_blocks::table
+            .select(UserBlock::as_select())
+            .load(&mut db)
+            .unwrap_or_else(|err| {
+                tracing::error!(message = "Could not get a list of blocks from the DB", ?err);
+                vec![]
+            });
+        for block in blocks {
+            let _: () = cache
+                .sadd(Self::blocks_key(&block.blocker_uuid), block.blocked_uuid)
+                .await?;
+        }
+
+        let (room_event_tx, _) = broadcast::channel(Self::INTERNAL_CHANNEL_CAPACITY);
+        let (user_event_tx, _) = broadcast::channel(Self::INTERNAL_CHANNEL_CAPACITY);
+
+        let attachments_dir: PathBuf = env::var("ATTACHMENTS_DIR")
+            .expect("Could not read $ATTACHMENTS_DIR")
+            .into();
+        std::fs::create_dir_all(&attachments_dir)
+            .expect("Could not create the attachments directory");
+        Self::spawn_attachment_sweeper(persistence_pool.clone(), attachments_dir.clone());
+
+        Ok(Self {
+            persistence_pool,
+            cache_client,
+            room_event_tx,
+            user_event_tx,
+            typing_deadlines: Arc::new(Mutex::new(HashMap::new())),
+            online_users: Arc::new(Mutex::new(HashMap::new())),
+            attachments_dir,
+            rate_limiter: RateLimiter::from_env(),
+        })
+    }
+
+    #[instrument(skip_all)]
+    async fn acquire_database_connection(
+        &self,
+    ) -> Result<PooledConnection<ConnectionManager<PgConnection>>, Status> {
+        let db_connection = self.persistence_pool.get().map_err(|error| {
+            let msg = "Couldn't acquire a database connection";
+            tracing::error!(message = msg, ?error);
+            Status::internal(msg)
+        })?;
+
+        Ok(db_connection)
+    }
+
+    #[instrument(skip_all)]
+    async fn acquire_cache_connection(&self) -> Result<MultiplexedConnection, Status> {
+        let cache_connection = self
+            .cache_client
+            .get_multiplexed_async_connection()
+            .await
+            .map_err(|error| {
+                let msg = "Couldn't acquire a cache connection";
+                tracing::error!(message = msg, ?error);
+                Status::internal(msg)
+            })?;
+
+        Ok(cache_connection)
+    }
+
+    #[instrument]
+    async fn check_room_membership(&self, user: &Uuid, room: &Uuid) -> Result<bool, Status> {
+        let mut cache = self.acquire_cache_connection().await?;
+        let allowed_rooms: Vec<Uuid> = cache
+            .lrange(Self::rooms_key(user), 0, -1)
+            .await
+            .unwrap_or_else(|error| {
+                tracing::error!(message = "Couldn't get membership from cache", ?error);
+                vec![]
+            });
+
+        Ok(allowed_rooms.contains(room))
+    }
+
+    /// Look up the role of a user in a room, if they are a member of it.
+    #[instrument]
+    async fn room_role(&self, user: &Uuid, room: &Uuid) -> Result<Option<RoomRole>, Status> {
+        let mut db = self.acquire_database_connection().await?;
+
+        use crate::entities::schema::rooms_users::dsl::*;
+        use diesel::prelude::*;
+
+        rooms_users
+            .find((*room, *user))
+            .select(role)
+            .first(&mut db)
+            .optional()
+            .map_err(|error| {
+                let msg = "Couldn't fetch the member's role from database";
+                tracing::error!(message = msg, ?error);
+                Status::internal(msg)
+            })
+    }
+
+    /// Ensure a user is allowed to perform an action in a room.
+    ///
+    /// Actions that any member may perform only go through the membership cache,
+    /// while the more privileged ones look up the user's role in the database.
+    #[instrument]
+    async fn authorize(&self, user: &Uuid, room: &Uuid, action: RoomAction) -> Result<(), Status> {
+        let required_role = action.required_role();
+        let user_role = if required_role == RoomRole::Member {
+            self.check_room_membership(user, room)
+                .await?
+                .then_some(RoomRole::Member)
+        } else {
+            self.room_role(user, room).await?
+        };
+
+        match user_role {
+            Some(user_role) if user_role.can(action) => Ok(()),
+            Some(user_role) => {
+                tracing::warn!(message = "User is not allowed to do this in the room", %user_role);
+                Err(Status::permission_denied(format!(
+                    "Only room {required_role}s can do that"
+                )))
+            }
+            None => {
+                tracing::warn!(message = "User is not a member of the room");
+                Err(Status::permission_denied(
+                    "You are not a member of this room",
+                ))
+            }
+        }
+    }
+
+    /// Parse a membership request into the UUIDs of its originator, the room and the target user.
+    ///
+    /// Ensures the originator is allowed to perform the action in the room.
+    #[instrument(skip_all)]
+    async fn membership_from_request(
+        &self,
+        request: Request<RoomMembershipRequest>,
+        action: RoomAction,
+    ) -> Result<(Uuid, Uuid, Uuid), Status> {
+        let originator_uuid = request
+            .get_originator_uuid()
+            .expect("The authenticator should not let anonymous requests through");
+
+        let membership_request = request.into_inner();
+        let room_uuid: Uuid = membership_request
+            .room_uuid
+            .and_then(|u| u.try_into().ok())
+            .ok_or(Status::invalid_argument("Invalid room UUID"))?;
+        let user_uuid: Uuid = membership_request
+            .user_uuid
+            .and_then(|u| u.try_into().ok())
+            .ok_or(Status::invalid_argument("Invalid user UUID"))?;
+
+        self.authorize(&originator_uuid, &room_uuid, action).await?;
+
+        Ok((originator_uuid, room_uuid, user_uuid))
+    }
+
+    /// Spend one call of the user's budget for a rate-limited RPC.
+    ///
+    /// Like [`RateLimiter::check`], this lets the call through if Redis can't be reached.
+    async fn throttle(&self, user: &Uuid, rpc: LimitedRpc) -> Result<(), Status> {
+        let mut cache = match self.cache_client.get_multiplexed_async_connection().await {
+            Ok(cache) => cache,
+            Err(error) => {
+                tracing::error!(message = "Couldn't check the rate limit", ?error);
+                return Ok(());
+            }
+        };
+        self.rate_limiter.check(&mut cache, user, rpc).await
+    }
+
+    /// Make sure a user has typed their current password, before letting them change their account.
+    async fn verify_password(&self, user: Uuid, typed_password: String) -> Result<(), Status> {
+        let mut db = self.acquire_database_connection().await?;
+
+        use crate::entities::schema::users::dsl::*;
+        use diesel::prelude::*;
+
+        let stored_hash: String =
+            users
+                .find(user)
+                .select(password)
+                .first(&mut db)
+                .map_err(|error| {
+                    let msg = "Couldn't fetch the user from database";
+                    tracing::error!(message = msg, ?error);
+                    Status::internal(msg)
+                })?;
+
+        // Password hashing is deliberately slow, so keep it off the async runtime.
+        let verification = tokio::task::spawn_blocking(move || {
+            crate::password::verify(&typed_password, &stored_hash)
+        })
+        .await
+        .map_err(|error| {
+            let msg = "Couldn't verify the password";
+            tracing::error!(message = msg, ?error);
+            Status::internal(msg)
+        })?;
+
+        match verification {
+            Verification::Valid { .. } => Ok(()),
+            Verification::Invalid => {
+                tracing::warn!(message = "User typed a wrong password", ?user);
+                Err(Status::permission_denied("The password is incorrect"))
+            }
+        }
+    }
+
+    /// The cache key of the list of rooms a user is a member of.
+    fn rooms_key(user: &Uuid) -> String {
+        format!("rooms:{user}")
+    }
+
+    /// The cache key of the set of users a user has blocked.
+    fn blocks_key(user: &Uuid) -> String {
+        format!("blocks:{user}")
+    }
+
+    /// Resolve the `@username` mentions in a message into the members of its room.
+    ///
+    /// Senders can't mention themselves, nor anyone who has blocked them.
+    async fn mentioned_members(&self, message: &Message) -> Result<Vec<RoomUser>, Status> {
+        let usernames = Mention::parse_usernames(&message.text);
+        if usernames.is_empty() {
+            return Ok(vec![]);
+        }
+
+        let mut db = self.acquire_database_connection().await?;
+
+        use crate::entities::schema::{rooms_users, user_blocks, users};
+        use diesel::dsl::{exists, not};
+        use diesel::prelude::*;
+
+        rooms_users::table
+            .inner_join(users::table)
+            .filter(rooms_users::room_uuid.eq(message.room_uuid))
+            .filter(users::username.eq_any(&usernames))
+            .filter(users::uuid.ne(message.sender_uuid))
+            .filter(not(exists(
+                user_blocks::table
+                    .filter(user_blocks::blocker_uuid.eq(users::uuid))
+                    .filter(user_blocks::blocked_uuid.eq(message.sender_uuid)),
+            )))
+            .select(RoomUser::as_select())
+            .load(&mut db)
+            .map_err(|error| {
+                let msg = "Couldn't resolve the mentioned users";
+                tracing::error!(message = msg, ?error);
+                Status::internal(msg)
+            })
+    }
+
+    /// Check whether a user has blocked another one.
+    async fn has_blocked(&self, blocker: Uuid, blocked: Uuid) -> Result<bool, Status> {
+        let mut cache = self.acquire_cache_connection().await?;
+        cache
+            .sismember(Self::blocks_key(&blocker), blocked)
+            .await
+            .map_err(|error| {
+                let msg = "Couldn't get blocks from cache";
+                tracing::error!(message = msg, ?error);
+                Status::internal(msg)
+            })
+    }
+
+    /// Delete one of the user's sessions, returning whether there was such a session.
+    async fn end_session(&self, user: Uuid, session: Uuid) -> Result<bool, Status> {
+        let mut db = self.acquire_database_connection().await?;
+
+        use crate::entities::schema::sessions::dsl::*;
+        use diesel::prelude::*;
+
+        let deleted = diesel::delete(sessions.find(session).filter(user_uuid.eq(user)))
+            .execute(&mut db)
+            .map_err(|error| {
+                let msg = "Couldn't delete the session from database";
+                tracing::error!(message = msg, ?error);
+                Status::internal(msg)
+            })?;
+
+        Ok(deleted > 0)
+    }
+
+    /// Find the direct room between a pair of users, if they have one.
+    async fn find_direct_room(&self, direct_pair: (Uuid, Uuid)) -> Result<Option<Uuid>, Status> {
+        let mut db = self.acquire_database_connection().await?;
+
+        use crate::entities::schema::rooms::dsl::*;
+        use diesel::prelude::*;
+
+        rooms
+            .filter(direct_user_low.eq(direct_pair.0))
+            .filter(direct_user_high.eq(direct_pair.1))
+            .select(uuid)
+            .first(&mut db)
+            .optional()
+            .map_err(|error| {
+                let msg = "Couldn't fetch the private chat from database";
+                tracing::error!(message = msg, ?error);
+                Status::internal(msg)
+            })
+    }
+
+    /// Bring back whichever of the two users has left their direct room since it was created.
+    async fn rejoin_direct_room(
+        &self,
+        room: Uuid,
+        direct_pair: (Uuid, Uuid),
+    ) -> Result<(), Status> {
+        for member in [direct_pair.0, direct_pair.1] {
+            if !self.check_room_membership(&member, &room).await? {
+                self.add_room_member(room, member).await?;
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Ensure a room is not a direct one, as those always have exactly two members.
+    async fn ensure_group_room(&self, room: &Uuid) -> Result<(), Status> {
+        let mut db = self.acquire_database_connection().await?;
+
+        use crate::entities::schema::rooms::dsl::*;
+        use diesel::prelude::*;
+
+        let room_kind: RoomKind =
+            rooms
+                .find(room)
+                .select(kind)
+                .first(&mut db)
+                .map_err(|error| {
+                    let msg = "Couldn't fetch the room from database";
+                    tracing::error!(message = msg, ?error);
+                    Status::internal(msg)
+                })?;
+
+        match room_kind {
+            RoomKind::Group => Ok(()),
+            RoomKind::Direct => Err(Status::failed_precondition(
+                "Private chats can't have their members changed",
+            )),
+        }
+    }
+
+    /// Look up a user that is about to join or leave a room.
+    ///
+    /// The placeholder for deleted accounts doesn't count as a user here.
+    async fn room_member(&self, member: Uuid) -> Result<User, Status> {
+        if member == User::DELETED_UUID {
+            return Err(Status::not_found("No such user"));
+        }
+
+        let mut db = self.acquire_database_connection().await?;
+
+        use crate::entities::schema::users::dsl::*;
+        use diesel::prelude::*;
+
+        users
+            .find(member)
+            .select(User::as_select())
+            .first(&mut db)
+            .optional()
+            .map_err(|error| {
+                let msg = "Couldn't fetch the user from database";
+                tracing::error!(message = msg, ?error);
+                Status::internal(msg)
+            })?
+            .ok_or(Status::not_found("No such user"))
+    }
+
+    /// Add a user to an existing room, updating the membership cache and notifying everyone involved.
+    async fn add_room_member(&self, room: Uuid, member: Uuid) -> Result<(), Status> {
+        let user = self.room_member(member).await?;
+        let mut db = self.acquire_database_connection().await?;
+        let mut cache = self.acquire_cache_connection().await?;
+
+        use crate::entities::schema::rooms_users::dsl::*;
+        use diesel::prelude::*;
+
+        let _ = diesel::insert_into(rooms_users)
+            .values(&RoomUser::new(room, member, RoomRole::Member))
+            .execute(&mut db)
+            .map_err(|error| {
+                let msg = "Could not save the room's new member in the database";
+                tracing::error!(message = msg, ?error);
+                Status::internal(msg)
+            })?;
+
+        let _: () = cache
+            .rpush(Self::rooms_key(&member), room)
+            .await
+            .map_err(|error| {
+                let msg = "Could not update membership cache";
+                tracing::error!(message = msg, ?error);
+                Status::internal(msg)
+            })?;
+
+        broadcast(
+            &self.room_event_tx,
+            ServersideRoomEvent {
+                room_uuid: Some(room.into()),
+                event: Some(RoomEvent::UserJoined(user.into())),
+            },
+        );
+        broadcast(
+            &self.user_event_tx,
+            ServersideUserEvent {
+                user_uuid: Some(member.into()),
+                event: Some(UserEvent::AddedToRoom(room.into())),
+            },
+        );
+
+        Ok(())
+    }
+
+    /// Remove a user from a room, updating the membership cache and notifying everyone left in it.
+    async fn remove_room_member(&self, room: Uuid, member: Uuid) -> Result<(), Status> {
+        let user = self.room_member(member).await?;
+        let mut db = self.acquire_database_connection().await?;
+        let mut cache = self.acquire_cache_connection().await?;
+
+        use crate::entities::schema::rooms_users::dsl::*;
+        use diesel::prelude::*;
+
+        let _ = diesel::delete(rooms_users.find((room, member)))
+            .execute(&mut db)
+            .map_err(|error| {
+                let msg = "Could not remove the room's member from the database";
+                tracing::error!(message = msg, ?error);
+                Status::internal(msg)
+            })?;
+
+        let _: () = cache
+            .lrem(Self::rooms_key(&member), 0, room)
+            .await
+            .map_err(|error| {
+                let msg = "Could not update membership cache";
+                tracing::error!(message = msg, ?error);
+                Status::internal(msg)
+            })?;
+
+        broadcast(
+            &self.room_event_tx,
+            ServersideRoomEvent {
+                room_uuid: Some(room.into()),
+                event: Some(RoomEvent::UserLeft(user.into())),
+            },
+        );
+
+        Ok(())
+    }
+
+    /// Subscribe a `Connect` stream to a room, if the user is a member of it.
+    async fn subscribe_connection(
+        cache: &mut MultiplexedConnection,
+        user: Uuid,
+        subscribed_rooms: &mut HashSet<Uuid>,
+        proto_uuid: proto::Uuid,
+    ) -> ServerFrameKind {
+        let member_rooms: Vec<Uuid> = cache.lrange(Self::rooms_key(&user), 0, -1).await.unwrap_or_else(|error| {
+            tracing::error!(message = "Could not retrieve membership from cache", user_uuid = ?user, ?error);
+            vec![]
+        });
+        match Uuid::try_from(proto_uuid.clone()) {
+            Ok(room) if member_rooms.contains(&room) => {
+                tracing::debug!(message = "Subscribed a connection to a room", user_uuid = ?user, ?room);
+                subscribed_rooms.insert(room);
+                ServerFrameKind::Subscribed(proto_uuid)
+            }
+            _ => ServerFrameKind::SubscriptionRefused(proto_uuid),
+        }
+    }
+
+    /// End an event stream whose internal event receiver has failed.
+    ///
+    /// This means the client fell so far behind that events were dropped (or the server
+    /// is shutting down), so it can't trust whatever it has cached anymore.
+    async fn end_lagging_connection<T>(
+        grpc_tx: &mpsc::Sender<Result<T, Status>>,
+        user: Uuid,
+        error: broadcast::error::RecvError,
+    ) {
+        let broadcast::error::RecvError::Lagged(missed) = error else {
+            return;
+        };
+        tracing::warn!(message = "A connection fell behind, closing it", user_uuid = ?user, %missed);
+        let _ = grpc_tx
+            .send(Err(Status::data_loss(format!(
+                "Missed {missed} events, reconnect to catch up"
+            ))))
+            .await;
+    }
+
+    /// Count a newly opened stream of user events, bringing the user online if it's their first one.
+    async fn open_user_stream(&self, user: Uuid) {
+        let mut online_users = self.online_users.lock().await;
+        let open_streams = online_users.entry(user).or_default();
+        *open_streams += 1;
+        let came_online = *open_streams == 1;
+        drop(online_users);
+
+        if came_online {
+            tracing::debug!(message = "User came online", user_uuid = ?user);
+            Self::broadcast_presence(
+                &self.user_event_tx,
+                Presence {
+                    user_uuid: Some(user.into()),
+                    online: true,
+                    last_seen: None,
+                },
+            );
+        }
+    }
+
+    /// Count a stream of user events as closed, taking the user offline if it was their last one.
+    async fn close_user_stream(
+        online_users: &Mutex<HashMap<Uuid, usize>>,
+        persistence_pool: &persistence::ConnectionPool,
+        user_event_tx: &broadcast::Sender<ServersideUserEvent>,
+        user: Uuid,
+    ) {
+        let mut online_users = online_users.lock().await;
+        let open_streams = online_users.entry(user).or_insert(1);
+        *open_streams -= 1;
+        let went_offline = *open_streams == 0;
+        if went_offline {
+            online_users.remove(&user);
+        }
+        drop(online_users);
+
+        if went_offline {
+            tracing::debug!(message = "User went offline", user_uuid = ?user);
+            Self::take_offline(persistence_pool, user_event_tx, user);
+        }
+    }
+
+    /// Check whether a room event should be streamed to a subscriber: it has to come from one
+    /// of the rooms they've subscribed to and are still a member of, and must not be about
+    /// someone they've blocked.
+    async fn streams_room_event(
+        cache: &mut MultiplexedConnection,
+        subscriber: Uuid,
+        subscribed_rooms: &HashSet<Uuid>,
+        event: &ServersideRoomEvent,
+    ) -> bool {
+        let Some(event_room) = event.room_uuid.clone().and_then(|u| u.try_into().ok()) else {
+            tracing::error!(message = "Caught a room event without a room UUID", ?event);
+            return false;
+        };
+        if !subscribed_rooms.contains(&event_room) {
+            return false;
+        }
+
+        let subscriber_rooms: Vec<Uuid> = cache
+            .lrange(Self::rooms_key(&subscriber), 0, -1)
+            .await
+            .unwrap_or_else(|error| {
+                tracing::error!(
+                    message = "Could not retrieve membership from cache",
+                    ?subscriber,
+                    ?error
+                );
+                vec![]
+            });
+        if !subscriber_rooms.contains(&event_room) {
+            return false;
+        }
+
+        !Self::hides_author(cache, subscriber, UserBlock::room_event_author(event)).await
+    }
+
+    /// Check whether a user event should be streamed to a subscriber, addressing it to them if so.
+    ///
+    /// Most user events are meant for a single user, but presence and profile changes are
+    /// broadcast once, addressed to their subject, and go to everyone who shares a room with
+    /// them instead. Either way, events about someone the subscriber has blocked are never streamed.
+    async fn addressed_user_event(
+        cache: &mut MultiplexedConnection,
+        subscriber: Uuid,
+        event: ServersideUserEvent,
+    ) -> Option<ServersideUserEvent> {
+        let Some(addressee) = event.user_uuid.clone().and_then(|u| u.try_into().ok()) else {
+            tracing::error!(message = "Caught a user event without a user UUID", ?event);
+            return None;
+        };
+        let meant_for_subscriber = match event.event {
+            Some(UserEvent::PresenceChanged(_)) => {
+                addressee != subscriber && Self::shares_room(cache, subscriber, addressee).await
+            }
+            Some(UserEvent::ProfileUpdated(_)) => {
+                addressee == subscriber || Self::shares_room(cache, subscriber, addressee).await
+            }
+            _ => addressee == subscriber,
+        };
+        if !meant_for_subscriber
+            || Self::hides_author(cache, subscriber, UserBlock::user_event_author(&event)).await
+        {
+            return None;
+        }
+
+        Some(ServersideUserEvent {
+            user_uuid: Some(subscriber.into()),
+            ..event
+        })
+    }
+
+    /// Check whether two users share at least one room, according to the membership cache.
+    async fn shares_room(cache: &mut MultiplexedConnection, user: Uuid, other: Uuid) -> bool {
+        let mut member_rooms = Vec::with_capacity(2);
+        for member in [user, other] {
+            let rooms: Vec<Uuid> = cache.lrange(Self::rooms_key(&member), 0, -1).await.unwrap_or_else(|error| {
+                tracing::error!(message = "Could not retrieve membership from cache", user_uuid = ?member, ?error);
+                vec![]
+            });
+            member_rooms.push(rooms);
+        }
+        member_rooms[0]
+            .iter()
+            .any(|room| member_rooms[1].contains(room))
+    }
+
+    /// Check whether a subscriber has blocked the author of an event, letting it through if the cache fails.
+    async fn hides_author(
+        cache: &mut MultiplexedConnection,
+        subscriber: Uuid,
+        author: Option<Uuid>,
+    ) -> bool {
+        let Some(author) = author else {
+            return false;
+        };
+        cache
+            .sismember(Self::blocks_key(&subscriber), author)
+            .await
+            .unwrap_or_else(|error| {
+                tracing::error!(
+                    message = "Could not retrieve blocks from cache",
+                    ?subscriber,
+                    ?error
+                );
+                false
+            })
+    }
+
+    /// Record when a user was last seen, and let everyone they share a room with know that they're offline.
+    fn take_offline(
+        persistence_pool: &persistence::ConnectionPool,
+        user_event_tx: &broadcast::Sender<ServersideUserEvent>,
+        user: Uuid,
+    ) {
+        use crate::entities::schema::users::dsl::*;
+        use diesel::prelude::*;
+
+        let now = SystemTime::now();
+        match persistence_pool.get() {
+            Ok(mut db) => {
+                if let Err(error) = diesel::update(users.find(user))
+                    .set(last_seen.eq(now))
+                    .execute(&mut db)
+                {
+                    tracing::error!(
+                        message = "Couldn't store when the user was last seen",
+                        ?error
+                    );
+                }
+            }
+            Err(error) => {
+                tracing::error!(message = "Couldn't acquire a database connection", ?error);
+            }
+        }
+
+        Self::broadcast_presence(
+            user_event_tx,
+            Presence {
+                user_uuid: Some(user.into()),
+                online: false,
+                last_seen: Some(now.into()),
+            },
+        );
+    }
+
+    /// Send a presence change once, addressed to its subject: each stream works out on its own
+    /// whether its subscriber shares a room with them (see [`Self::addressed_user_event`]).
+    fn broadcast_presence(
+        user_event_tx: &broadcast::Sender<ServersideUserEvent>,
+        presence: Presence,
+    ) {
+        broadcast(
+            user_event_tx,
+            ServersideUserEvent {
+                user_uuid: presence.user_uuid.clone(),
+                event: Some(UserEvent::PresenceChanged(presence)),
+            },
+        );
+    }
+
+    /// Send a profile change once, addressed to its subject: it goes to their other devices and,
+    /// like presence, to everyone they share a room with (see [`Self::addressed_user_event`]).
+    fn broadcast_profile(&self, subject: Uuid, profile: &proto::Profile) {
+        broadcast(
+            &self.user_event_tx,
+            ServersideUserEvent {
+                user_uuid: Some(subject.into()),
+                event: Some(UserEvent::ProfileUpdated(profile.clone())),
+            },
+        );
+    }
+
+    /// Look up a user along with their profile, which is empty if they've never filled it in.
+    async fn user_profile(&self, user: Uuid) -> Result<proto::Profile, Status> {
+        let mut db = self.acquire_database_connection().await?;
+
+        use crate::entities::schema::{profiles, users};
+        use diesel::prelude::*;
+
+        let profile_user: User = users::table
+            .find(user)
+            .select(User::as_select())
+            .first(&mut db)
+            .optional()
+            .map_err(|error| {
+                let msg = "Couldn't fetch the user from database";
+                tracing::error!(message = msg, ?error);
+                Status::internal(msg)
+            })?
+            .ok_or(Status::not_found("No such user"))?;
+        let profile: Profile = profiles::table
+            .find(user)
+            .select(Profile::as_select())
+            .first(&mut db)
+            .optional()
+            .map_err(|error| {
+                let msg = "Couldn't fetch the profile from database";
+                tracing::error!(message = msg, ?error);
+                Status::internal(msg)
+            })?
+            .unwrap_or_else(|| Profile::empty(user));
+
+        Ok((profile_user, profile).into())
+    }
+
+    /// Build a [`RoomEvent::UserTyping`] event for a room.
+    fn typing_event(room: Uuid, user: Uuid, typing: bool) -> ServersideRoomEvent {
+        ServersideRoomEvent {
+            room_uuid: Some(room.into()),
+            event: Some(RoomEvent::UserTyping(UserTyping {
+                user_uuid: Some(user.into()),
+                typing,
+            })),
+        }
+    }
 
+    /// Convert a room from the database into a [`ServersideRoom`], as seen by a certain user.
+    async fn serverside_room(&self, db_room: Room, viewer: Uuid) -> Result<ServersideRoom, Status> {
         let mut db = self.acquire_database_connection().await?;
 
-        let messages: Vec<Message> = {
-            use crate::entities::schema::messages::dsl::*;
-            use diesel::prelude::*;
+        use diesel::prelude::*;
 
-            messages
-                .filter(room_uuid.eq(room_uuid))
-                .select(Message::as_select())
-                .load::<Message>(&mut db)
+        let room_members: Vec<RoomUser> = {
+            use crate::entities::schema::rooms_users::dsl::*;
+
+            rooms_users
+                .filter(room_uuid.eq(db_room.uuid))
+                .select(RoomUser::as_select())
+                .load(&mut db)
                 .map_err(|error| {
-                    let message = "Couldn't load messages from the database";
-                    tracing::error!(message = message, ?error);
-                    Status::internal(message)
+                    let msg = "Couldn't fetch membership from database";
+                    tracing::error!(message = msg, ?error);
+                    Status::internal(msg)
                 })?
         };
-
-        let usernames: HashMap<Uuid, String> = messages
+        let members_with_role = |required_role: RoomRole| {
+            room_members
+                .iter()
+                .filter(move |member| member.role == required_role)
+                .map(|member| proto::Uuid::from(member.user_uuid))
+        };
+        let notifications = room_members
             .iter()
-            .map(|message| message.sender_uuid)
-            .unique()
-            .map(|user_uuid| {
-                use crate::entities::schema::users::dsl::*;
-                use diesel::prelude::*;
+            .find(|member| member.user_uuid == viewer)
+            .map(|membership| {
+                // Mutes that have run out are reported as such.
+                let level = membership.notification_level_at(SystemTime::now());
+                RoomNotificationSettings {
+                    room_uuid: Some(db_room.uuid.into()),
+                    level: proto::NotificationLevel::from(level).into(),
+                    muted_until: membership
+                        .muted_until
+                        .filter(|_| level == NotificationLevel::Muted)
+                        .map(Into::into),
+                }
+            });
 
-                (
-                    user_uuid,
-                    users
-                        .find(user_uuid)
-                        .select(username)
-                        .first(&mut db)
-                        .expect("Foreign key can't point to a non-existing primary key"),
-                )
-            })
-            .collect();
+        use crate::entities::schema::messages::dsl::*;
 
-        let formatted_messages: Vec<String> = messages
-            .into_iter()
-            .map(|message| {
-                let username = usernames
-                    .get(&message.sender_uuid)
-                    .cloned()
-                    .unwrap_or_default();
-                format!("\n{username}: {}", message.text)
-            })
-            .collect();
+        // Count the messages from other users after the viewer's read marker.
+        let last_read = Self::read_marker(&mut db, viewer, db_room.uuid).await?;
+        let unread_query = messages
+            .filter(room_uuid.eq(db_room.uuid))
+            .filter(sender_uuid.ne(viewer))
+            .filter(deleted_at.is_null())
+            .into_boxed();
+        let unread_query = match last_read {
+            Some((read_uuid, read_timestamp)) => unread_query.filter(
+                timestamp
+                    .gt(read_timestamp)
+                    .or(timestamp.eq(read_timestamp).and(uuid.gt(read_uuid))),
+            ),
+            None => unread_query,
+        };
+        let unread_count: i64 = unread_query.count().get_result(&mut db).map_err(|error| {
+            let msg = "Couldn't count unread messages";
+            tracing::error!(message = msg, ?error);
+            Status::internal(msg)
+        })?;
 
-        // Connect to the running LLM.
-        let expect_message = "$LLM_HOST and $LLM_PORT should point to an LLM instance!";
-        let host = format!("http://{}", env::var("LLM_HOST").expect(expect_message));
-        let port = env::var("LLM_PORT")
-            .expect(expect_message)
-            .parse::<u16>()
-            .expect(expect_message);
-        let llm = Ollama::new(host, port);
+        Ok(ServersideRoom {
+            uuid: Some(db_room.uuid.into()),
+            name: db_room.name,
+            topic: db_room.topic,
+            description: db_room.description,
+            kind: proto::RoomKind::from(db_room.kind).into(),
+            members: room_members
+                .iter()
+                .map(|member| proto::Uuid::from(member.user_uuid))
+                .collect(),
+            unread_count: u64::try_from(unread_count).unwrap_or_default(),
+            last_read_message: last_read.map(|(read_uuid, _)| read_uuid.into()),
+            owner: members_with_role(RoomRole::Owner).next(),
+            admins: members_with_role(RoomRole::Admin).collect(),
+            notifications,
+        })
+    }
 
-        tracing::trace!(message = "Connected to LLM, forming a prompt");
+    /// Find the UUID and timestamp of the last message a user has read in a room.
+    async fn read_marker(
+        db: &mut PooledConnection<ConnectionManager<PgConnection>>,
+        user: Uuid,
+        room: Uuid,
+    ) -> Result<Option<(Uuid, SystemTime)>, Status> {
+        use crate::entities::schema::{messages, read_markers};
+        use diesel::prelude::*;
 
-        let model = "llama3".to_string();
-        let mut prompt = r#"
-            Hey. Here are a bunch of messages from a chat room, along with the user's names.
-            I want you to analyze the messages and figure out what topics were discussed, as
-            well as look for pattterns in the messages and provide insignt into the users'
-            mood and intentions. Provide additional information about the same things the users
-            were talking about. Your response will be used as a mean of improving the users'
-            experience, and also be a part of a real-time behavioral analysis. Stay concise,
-            go straight to the point, no introductions. Here are the messages:
-        "#
-        .to_string();
+        read_markers::table
+            .inner_join(messages::table)
+            .filter(read_markers::user_uuid.eq(user))
+            .filter(read_markers::room_uuid.eq(room))
+            .select((messages::uuid, messages::timestamp))
+            .first(db)
+            .optional()
+            .map_err(|error| {
+                let msg = "Couldn't fetch the read marker from database";
+                tracing::error!(message = msg, ?error);
+                Status::internal(msg)
+            })
+    }
 
-        for message in formatted_messages.iter() {
-            prompt.push_str(message);
-        }
+    /// Turn a reaction request into a [`Reaction`] of the originator, along with the room it's in.
+    ///
+    /// Ensures the reaction is valid, and that the originator is a member of the room the
+    /// message is from, the same way [`Self::authorize`] would for the room itself.
+    #[instrument(skip_all)]
+    async fn reaction_from_request(
+        &self,
+        request: Request<ReactionRequest>,
+    ) -> Result<(Reaction, Uuid), Status> {
+        let originator_uuid = request
+            .get_originator_uuid()
+            .expect("The authenticator should not let anonymous requests through");
 
-        tracing::trace!(message = "Awaiting LLM response...");
+        let reaction_request = request.into_inner();
+        let reacted_message_uuid: Uuid = reaction_request
+            .message_uuid
+            .and_then(|u| u.try_into().ok())
+            .ok_or(Status::invalid_argument("Invalid message UUID"))?;
+        Reaction::validate_emoji(&reaction_request.emoji).map_err(Status::invalid_argument)?;
 
-        let response = llm
-            .generate(GenerationRequest::new(model, prompt))
-            .await
+        let mut db = self.acquire_database_connection().await?;
+
+        use crate::entities::schema::messages::dsl::*;
+        use diesel::prelude::*;
+
+        let reacted_message: Message = messages
+            .find(reacted_message_uuid)
+            .select(Message::as_select())
+            .first(&mut db)
+            .optional()
             .map_err(|error| {
-                let message = "Local LLM returned an error";
-                tracing::error!(message = message, ?error);
-                Status::internal(message)
+                let msg = "Couldn't fetch the message from database";
+                tracing::error!(message = msg, ?error);
+                Status::internal(msg)
             })?
-            .response;
+            .ok_or(Status::not_found("No such message"))?;
 
-        tracing::info!(message = "LLM analysis complete");
+        self.authorize(
+            &originator_uuid,
+            &reacted_message.room_uuid,
+            RoomAction::Post,
+        )
+        .await?;
+        if reacted_message.is_deleted() {
+            return Err(Status::failed_precondition("This message was deleted"));
+        }
 
-        Ok(Response::new(proto::RoomAnalysisResponse { response }))
+        let reaction = Reaction {
+            message_uuid: reacted_message_uuid,
+            user_uuid: originator_uuid,
+            emoji: reaction_request.emoji,
+        };
+
+        Ok((reaction, reacted_message.room_uuid))
     }
-}
 
-impl Chat {
-    const INTERNAL_CHANNEL_CAPACITY: usize = 16;
+    /// Convert messages from the database into [`ServersideMessage`]s, along with their
+    /// reactions and reply counts.
+    async fn into_serverside_messages(
+        db: &mut PooledConnection<ConnectionManager<PgConnection>>,
+        db_messages: Vec<Message>,
+    ) -> Result<Vec<ServersideMessage>, Status> {
+        use diesel::dsl::count_star;
+        use diesel::prelude::*;
 
-    pub async fn new(persistence_pool: persistence::ConnectionPool) -> RedisResult<Self> {
-        let cache_client = Client::open(env::var("KV_URL").expect("Could not read $KV_URL"))?;
-        let mut cache = cache_client.get_multiplexed_async_connection().await?;
+        let message_uuids: Vec<Uuid> = db_messages.iter().map(|m| m.uuid).collect();
 
-        // Flush the membership cache.
-        let _: () = redis::cmd("FLUSHALL")
-            .query_async(&mut cache)
-            .await
+        let mut reply_counts: HashMap<Uuid, u32> = {
+            use crate::entities::schema::messages::dsl::*;
+
+            messages
+                .filter(reply_to.eq_any(&message_uuids))
+                .filter(deleted_at.is_null())
+                .group_by(reply_to)
+                .select((reply_to, count_star()))
+                .load::<(Option<Uuid>, i64)>(db)
+                .map_err(|error| {
+                    let msg = "Couldn't fetch reply counts from database";
+                    tracing::error!(message = msg, ?error);
+                    Status::internal(msg)
+                })?
+                .into_iter()
+                .filter_map(|(root, count)| Some((root?, u32::try_from(count).unwrap_or(u32::MAX))))
+                .collect()
+        };
+
+        let mut sent_attachments: HashMap<Uuid, Vec<proto::Attachment>> = {
+            use crate::entities::schema::attachments::dsl::*;
+
+            let mut sent_attachments: HashMap<Uuid, Vec<proto::Attachment>> = HashMap::new();
+            for attachment in attachments
+                .filter(message_uuid.eq_any(&message_uuids))
+                .order((uploaded_at.asc(), uuid.asc()))
+                .select(Attachment::as_select())
+                .load(db)
+                .map_err(|error| {
+                    let msg = "Couldn't fetch attachments from database";
+                    tracing::error!(message = msg, ?error);
+                    Status::internal(msg)
+                })?
+            {
+                if let Some(sent_with) = attachment.message_uuid {
+                    sent_attachments
+                        .entry(sent_with)
+                        .or_default()
+                        .push(attachment.into());
+                }
+            }
+            sent_attachments
+        };
+
+        use crate::entities::schema::message_reactions::dsl::*;
+
+        let reaction_counts: Vec<(Uuid, String, i64)> = message_reactions
+            .filter(message_uuid.eq_any(&message_uuids))
+            .group_by((message_uuid, emoji))
+            .select((message_uuid, emoji, count_star()))
+            .load(db)
             .map_err(|error| {
-                tracing::error!(message = "Could not flush the membership cache", ?error);
-                error
+                let msg = "Couldn't fetch reactions from database";
+                tracing::error!(message = msg, ?error);
+                Status::internal(msg)
             })?;
 
-        // Acquire a connection to the database.
-        let mut db = persistence_pool
-            .get()
-            .expect("Couldn't acquire a database connection");
+        let mut reactions: HashMap<Uuid, Vec<ReactionCount>> = HashMap::new();
+        for (reacted_message, reaction_emoji, count) in reaction_counts {
+            reactions
+                .entry(reacted_message)
+                .or_default()
+                .push(ReactionCount {
+                    emoji: reaction_emoji,
+                    count: u32::try_from(count).unwrap_or(u32::MAX),
+                });
+        }
 
-        use crate::entities::schema::rooms_users::dsl::*;
-        use crate::entities::schema::users::dsl::*;
-        use diesel::prelude::*;
+        let serverside_messages = db_messages
+            .into_iter()
+            .map(|db_message| {
+                let mut counts = reactions.remove(&db_message.uuid).unwrap_or_default();
+                counts.sort_by(|a, b| b.count.cmp(&a.count).then_with(|| a.emoji.cmp(&b.emoji)));
 
-        // Get a list of registered users.
-        let user_uuids = users
-            .select(uuid)
-            .load::<Uuid>(&mut db)
-            .unwrap_or_else(|err| {
-                tracing::error!(message = "Could not get a list of users from the DB", ?err);
-                vec![]
-            });
+                let reply_count = reply_counts.remove(&db_message.uuid).unwrap_or_default();
+                let message_attachments = sent_attachments
+                    .remove(&db_message.uuid)
+                    .unwrap_or_default();
 
-        // Set up the membership cache.
-        for user in user_uuids.iter() {
-            tracing::debug!(message = "Setting up membership cache", ?user);
-            let rooms: Vec<Uuid> = rooms_users
-                .filter(user_uuid.eq(user))
-                .select(room_uuid)
-                .load::<Uuid>(&mut db)
-                .unwrap_or_default();
-            for room in rooms.iter() {
-                cache.rpush(user, room).await?;
-            }
-        }
+                let mut serverside_message = ServersideMessage::from(db_message);
+                serverside_message.reactions = counts;
+                serverside_message.reply_count = reply_count;
+                serverside_message.attachments = message_attachments;
+                serverside_message
+            })
+            .collect();
 
-        let (message_tx, _) = broadcast::channel(Self::INTERNAL_CHANNEL_CAPACITY);
-        let (user_event_tx, _) = broadcast::channel(Self::INTERNAL_CHANNEL_CAPACITY);
+        Ok(serverside_messages)
+    }
 
-        Ok(Self {
-            persistence_pool,
-            cache_client,
-            message_tx,
-            user_event_tx,
-        })
+    fn attachment_path(&self, attachment: &Uuid) -> PathBuf {
+        Self::attachment_path_in(&self.attachments_dir, attachment)
     }
 
-    #[instrument(skip_all)]
-    async fn acquire_database_connection(
-        &self,
-    ) -> Result<PooledConnection<ConnectionManager<PgConnection>>, Status> {
-        let db_connection = self.persistence_pool.get().map_err(|error| {
-            let msg = "Couldn't acquire a database connection";
+    fn attachment_path_in(attachments_dir: &Path, attachment: &Uuid) -> PathBuf {
+        attachments_dir.join(attachment.simple().to_string())
+    }
+
+    /// Write the data chunks of an upload into a file, returning its size and BLAKE3 hash.
+    async fn receive_attachment(
+        chunks: &mut Streaming<AttachmentUploadChunk>,
+        path: &Path,
+    ) -> Result<(u64, String), Status> {
+        let write_error = |error| {
+            let msg = "Couldn't store the attachment";
             tracing::error!(message = msg, ?error);
             Status::internal(msg)
-        })?;
+        };
 
-        Ok(db_connection)
+        let mut file = tokio::fs::File::create(path).await.map_err(write_error)?;
+        let mut hasher = blake3::Hasher::new();
+        let mut size: u64 = 0;
+
+        while let Some(chunk) = chunks.message().await? {
+            let Some(UploadChunk::Data(data)) = chunk.chunk else {
+                return Err(Status::invalid_argument(
+                    "Only the first chunk of an upload may carry metadata",
+                ));
+            };
+
+            size += data.len() as u64;
+            if size > Attachment::MAX_SIZE {
+                return Err(Status::out_of_range(format!(
+                    "Attachments can't be larger than {} bytes",
+                    Attachment::MAX_SIZE
+                )));
+            }
+
+            hasher.update(&data);
+            file.write_all(&data).await.map_err(write_error)?;
+        }
+        file.sync_all().await.map_err(write_error)?;
+
+        Ok((size, hasher.finalize().to_hex().to_string()))
     }
 
-    #[instrument(skip_all)]
-    async fn acquire_cache_connection(&self) -> Result<MultiplexedConnection, Status> {
-        let cache_connection = self
-            .cache_client
-            .get_multiplexed_async_connection()
-            .await
+    /// Count the bytes of the attachments a user has uploaded, but not sent yet.
+    async fn unsent_attachments_size(&self, uploader: Uuid) -> Result<u64, Status> {
+        use crate::entities::schema::attachments::dsl::*;
+        use diesel::prelude::*;
+        use diesel::sql_types::BigInt;
+
+        let mut db = self.acquire_database_connection().await?;
+        let unsent_size: i64 = attachments
+            .filter(uploader_uuid.eq(uploader))
+            .filter(message_uuid.is_null())
+            .select(diesel::dsl::sql::<BigInt>("COALESCE(SUM(size), 0)::BIGINT"))
+            .first(&mut db)
             .map_err(|error| {
-                let msg = "Couldn't acquire a cache connection";
+                let msg = "Couldn't fetch unsent attachments from database";
                 tracing::error!(message = msg, ?error);
                 Status::internal(msg)
             })?;
 
-        Ok(cache_connection)
+        Ok(u64::try_from(unsent_size).unwrap_or_default())
     }
 
-    #[instrument]
-    async fn check_room_membership(&self, user: &Uuid, room: &Uuid) -> Result<bool, Status> {
-        let mut cache = self.acquire_cache_connection().await?;
-        let allowed_rooms: Vec<Uuid> = cache.lrange(user, 0, -1).await.unwrap_or_else(|error| {
-            tracing::error!(message = "Couldn't get membership from cache", ?error);
-            vec![]
+    fn unsent_attachments_exhausted() -> Status {
+        Status::resource_exhausted(format!(
+            "Unsent attachments can't take up more than {} bytes, send some of them first",
+            Attachment::MAX_UNSENT_SIZE
+        ))
+    }
+
+    /// Periodically delete the attachments that were uploaded, but never sent, for too long.
+    fn spawn_attachment_sweeper(
+        persistence_pool: persistence::ConnectionPool,
+        attachments_dir: PathBuf,
+    ) {
+        tokio::spawn(async move {
+            use crate::entities::schema::attachments::dsl::*;
+            use diesel::prelude::*;
+
+            let mut sweeps = tokio::time::interval(Self::ATTACHMENT_SWEEP_INTERVAL);
+            loop {
+                sweeps.tick().await;
+
+                let Some(expired_before) =
+                    SystemTime::now().checked_sub(Attachment::UNSENT_LIFETIME)
+                else {
+                    continue;
+                };
+                let expired: Vec<Uuid> = match persistence_pool.get() {
+                    Ok(mut db) => diesel::delete(
+                        attachments
+                            .filter(message_uuid.is_null())
+                            .filter(uploaded_at.lt(expired_before)),
+                    )
+                    .returning(uuid)
+                    .get_results(&mut db)
+                    .unwrap_or_else(|error| {
+                        tracing::error!(message = "Couldn't delete unsent attachments", ?error);
+                        vec![]
+                    }),
+                    Err(error) => {
+                        tracing::error!(message = "Couldn't acquire a database connection", ?error);
+                        continue;
+                    }
+                };
+
+                if !expired.is_empty() {
+                    tracing::info!(message = "Deleted unsent attachments", count = %expired.len());
+                }
+                Self::remove_files_of(&attachments_dir, &expired).await;
+            }
         });
+    }
 
-        Ok(allowed_rooms.contains(room))
+    /// Remove the contents of deleted attachments from the disk.
+    ///
+    /// The attachments are already gone from the database at this point, so a file that
+    /// couldn't be removed is merely wasted space, not a reason to fail the request.
+    async fn remove_attachment_files(&self, attachments: &[Uuid]) {
+        Self::remove_files_of(&self.attachments_dir, attachments).await;
+    }
+
+    async fn remove_files_of(attachments_dir: &Path, attachments: &[Uuid]) {
+        for attachment in attachments {
+            let path = Self::attachment_path_in(attachments_dir, attachment);
+            if let Err(error) = tokio::fs::remove_file(path).await {
+                tracing::warn!(
+                    message = "Couldn't remove an attachment's file",
+                    ?attachment,
+                    ?error
+                );
+            }
+        }
     }
 
+    /// Create a room and add its members, the creator being its owner.
+    ///
+    /// A `direct_pair` (see [`Room::direct_pair`]) makes it a direct room between the two users.
     #[instrument(skip_all)]
-    async fn create_room(&self, clientside_room: ClientsideRoom) -> Result<Uuid, Status> {
+    async fn create_room(
+        &self,
+        clientside_room: ClientsideRoom,
+        creator: Uuid,
+        direct_pair: Option<(Uuid, Uuid)>,
+    ) -> Result<Uuid, Status> {
         let mut db_connection = self.acquire_database_connection().await?;
         let mut cache_connection = self.acquire_cache_connection().await?;
 
-        let user_uuids: Vec<Uuid> = clientside_room
-            .members
-            .into_iter()
-            .map(Uuid::try_from)
+        // The creator is always a member of the room, even if they didn't list themselves.
+        let user_uuids: Vec<Uuid> = std::iter::once(Ok(creator))
+            .chain(clientside_room.members.into_iter().map(Uuid::try_from))
             .unique() // Ensure we don't operate on the same user twice!
             .collect::<Result<Vec<_>, _>>()
             .map_err(|error| {
@@ -764,37 +4099,67 @@ impl Chat {
                 Status::invalid_argument(message)
             })?;
 
-        let room = Room::new(clientside_room.name);
+        if user_uuids.contains(&User::DELETED_UUID) {
+            return Err(Status::invalid_argument(
+                "Deleted accounts can't be added to rooms",
+            ));
+        }
+        for member in user_uuids.iter().filter(|member| **member != creator) {
+            if self.has_blocked(*member, creator).await? {
+                tracing::warn!(message = "Blocked user tried to add the blocker to a room", blocker = ?member);
+                return Err(Status::permission_denied(
+                    "You can't add some of these users to a room",
+                ));
+            }
+        }
+
+        Room::validate_name(&clientside_room.name).map_err(Status::invalid_argument)?;
+        let mut room = Room::new(clientside_room.name);
+        if direct_pair.is_some() {
+            room.kind = RoomKind::Direct;
+        }
         let members: Vec<RoomUser> = user_uuids
             .iter()
-            .map(|user_uuid| RoomUser {
-                room_uuid: room.uuid,
-                user_uuid: *user_uuid,
+            .map(|user_uuid| {
+                let member_role = if *user_uuid == creator {
+                    RoomRole::Owner
+                } else {
+                    RoomRole::Member
+                };
+                RoomUser::new(room.uuid, *user_uuid, member_role)
             })
             .collect();
 
-        // Store the room and members in the database.
+        // Store the room and members in the database, all at once: a room without its members
+        // would still claim the direct pair, leaving the two users a chat neither of them is in.
         {
             use crate::entities::schema::rooms::dsl::*;
             use crate::entities::schema::rooms_users::dsl::*;
-            use diesel::{insert_into, RunQueryDsl};
+            use diesel::result::{DatabaseErrorKind, Error::DatabaseError};
+            use diesel::{insert_into, Connection, ExpressionMethods, RunQueryDsl};
 
-            let _ = insert_into(rooms)
-                .values(&room)
-                .execute(&mut db_connection)
-                .map_err(|error| {
-                    let message = "Could not save the room in the database";
-                    tracing::error!(message = message, ?error);
-                    Status::internal(message)
-                })?;
+            db_connection
+                .transaction(|db| {
+                    insert_into(rooms)
+                        .values((
+                            &room,
+                            direct_user_low.eq(direct_pair.map(|(low, _)| low)),
+                            direct_user_high.eq(direct_pair.map(|(_, high)| high)),
+                        ))
+                        .execute(db)?;
+                    insert_into(rooms_users).values(&members).execute(db)?;
 
-            let _ = insert_into(rooms_users)
-                .values(&members)
-                .execute(&mut db_connection)
-                .map_err(|error| {
-                    let message = "Could not save the room's members in the database";
-                    tracing::error!(message = message, ?error);
-                    Status::internal(message)
+                    diesel::QueryResult::Ok(())
+                })
+                .map_err(|error| match error {
+                    DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+                        Status::already_exists("The two users already have a private chat")
+                    }
+                    error => {
+                        let message = "Could not save the room in the database";
+                        tracing::error!(message = message, ?error);
+                        Status::internal(message)
+                    }
                 })?;
 
             tracing::info!(message = "Created new room", members = ?user_uuids, uuid = ?room.uuid);
@@ -803,7 +4168,7 @@ impl Chat {
         // Update the membership cache.
         for user_uuid in user_uuids.into_iter() {
             let _: () = cache_connection
-                .rpush(user_uuid, room.uuid)
+                .rpush(Self::rooms_key(&user_uuid), room.uuid)
                 .await
                 .map_err(|error| {
                     let message = "Could not update membership cache";
@@ -813,19 +4178,9 @@ impl Chat {
 
             let event = ServersideUserEvent {
                 user_uuid: Some(user_uuid.into()),
-                event: Some(Event::AddedToRoom(room.uuid.into())),
+                event: Some(UserEvent::AddedToRoom(room.uuid.into())),
             };
-
-            match self.user_event_tx.send(event) {
-                Ok(recv_count) => tracing::trace!(message = "Broadcasting user event", ?recv_count),
-                Err(error) => {
-                    if self.user_event_tx.receiver_count() > 0 {
-                        tracing::error!(message = "Could not broadcast user event", ?error);
-                    } else {
-                        tracing::trace!(message = "No subscribers for user event");
-                    }
-                }
-            }
+            broadcast(&self.user_event_tx, event);
         }
 
         tracing::info!(message = "Updated membership cache", room = ?room.uuid);
@@ -833,3 +4188,19 @@ impl Chat {
         Ok(room.uuid)
     }
 }
+
+/// Send an event to all subscribers of an internal `broadcast` channel.
+///
+/// Having no subscribers at all is perfectly fine (nobody is online), so it's not an error.
+fn broadcast<E: Debug>(channel: &broadcast::Sender<E>, event: E) {
+    match channel.send(event) {
+        Ok(recv_count) => tracing::trace!(message = "Broadcasting event", ?recv_count),
+        Err(error) => {
+            if channel.receiver_count() > 0 {
+                tracing::error!(message = "Could not broadcast event", ?error);
+            } else {
+                tracing::trace!(message = "No subscribers for event");
+            }
+        }
+    }
+}
@@ -1,9 +1,13 @@
-use crate::entities::User;
+use crate::entities::{AuthToken, Session, TokenKey, User};
+use crate::lockout::LoginGuard;
+use crate::password::Verification;
 use crate::persistence::ConnectionPool;
 use crate::proto::{self, AuthPair, UserCredentials};
+use diesel::PgConnection;
 use rand_chacha::ChaCha20Rng;
 use rand_core::{OsRng, RngCore, SeedableRng};
 use std::sync::Arc;
+use std::time::SystemTime;
 use tokio::sync::Mutex;
 use tonic::{Request, Response, Status};
 use tracing::instrument;
@@ -11,17 +15,90 @@ use tracing::instrument;
 #[derive(Debug)]
 pub struct Registry {
     persistence_pool: ConnectionPool,
+    token_key: TokenKey,
+    login_guard: LoginGuard,
     rng: Arc<Mutex<ChaCha20Rng>>,
 }
 
 impl Registry {
-    pub fn with_persistence_pool(persistence_pool: ConnectionPool) -> Self {
+    pub fn new(
+        persistence_pool: ConnectionPool,
+        token_key: TokenKey,
+        login_guard: LoginGuard,
+    ) -> Self {
         let rng = ChaCha20Rng::seed_from_u64(OsRng.next_u64());
         Self {
             persistence_pool,
+            token_key,
+            login_guard,
             rng: Arc::new(Mutex::new(rng)),
         }
     }
+
+    /// Hash a password for storage, off the async runtime (it's deliberately slow).
+    pub(crate) async fn hash_password(plain_password: String) -> Result<String, Status> {
+        tokio::task::spawn_blocking(move || crate::password::hash(&plain_password))
+            .await
+            .map_err(|err| Status::internal(err.to_string()))?
+            .map_err(|err| Status::invalid_argument(err.to_string()))
+    }
+
+    /// Store a new session, clearing out the user's expired ones while at it.
+    async fn start_session(connection: &mut PgConnection, session: &Session) -> Result<(), Status> {
+        use crate::entities::schema::sessions::dsl::*;
+        use diesel::prelude::*;
+
+        diesel::delete(
+            sessions
+                .filter(user_uuid.eq(session.user_uuid))
+                .filter(expires_at.le(SystemTime::now())),
+        )
+        .execute(connection)
+        .map_err(|err| Status::internal(err.to_string()))?;
+        diesel::insert_into(sessions)
+            .values(session)
+            .execute(connection)
+            .map_err(|err| Status::internal(err.to_string()))?;
+
+        Ok(())
+    }
+
+    /// Replace an outdated password hash with a fresh one in the background.
+    ///
+    /// The user has already proven they know the password, so a failure here
+    /// is only logged and the old hash keeps working until the next login.
+    fn upgrade_password_hash(&self, user: &User, plain_password: String) {
+        let persistence_pool = self.persistence_pool.clone();
+        let user_uuid = user.uuid;
+
+        tokio::spawn(async move {
+            use crate::entities::schema::users::dsl::*;
+            use diesel::prelude::*;
+
+            let new_hash = match Self::hash_password(plain_password).await {
+                Ok(new_hash) => new_hash,
+                Err(error) => {
+                    tracing::error!(message = "Could not rehash a password", ?error);
+                    return;
+                }
+            };
+            let upgraded = persistence_pool
+                .get()
+                .map_err(|err| err.to_string())
+                .and_then(|mut connection| {
+                    diesel::update(users.find(user_uuid))
+                        .set(password.eq(new_hash))
+                        .execute(&mut connection)
+                        .map_err(|err| err.to_string())
+                });
+            match upgraded {
+                Ok(_) => tracing::info!(message = "Upgraded a password hash", user = ?user_uuid),
+                Err(error) => {
+                    tracing::error!(message = "Could not store a rehashed password", user = ?user_uuid, ?error)
+                }
+            }
+        });
+    }
 }
 
 #[tonic::async_trait]
@@ -39,8 +116,10 @@ impl proto::registry_server::Registry for Registry {
         // Import some traits and methods to interact with the ORM.
         use crate::entities::schema::users::dsl::*;
         use diesel::prelude::*;
+        use diesel::result::{DatabaseErrorKind, Error::DatabaseError};
 
         let mut credentials = request.into_inner();
+        User::validate_username(&credentials.username).map_err(Status::invalid_argument)?;
         let duplicate_user = users
             .filter(username.eq(&credentials.username))
             .select(User::as_select())
@@ -51,15 +130,22 @@ impl proto::registry_server::Registry for Registry {
         match duplicate_user {
             // No duplicate usernames found, registering a new account.
             None => {
-                // Hash the password using Blake3 hash function.
-                credentials.password = blake3::hash(credentials.password.as_bytes()).to_string();
+                credentials.password = Self::hash_password(credentials.password).await?;
 
-                let mut rng = self.rng.lock().await;
-                let user = User::new(credentials.username.clone(), credentials.password, &mut rng);
+                // Usernames are unique in the database too, in case someone else has registered
+                // the same username since it was checked.
+                let user = User::new(credentials.username.clone(), credentials.password);
                 let _ = diesel::insert_into(users)
                     .values(&user)
                     .execute(&mut connection)
-                    .map_err(|err| Status::internal(err.to_string()))?;
+                    .map_err(|err| match err {
+                        DatabaseError(DatabaseErrorKind::UniqueViolation, _) => {
+                            let msg = "Such user already exists";
+                            tracing::warn!(message = msg, username = ?credentials.username);
+                            Status::already_exists(msg)
+                        }
+                        err => Status::internal(err.to_string()),
+                    })?;
                 tracing::info!(message = "Registered new user", username = ?credentials.username);
                 Ok(Response::new(()))
             }
@@ -73,11 +159,16 @@ impl proto::registry_server::Registry for Registry {
         }
     }
 
-    #[instrument(skip_all, fields(username, uuid))]
+    #[instrument(skip_all, fields(username, uuid, peer))]
     async fn login_as_user(
         &self,
         request: Request<UserCredentials>,
     ) -> Result<Response<AuthPair>, Status> {
+        let peer = request.remote_addr().map(|address| address.ip());
+        if let Some(peer) = peer {
+            tracing::Span::current().record("peer", peer.to_string());
+        }
+
         let mut connection = self
             .persistence_pool
             .get()
@@ -85,33 +176,61 @@ impl proto::registry_server::Registry for Registry {
 
         // Import some traits and methods to interact with the ORM.
         use crate::entities::schema::users::dsl::*;
-        use diesel::query_dsl::methods::{FilterDsl, SelectDsl};
-        use diesel::{ExpressionMethods, OptionalExtension, RunQueryDsl, SelectableHelper};
+        use diesel::prelude::*;
 
-        let mut credentials = request.into_inner();
+        let credentials = request.into_inner();
         tracing::Span::current().record("username", &credentials.username);
-
-        // Hash the password using Blake3 hash function.
-        credentials.password = blake3::hash(credentials.password.as_bytes()).to_string();
+        self.login_guard.check(&credentials.username, peer).await?;
 
         let candidate_user = users
             .filter(username.eq(&credentials.username))
-            .filter(password.eq(&credentials.password))
+            .filter(uuid.ne(User::DELETED_UUID))
             .select(User::as_select())
             .first(&mut connection)
             .optional()
             .map_err(|err| Status::internal(err.to_string()))?;
 
+        // Password hashing is deliberately slow, so keep it off the async runtime.
+        let typed_password = credentials.password.clone();
+        let candidate_user = tokio::task::spawn_blocking(move || match candidate_user {
+            Some(user) => match crate::password::verify(&typed_password, &user.password) {
+                Verification::Valid { needs_rehash } => Some((user, needs_rehash)),
+                Verification::Invalid => None,
+            },
+            None => {
+                crate::password::verify_nothing(&typed_password);
+                None
+            }
+        })
+        .await
+        .map_err(|err| Status::internal(err.to_string()))?
+        .map(|(user, needs_rehash)| {
+            if needs_rehash {
+                self.upgrade_password_hash(&user, credentials.password);
+            }
+            user
+        });
+
         match candidate_user {
-            // A an account with matching credentials exist, returns its UUID and token.
+            // An account with matching credentials exists, start a new session for it.
             Some(user) => {
                 tracing::Span::current().record("uuid", user.uuid.to_string());
-                tracing::info!("Login succeeded");
-                Ok(Response::new(user.auth_pair()))
+
+                let token = AuthToken::new(&mut *self.rng.lock().await);
+                let session =
+                    Session::new(user.uuid, &credentials.device, self.token_key.hash(&token));
+                Self::start_session(&mut connection, &session).await?;
+                self.login_guard
+                    .record_success(&credentials.username, peer)
+                    .await;
+
+                tracing::info!(message = "Login succeeded", device = ?session.device);
+                Ok(Response::new(session.auth_pair(token)))
             }
 
             // No matching username+password pair was found, reject.
             None => {
+                // The failure was already counted by the login guard's check.
                 tracing::Span::current().record("uuid", "<not found>");
                 let message = "Login failed: Invalid username or password";
                 tracing::warn!(message);